@@ -3,6 +3,28 @@ use eightfish_sdk::EightFishModel;
 use serde::{Deserialize, Serialize};
 use spin_sdk::pg::{DbValue, Decode, ParameterValue};
 
+#[derive(Debug, Clone, Serialize, Deserialize, Default, EightFishModel)]
+pub struct GutpApp {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    pub owner_keys: String,
+    pub max_content_len: i64,
+    pub allow_anonymous_read: bool,
+    pub features: String,
+    pub created_time: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default, EightFishModel)]
+pub struct GutpCategory {
+    pub id: String,
+    pub app_id: String,
+    pub name: String,
+    pub description: String,
+    pub weight: i16,
+    pub created_time: i64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default, EightFishModel)]
 pub struct GutpUser {
     pub id: String,
@@ -10,11 +32,21 @@ pub struct GutpUser {
     pub oauth_source: String,
     pub nickname: String,
     pub avatar: String,
+    pub public_key: String,
     pub role: i16,
     pub status: i16,
+    pub app_id: String,
     pub created_time: i64,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, Default, EightFishModel)]
+pub struct GutpUserProfile {
+    pub id: String,
+    pub user_id: String,
+    pub blob: String,
+    pub updated_time: i64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default, EightFishModel)]
 pub struct GutpSubspace {
     pub id: String,
@@ -23,6 +55,8 @@ pub struct GutpSubspace {
     pub description: String,
     pub banner: String,
     pub is_public: bool,
+    pub key_version: i64,
+    pub rekey_required: bool,
     pub status: i16,
     pub weight: i16,
     pub owner_id: String,
@@ -44,6 +78,7 @@ pub struct GutpPost {
     pub is_public: bool,
     pub status: i16,
     pub weight: i16,
+    pub pinned_time: i64,
     pub category: String,
     pub app_id: String,
     pub created_time: i64,
@@ -61,6 +96,7 @@ pub struct GutpComment {
     pub is_public: bool,
     pub status: i16,
     pub weight: i32,
+    pub app_id: String,
     pub created_time: i64,
 }
 
@@ -68,9 +104,14 @@ pub struct GutpComment {
 pub struct GutpTag {
     pub id: String,
     pub caption: String,
+    pub caption_key: String,
     pub subspace_id: String,
     pub is_public: bool,
     pub weight: i16,
+    pub parent_tag_id: String,
+    pub alias_of: String,
+    pub post_count: i64,
+    pub app_id: String,
     pub created_time: i64,
 }
 
@@ -99,6 +140,128 @@ pub struct GutpModerator {
     pub is_subspace_moderator: bool,
     pub tag_id: String,
     pub permission_level: i16,
+    pub app_id: String,
+    pub created_time: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default, EightFishModel)]
+pub struct GutpReactionKind {
+    pub id: String,
+    pub app_id: String,
+    pub name: String,
+    pub score: i16,
+    pub created_time: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default, EightFishModel)]
+pub struct GutpReaction {
+    pub id: String,
+    pub user_id: String,
+    pub target_model: String,
+    pub target_id: String,
+    pub kind: String,
+    pub score: i16,
+    pub created_time: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default, EightFishModel)]
+pub struct GutpBookmark {
+    pub id: String,
+    pub user_id: String,
+    pub post_id: String,
+    pub folder: String,
+    pub created_time: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default, EightFishModel)]
+pub struct GutpFollow {
+    pub id: String,
+    pub follower_id: String,
+    pub target_model: String,
+    pub target_id: String,
+    pub created_time: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default, EightFishModel)]
+pub struct GutpMember {
+    pub id: String,
+    pub subspace_id: String,
+    pub user_id: String,
+    pub role: i16,
+    pub status: i16,
+    pub inviter_id: String,
+    pub created_time: i64,
+    pub updated_time: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default, EightFishModel)]
+pub struct GutpSubspaceKey {
+    pub id: String,
+    pub subspace_id: String,
+    pub user_id: String,
+    pub key_version: i64,
+    pub wrapped_key: String,
+    pub wrapper_id: String,
+    pub created_time: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default, EightFishModel)]
+pub struct GutpConversation {
+    pub id: String,
+    pub app_id: String,
+    pub user_a_id: String,
+    pub user_b_id: String,
+    pub a_read_time: i64,
+    pub b_read_time: i64,
+    pub last_message_time: i64,
+    pub created_time: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default, EightFishModel)]
+pub struct GutpMessage {
+    pub id: String,
+    pub conversation_id: String,
+    pub sender_id: String,
+    pub content: String,
+    pub created_time: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default, EightFishModel)]
+pub struct GutpModLog {
+    pub id: String,
+    pub app_id: String,
+    pub actor_id: String,
+    pub subspace_id: String,
+    pub target_model: String,
+    pub target_id: String,
+    pub action: String,
+    pub before_value: String,
+    pub after_value: String,
+    pub reason: String,
+    pub created_time: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default, EightFishModel)]
+pub struct GutpReportCase {
+    pub id: String,
+    pub app_id: String,
+    pub subspace_id: String,
+    pub target_model: String,
+    pub target_id: String,
+    pub report_count: i64,
+    pub status: i16,
+    pub resolver_id: String,
+    pub resolution: String,
+    pub created_time: i64,
+    pub updated_time: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default, EightFishModel)]
+pub struct GutpReport {
+    pub id: String,
+    pub case_id: String,
+    pub reporter_id: String,
+    pub reason: String,
     pub created_time: i64,
 }
 
@@ -0,0 +1,241 @@
+use anyhow::{anyhow, bail};
+use eightfish_sdk::{HandlerCRUD, Info, Module, Request, Response, Result, Router, Status};
+use spin_sdk::pg;
+
+use crate::constants::{APP_FEATURES, APP_ID_KEY, APP_KEY, DB_URL_ENV, OPERATOR_KEYS_ENV};
+use crate::utils;
+use gutp_types::GutpApp;
+
+pub struct GutpAppModule;
+
+impl GutpAppModule {
+    pub(crate) fn find_app(pg_conn: &pg::Connection, id: &str) -> Result<Option<GutpApp>> {
+        let (sql, sql_params) = GutpApp::build_get_by_id(id);
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+        Ok(rowset.rows.into_iter().next().map(GutpApp::from_row))
+    }
+
+    /// The registered app of the request, as loaded by the global filter, so
+    /// its config can be consulted without another query.
+    pub(crate) fn get_request_app(req: &Request) -> Result<GutpApp> {
+        let app = req
+            .ext()
+            .get(APP_KEY)
+            .ok_or(anyhow!("the app is not registered"))?;
+        Ok(serde_json::from_str(app)?)
+    }
+
+    pub(crate) fn is_owner(app: &GutpApp, caller_id: &str) -> bool {
+        app.owner_keys.split(',').any(|key| key == caller_id)
+    }
+
+    /// Only the operators of the instance can register apps, so an app id
+    /// can not be claimed by whoever comes first.
+    fn check_operator(caller_id: &str) -> Result<()> {
+        let operator_keys = std::env::var(OPERATOR_KEYS_ENV)
+            .map_err(|_| anyhow!("app registration is not enabled on this instance"))?;
+        if !operator_keys.split(',').any(|key| key.trim() == caller_id) {
+            bail!("only the operators of this instance can register apps");
+        }
+        Ok(())
+    }
+
+    /// Reject the request if its app has switched `feature` off.
+    pub(crate) fn check_feature(req: &Request, feature: &str) -> Result<()> {
+        let app = Self::get_request_app(req)?;
+        if !app.features.split(',').any(|f| f == feature) {
+            bail!("{feature} is not enabled in this app");
+        }
+        Ok(())
+    }
+
+    /// Content is measured as stored, for non-public content that is the
+    /// encryption envelope.
+    pub(crate) fn check_content_len(app: &GutpApp, content: &str) -> Result<()> {
+        if app.max_content_len > 0 && content.len() as i64 > app.max_content_len {
+            bail!("content must be at most {} bytes", app.max_content_len);
+        }
+        Ok(())
+    }
+
+    fn check_features(features: Vec<String>) -> Result<String> {
+        if let Some(feature) = features
+            .iter()
+            .find(|f| !APP_FEATURES.contains(&f.as_str()))
+        {
+            bail!("unknown feature: {feature}");
+        }
+        Ok(features.join(","))
+    }
+
+    /// The app of the request, with its config.
+    fn get_one(req: &mut Request) -> Result<Response> {
+        let results: Vec<GutpApp> = vec![Self::get_request_app(req)?];
+
+        let info = Info {
+            model_name: GutpApp::model_name(),
+            action: HandlerCRUD::GetOne,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
+    /// Register the app of the request, by an operator of the instance. The
+    /// owners are `owner_keys`, by default the operator. All features are on,
+    /// and there are no limits, until an owner changes the config.
+    fn register(req: &mut Request) -> Result<Response> {
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let name = params
+            .get("name")
+            .ok_or(anyhow!("name is required"))?
+            .trim()
+            .to_string();
+        if name.is_empty() {
+            bail!("name can not be empty");
+        }
+        let description = params
+            .get("description")
+            .unwrap_or(&"".to_string())
+            .to_owned();
+        let owner_keys = utils::get_list_param(&params, "owner_keys");
+
+        let id = req
+            .ext()
+            .get(APP_ID_KEY)
+            .ok_or(anyhow!("app context is required"))?
+            .to_owned();
+        let caller_id = utils::get_caller_id(req)?;
+        let time = req
+            .ext()
+            .get("time")
+            .ok_or(anyhow!("generate time failed"))?
+            .parse::<i64>()?;
+
+        Self::check_operator(&caller_id)?;
+        let owner_keys = match owner_keys {
+            Some(owner_keys) if owner_keys.is_empty() => {
+                bail!("an app must have at least one owner")
+            }
+            Some(owner_keys) => owner_keys.join(","),
+            None => caller_id,
+        };
+        if Self::find_app(&pg_conn, &id)?.is_some() {
+            bail!("app {id} is already registered");
+        }
+
+        let app = GutpApp {
+            id,
+            name,
+            description,
+            owner_keys,
+            max_content_len: 0,
+            allow_anonymous_read: true,
+            features: APP_FEATURES.join(","),
+            created_time: time,
+        };
+
+        let (sql, sql_params) = app.build_insert();
+        _ = pg_conn.execute(&sql, &sql_params)?;
+
+        let results: Vec<GutpApp> = vec![app];
+
+        let info = Info {
+            model_name: GutpApp::model_name(),
+            action: HandlerCRUD::Create,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
+    /// Change the config of the app, by one of its owners. Params left out
+    /// keep their current value.
+    fn update(req: &mut Request) -> Result<Response> {
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_caller_id(req)?;
+
+        let old_app =
+            Self::find_app(&pg_conn, &app_id)?.ok_or(anyhow!("the app is not registered"))?;
+        if !Self::is_owner(&old_app, &caller_id) {
+            bail!("only the owners of the app can change it");
+        }
+
+        let name = match params.get("name").map(|name| name.trim()) {
+            Some("") => bail!("name can not be empty"),
+            Some(name) => name.to_string(),
+            None => old_app.name.clone(),
+        };
+        let description = params
+            .get("description")
+            .unwrap_or(&old_app.description)
+            .to_owned();
+        let owner_keys = match utils::get_list_param(&params, "owner_keys") {
+            Some(owner_keys) if owner_keys.is_empty() => {
+                bail!("an app must keep at least one owner")
+            }
+            Some(owner_keys) => owner_keys.join(","),
+            None => old_app.owner_keys.clone(),
+        };
+        let max_content_len = match params.get("max_content_len") {
+            Some(max_content_len) => {
+                let max_content_len = max_content_len.parse::<i64>()?;
+                if max_content_len < 0 {
+                    bail!("max_content_len can not be negative");
+                }
+                max_content_len
+            }
+            None => old_app.max_content_len,
+        };
+        let allow_anonymous_read = match params.get("allow_anonymous_read") {
+            Some(allow_anonymous_read) => allow_anonymous_read.parse::<bool>()?,
+            None => old_app.allow_anonymous_read,
+        };
+        let features = match utils::get_list_param(&params, "features") {
+            Some(features) => Self::check_features(features)?,
+            None => old_app.features.clone(),
+        };
+
+        let app = GutpApp {
+            name,
+            description,
+            owner_keys,
+            max_content_len,
+            allow_anonymous_read,
+            features,
+            ..old_app
+        };
+
+        let (sql, sql_params) = app.build_update();
+        _ = pg_conn.execute(&sql, &sql_params)?;
+
+        let results: Vec<GutpApp> = vec![app];
+
+        let info = Info {
+            model_name: GutpApp::model_name(),
+            action: HandlerCRUD::Update,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+}
+
+impl Module for GutpAppModule {
+    fn router(&self, router: &mut Router) -> Result<()> {
+        router.get("/gutp/v1/app", Self::get_one);
+        router.post("/gutp/v1/app/register", Self::register);
+        router.post("/gutp/v1/app/update", Self::update);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,221 @@
+use anyhow::anyhow;
+use eightfish_sdk::{HandlerCRUD, Info, Module, Request, Response, Result, Router, Status};
+use spin_sdk::pg::{self, Decode, ParameterValue};
+use sql_builder::SqlBuilder;
+
+use crate::app::GutpAppModule;
+use crate::constants::DB_URL_ENV;
+use crate::post::GutpPostModule;
+use crate::utils;
+use gutp_types::GutpBookmark;
+
+pub struct GutpBookmarkModule;
+
+impl GutpBookmarkModule {
+    /// The ids of the posts in `post_ids` which the user has bookmarked.
+    pub(crate) fn get_bookmarked(
+        pg_conn: &pg::Connection,
+        user_id: &str,
+        post_ids: &[String],
+    ) -> Result<Vec<String>> {
+        let mut bookmarked: Vec<String> = vec![];
+        if post_ids.is_empty() {
+            return Ok(bookmarked);
+        }
+
+        let placeholders = utils::build_placeholders(2, post_ids.len());
+        let mut sql_params = vec![ParameterValue::Str(user_id.to_owned())];
+        for post_id in post_ids {
+            sql_params.push(ParameterValue::Str(post_id.clone()));
+        }
+        let sql = format!(
+            "SELECT post_id FROM gutpbookmark WHERE user_id = $1 AND post_id IN ({placeholders})"
+        );
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+        for row in rowset.rows {
+            bookmarked.push(String::decode(&row[0])?);
+        }
+
+        Ok(bookmarked)
+    }
+
+    /// Remove all the bookmarks of a post, when the post is deleted.
+    pub(crate) fn delete_by_post(pg_conn: &pg::Connection, post_id: &str) -> Result<()> {
+        let sql_param = ParameterValue::Str(post_id.to_owned());
+        _ = pg_conn.execute("DELETE FROM gutpbookmark WHERE post_id = $1", &[sql_param])?;
+        Ok(())
+    }
+
+    fn find_bookmark(
+        pg_conn: &pg::Connection,
+        user_id: &str,
+        post_id: &str,
+    ) -> Result<Option<GutpBookmark>> {
+        let sql = SqlBuilder::select_from(&GutpBookmark::model_name())
+            .fields(&GutpBookmark::fields())
+            .and_where_eq("user_id", "$1")
+            .and_where_eq("post_id", "$2")
+            .sql()?;
+        let sql_params = [
+            ParameterValue::Str(user_id.to_owned()),
+            ParameterValue::Str(post_id.to_owned()),
+        ];
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+
+        Ok(rowset.rows.into_iter().next().map(GutpBookmark::from_row))
+    }
+
+    /// The caller's bookmarks, the latest first, optionally in one folder.
+    fn get_list(req: &mut Request) -> Result<Response> {
+        GutpAppModule::check_feature(req, "bookmarks")?;
+
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let user_id = utils::get_caller_id(req)?;
+        let (limit, offset) = utils::build_page_info(&params)?;
+
+        let mut builder = SqlBuilder::select_from(&GutpBookmark::model_name());
+        builder
+            .fields(&GutpBookmark::fields())
+            .and_where_eq("user_id", "$1");
+        let mut sql_params = vec![ParameterValue::Str(user_id)];
+        if let Some(folder) = params.get("folder") {
+            sql_params.push(ParameterValue::Str(folder.clone()));
+            builder.and_where_eq("folder", "$2");
+        }
+        let sql = builder
+            .order_desc("created_time")
+            .limit(limit)
+            .offset(offset)
+            .sql()?;
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+
+        let mut results: Vec<GutpBookmark> = vec![];
+        for row in rowset.rows {
+            let sp = GutpBookmark::from_row(row);
+            results.push(sp);
+        }
+
+        let info = Info {
+            model_name: GutpBookmark::model_name(),
+            action: HandlerCRUD::List,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
+    /// Bookmark a post for the caller. Bookmarking it again moves it to the
+    /// given folder.
+    fn new_one(req: &mut Request) -> Result<Response> {
+        GutpAppModule::check_feature(req, "bookmarks")?;
+
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let post_id = params
+            .get("post_id")
+            .ok_or(anyhow!("post_id is required"))?
+            .to_owned();
+        let folder = params
+            .get("folder")
+            .unwrap_or(&"".to_string())
+            .trim()
+            .to_string();
+
+        let app_id = utils::get_app_id(req)?;
+        let user_id = utils::get_caller_id(req)?;
+        let id = req
+            .ext()
+            .get("random_str")
+            .ok_or(anyhow!("generate id failed"))?
+            .to_owned();
+        let time = req
+            .ext()
+            .get("time")
+            .ok_or(anyhow!("generate time failed"))?
+            .parse::<i64>()?;
+
+        let bookmark = utils::with_transaction(&pg_conn, |conn| {
+            if let Some(old_bookmark) = Self::find_bookmark(conn, &user_id, &post_id)? {
+                let bookmark = GutpBookmark {
+                    folder,
+                    ..old_bookmark
+                };
+                let (sql, sql_params) = bookmark.build_update();
+                _ = conn.execute(&sql, &sql_params)?;
+                return Ok(bookmark);
+            }
+
+            // make sure the post exists
+            _ = GutpPostModule::get_post_by_id(conn, &app_id, &post_id)?;
+
+            let bookmark = GutpBookmark {
+                id,
+                user_id,
+                post_id,
+                folder,
+                created_time: time,
+            };
+            let (sql, sql_params) = bookmark.build_insert();
+            _ = conn.execute(&sql, &sql_params)?;
+
+            Ok(bookmark)
+        })?;
+
+        let results: Vec<GutpBookmark> = vec![bookmark];
+
+        let info = Info {
+            model_name: GutpBookmark::model_name(),
+            action: HandlerCRUD::Create,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
+    /// Remove the caller's bookmark of a post.
+    fn delete(req: &mut Request) -> Result<Response> {
+        GutpAppModule::check_feature(req, "bookmarks")?;
+
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let post_id = params
+            .get("post_id")
+            .ok_or(anyhow!("post_id is required"))?;
+
+        let user_id = utils::get_caller_id(req)?;
+
+        if let Some(bookmark) = Self::find_bookmark(&pg_conn, &user_id, post_id)? {
+            let (sql, sql_params) = GutpBookmark::build_delete(&bookmark.id);
+            _ = pg_conn.execute(&sql, &sql_params)?;
+        }
+
+        let info = Info {
+            model_name: GutpBookmark::model_name(),
+            action: HandlerCRUD::Delete,
+            extra: "".to_string(),
+        };
+        let results: Vec<GutpBookmark> = vec![];
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+}
+
+impl Module for GutpBookmarkModule {
+    fn router(&self, router: &mut Router) -> Result<()> {
+        router.get("/gutp/v1/bookmark/list", Self::get_list);
+        router.post("/gutp/v1/bookmark/create", Self::new_one);
+        router.post("/gutp/v1/bookmark/delete", Self::delete);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,358 @@
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, bail};
+use eightfish_sdk::{HandlerCRUD, Info, Module, Request, Response, Result, Router, Status};
+use spin_sdk::pg::{self, Decode, ParameterValue};
+use sql_builder::SqlBuilder;
+
+use crate::app::GutpAppModule;
+use crate::constants::DB_URL_ENV;
+use crate::utils;
+use gutp_types::GutpCategory;
+
+pub struct GutpCategoryModule;
+
+impl GutpCategoryModule {
+    /// Load a category of an app. Categories of other apps are not found.
+    fn get_category_by_id(
+        pg_conn: &pg::Connection,
+        app_id: &str,
+        id: &str,
+    ) -> Result<GutpCategory> {
+        let (sql, sql_params) = GutpCategory::build_get_by_id(id);
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+        match rowset.rows.into_iter().next().map(GutpCategory::from_row) {
+            Some(category) if category.app_id == app_id => Ok(category),
+            _ => bail!("no such category: {id}"),
+        }
+    }
+
+    fn find_by_name(
+        pg_conn: &pg::Connection,
+        app_id: &str,
+        name: &str,
+    ) -> Result<Option<GutpCategory>> {
+        let sql = SqlBuilder::select_from(&GutpCategory::model_name())
+            .fields(&GutpCategory::fields())
+            .and_where_eq("app_id", "$1")
+            .and_where_eq("name", "$2")
+            .sql()?;
+        let sql_params = [
+            ParameterValue::Str(app_id.to_owned()),
+            ParameterValue::Str(name.to_owned()),
+        ];
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+
+        Ok(rowset.rows.into_iter().next().map(GutpCategory::from_row))
+    }
+
+    /// Check the `category` of a subspace or post. Once an app manages its
+    /// categories, only those are accepted; before that any value is.
+    pub(crate) fn check_category(
+        pg_conn: &pg::Connection,
+        app_id: &str,
+        category: &str,
+    ) -> Result<()> {
+        if Self::find_by_name(pg_conn, app_id, category)?.is_some() {
+            return Ok(());
+        }
+        let rowset = pg_conn.query(
+            "SELECT id FROM gutpcategory WHERE app_id = $1 LIMIT 1",
+            &[ParameterValue::Str(app_id.to_owned())],
+        )?;
+        if !rowset.rows.is_empty() {
+            bail!("no such category in this app: {category}");
+        }
+        Ok(())
+    }
+
+    fn check_name(name: &str) -> Result<String> {
+        let name = name.trim();
+        if name.is_empty() {
+            bail!("name can not be empty");
+        }
+        Ok(name.to_string())
+    }
+
+    fn check_owner(req: &Request) -> Result<()> {
+        let app = GutpAppModule::get_request_app(req)?;
+        let caller_id = utils::get_caller_id(req)?;
+        if !GutpAppModule::is_owner(&app, &caller_id) {
+            bail!("only the owners of the app can manage its categories");
+        }
+        Ok(())
+    }
+
+    /// Number of subspaces in each category, as a json object keyed by
+    /// category name.
+    fn build_subspace_counts(
+        pg_conn: &pg::Connection,
+        app_id: &str,
+        categories: &[GutpCategory],
+    ) -> Result<String> {
+        let mut counts: BTreeMap<String, i64> = BTreeMap::new();
+        if categories.is_empty() {
+            return Ok(serde_json::to_string(&counts)?);
+        }
+
+        let placeholders = utils::build_placeholders(2, categories.len());
+        let mut sql_params = vec![ParameterValue::Str(app_id.to_owned())];
+        for category in categories {
+            sql_params.push(ParameterValue::Str(category.name.clone()));
+            counts.insert(category.name.clone(), 0);
+        }
+        let sql = format!(
+            "SELECT category, COUNT(*) FROM gutpsubspace WHERE app_id = $1 AND category IN ({placeholders}) GROUP BY category"
+        );
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+        for row in rowset.rows {
+            let name = String::decode(&row[0])?;
+            let count = i64::decode(&row[1])?;
+            counts.insert(name, count);
+        }
+
+        Ok(serde_json::to_string(&counts)?)
+    }
+
+    fn get_one(req: &mut Request) -> Result<Response> {
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let id = params.get("id").ok_or(anyhow!("id is required"))?;
+        let app_id = utils::get_app_id(req)?;
+
+        let results: Vec<GutpCategory> = vec![Self::get_category_by_id(&pg_conn, &app_id, id)?];
+
+        let info = Info {
+            model_name: GutpCategory::model_name(),
+            action: HandlerCRUD::GetOne,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
+    /// The categories of the app, heaviest first, with the number of
+    /// subspaces in each in extra.
+    fn list_categories(req: &mut Request) -> Result<Response> {
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let app_id = utils::get_app_id(req)?;
+        let (limit, offset) = utils::build_page_info(&params)?;
+
+        let sql = SqlBuilder::select_from(&GutpCategory::model_name())
+            .fields(&GutpCategory::fields())
+            .and_where_eq("app_id", "$1")
+            .order_desc("weight")
+            .order_asc("name")
+            .limit(limit)
+            .offset(offset)
+            .sql()?;
+        let sql_param = ParameterValue::Str(app_id.clone());
+        let rowset = pg_conn.query(&sql, &[sql_param])?;
+
+        let mut results: Vec<GutpCategory> = vec![];
+        for row in rowset.rows {
+            let sp = GutpCategory::from_row(row);
+            results.push(sp);
+        }
+
+        let extra = Self::build_subspace_counts(&pg_conn, &app_id, &results)?;
+
+        let info = Info {
+            model_name: GutpCategory::model_name(),
+            action: HandlerCRUD::List,
+            extra,
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
+    /// Add a category to the app, by one of its owners.
+    fn new_one(req: &mut Request) -> Result<Response> {
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let name = Self::check_name(params.get("name").ok_or(anyhow!("name is required"))?)?;
+        let description = params
+            .get("description")
+            .unwrap_or(&"".to_string())
+            .to_owned();
+        let weight = params
+            .get("weight")
+            .unwrap_or(&"0".to_string())
+            .parse::<i16>()?;
+
+        let app_id = utils::get_app_id(req)?;
+        Self::check_owner(req)?;
+        let id = req
+            .ext()
+            .get("random_str")
+            .ok_or(anyhow!("generate id failed"))?
+            .to_owned();
+        let time = req
+            .ext()
+            .get("time")
+            .ok_or(anyhow!("generate time failed"))?
+            .parse::<i64>()?;
+
+        if Self::find_by_name(&pg_conn, &app_id, &name)?.is_some() {
+            bail!("category {name} already exists in this app");
+        }
+
+        let category = GutpCategory {
+            id,
+            app_id,
+            name,
+            description,
+            weight,
+            created_time: time,
+        };
+
+        let (sql, sql_params) = category.build_insert();
+        _ = pg_conn.execute(&sql, &sql_params)?;
+
+        let results: Vec<GutpCategory> = vec![category];
+
+        let info = Info {
+            model_name: GutpCategory::model_name(),
+            action: HandlerCRUD::Create,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
+    /// Change a category, by one of the app owners. A renamed category is
+    /// renamed on its subspaces and posts too.
+    fn update(req: &mut Request) -> Result<Response> {
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let id = params.get("id").ok_or(anyhow!("id is required"))?;
+        let name = Self::check_name(params.get("name").ok_or(anyhow!("name is required"))?)?;
+        let description = params
+            .get("description")
+            .ok_or(anyhow!("description is required"))?
+            .to_owned();
+        let weight = params
+            .get("weight")
+            .ok_or(anyhow!("weight is required"))?
+            .parse::<i16>()?;
+
+        let app_id = utils::get_app_id(req)?;
+        Self::check_owner(req)?;
+
+        let category = utils::with_transaction(&pg_conn, |conn| {
+            let old_category = Self::get_category_by_id(conn, &app_id, id)?;
+
+            if name != old_category.name {
+                if Self::find_by_name(conn, &app_id, &name)?.is_some() {
+                    bail!("category {name} already exists in this app");
+                }
+                let sql_params = [
+                    ParameterValue::Str(name.clone()),
+                    ParameterValue::Str(app_id.clone()),
+                    ParameterValue::Str(old_category.name.clone()),
+                ];
+                _ = conn.execute(
+                    "UPDATE gutpsubspace SET category = $1 WHERE app_id = $2 AND category = $3",
+                    &sql_params,
+                )?;
+                _ = conn.execute(
+                    "UPDATE gutppost SET category = $1 WHERE app_id = $2 AND category = $3",
+                    &sql_params,
+                )?;
+            }
+
+            let category = GutpCategory {
+                name,
+                description,
+                weight,
+                ..old_category
+            };
+            let (sql, sql_params) = category.build_update();
+            _ = conn.execute(&sql, &sql_params)?;
+
+            Ok(category)
+        })?;
+
+        let results: Vec<GutpCategory> = vec![category];
+
+        let info = Info {
+            model_name: GutpCategory::model_name(),
+            action: HandlerCRUD::Update,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
+    /// Remove a category no subspace or post uses any more, by one of the app
+    /// owners.
+    fn delete(req: &mut Request) -> Result<Response> {
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let id = params.get("id").ok_or(anyhow!("id is required"))?;
+
+        let app_id = utils::get_app_id(req)?;
+        Self::check_owner(req)?;
+
+        utils::with_transaction(&pg_conn, |conn| {
+            let category = Self::get_category_by_id(conn, &app_id, id)?;
+
+            let sql_params = [
+                ParameterValue::Str(app_id.clone()),
+                ParameterValue::Str(category.name.clone()),
+            ];
+            let rowset = conn.query(
+                "SELECT (SELECT COUNT(*) FROM gutpsubspace WHERE app_id = $1 AND category = $2) + (SELECT COUNT(*) FROM gutppost WHERE app_id = $1 AND category = $2)",
+                &sql_params,
+            )?;
+            let in_use = match rowset.rows.first() {
+                Some(row) => i64::decode(&row[0])?,
+                None => 0,
+            };
+            if in_use > 0 {
+                bail!("category {} is still in use", category.name);
+            }
+
+            let (sql, sql_params) = GutpCategory::build_delete(id);
+            _ = conn.execute(&sql, &sql_params)?;
+            Ok(())
+        })?;
+
+        let info = Info {
+            model_name: GutpCategory::model_name(),
+            action: HandlerCRUD::Delete,
+            extra: "".to_string(),
+        };
+        let results: Vec<GutpCategory> = vec![];
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+}
+
+impl Module for GutpCategoryModule {
+    fn router(&self, router: &mut Router) -> Result<()> {
+        router.get("/gutp/v1/category", Self::get_one);
+        router.get("/gutp/v1/category/list_categories", Self::list_categories);
+        router.post("/gutp/v1/category/create", Self::new_one);
+        router.post("/gutp/v1/category/update", Self::update);
+        router.post("/gutp/v1/category/delete", Self::delete);
+
+        Ok(())
+    }
+}
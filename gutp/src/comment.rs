@@ -1,13 +1,22 @@
+use crate::app::GutpAppModule;
 use crate::constants::DB_URL_ENV;
+use crate::envelope::Envelope;
+use crate::member::GutpMemberModule;
+use crate::moderator::GutpModeratorModule;
+use crate::modlog::GutpModLogModule;
+use crate::post::GutpPostModule;
+use crate::reaction::GutpReactionModule;
+use crate::subspace::GutpSubspaceModule;
+use crate::user::GutpUserModule;
 use crate::utils;
 use anyhow::{anyhow, bail};
 use eightfish_sdk::{HandlerCRUD, Info, Module, Request, Response, Result, Router, Status};
 use spin_sdk::pg::{self, ParameterValue};
 use sql_builder::SqlBuilder;
 
-use gutp_types::GutpComment;
+use gutp_types::{GutpComment, GutpModLog, GutpPost};
 
-enum GutpCommentStatus {
+pub(crate) enum GutpCommentStatus {
     Normal = 0,
     Frozen = 1,
     Forbidden = 2,
@@ -16,11 +25,116 @@ enum GutpCommentStatus {
 
 enum GutpCommentWeight {
     Normal = 0,
+    Low = -1,
+    VeryLow = -2,
+    SuperLow = -3,
+    High = 1,
+    VeryHigh = 2,
+    SuperHigh = 3,
 }
 
 pub struct GutpCommentModule;
 
 impl GutpCommentModule {
+    /// A comment on a non-public post can not be public, and the content of a
+    /// non-public comment must be an encryption envelope.
+    fn check_privacy(
+        pg_conn: &pg::Connection,
+        post: &GutpPost,
+        is_public: bool,
+        content: String,
+    ) -> Result<String> {
+        if is_public {
+            if !post.is_public {
+                bail!("comments on a non-public post must not be public");
+            }
+            return Ok(content);
+        }
+        let subspace =
+            GutpSubspaceModule::get_subspace_by_id(pg_conn, &post.app_id, &post.subspace_id)?;
+        Envelope::check(&content, subspace.key_version)
+    }
+
+    /// Sql condition keeping the comments on the posts the user in
+    /// `user_placeholder` may read.
+    fn build_readable_cond(user_placeholder: &str) -> String {
+        format!(
+            "post_id IN (SELECT id FROM gutppost WHERE {})",
+            GutpMemberModule::build_readable_cond("subspace_id", user_placeholder)
+        )
+    }
+
+    /// Load a comment of an app. Comments of other apps are not found.
+    pub(crate) fn get_comment_by_id(
+        pg_conn: &pg::Connection,
+        app_id: &str,
+        id: &str,
+    ) -> Result<GutpComment> {
+        let (sql, sql_params) = GutpComment::build_get_by_id(id);
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+        match rowset.rows.into_iter().next().map(GutpComment::from_row) {
+            Some(comment) if comment.app_id == app_id => Ok(comment),
+            _ => bail!("no such comment: {id}"),
+        }
+    }
+
+    /// Load a comment of an app and lock its row until the transaction ends,
+    /// so it can not change between a check on it and its update.
+    fn get_comment_for_update(
+        pg_conn: &pg::Connection,
+        app_id: &str,
+        id: &str,
+    ) -> Result<GutpComment> {
+        let sql = format!(
+            "SELECT {} FROM gutpcomment WHERE id = $1 FOR UPDATE",
+            GutpComment::fields().join(", ")
+        );
+        let rowset = pg_conn.query(&sql, &[ParameterValue::Str(id.to_owned())])?;
+        match rowset.rows.into_iter().next().map(GutpComment::from_row) {
+            Some(comment) if comment.app_id == app_id => Ok(comment),
+            _ => bail!("no such comment: {id}"),
+        }
+    }
+
+    /// Reaction counts and the caller's own reactions on the comments,
+    /// returned in extra along with them.
+    fn build_extra(
+        pg_conn: &pg::Connection,
+        comments: &[GutpComment],
+        caller_id: Option<&str>,
+    ) -> Result<String> {
+        let comment_ids: Vec<String> = comments.iter().map(|comment| comment.id.clone()).collect();
+        GutpReactionModule::build_summary(
+            pg_conn,
+            &GutpComment::model_name(),
+            &comment_ids,
+            caller_id,
+        )
+    }
+
+    /// Change the status of a comment from moderation, returning the status
+    /// it had before.
+    pub(crate) fn set_status(
+        pg_conn: &pg::Connection,
+        app_id: &str,
+        id: &str,
+        status: i16,
+    ) -> Result<i16> {
+        if !(GutpCommentStatus::Normal as i16..=GutpCommentStatus::Deleted as i16).contains(&status)
+        {
+            bail!("status is out of range");
+        }
+        let old_comment = Self::get_comment_by_id(pg_conn, app_id, id)?;
+        let old_status = old_comment.status;
+        let comment = GutpComment {
+            status,
+            ..old_comment
+        };
+        let (sql, sql_params) = comment.build_update();
+        _ = pg_conn.execute(&sql, &sql_params)?;
+        Ok(old_status)
+    }
+
     fn get_one(req: &mut Request) -> Result<Response> {
         let pg_addr = std::env::var(DB_URL_ENV)?;
         let pg_conn = pg::Connection::open(&pg_addr)?;
@@ -28,19 +142,30 @@ impl GutpCommentModule {
         let params = req.parse_urlencoded()?;
         let comment_id = params.get("id").ok_or(anyhow!("id required."))?;
         //let comment_id = utils::get_required_param(&params, "id")?;
+        let app_id = utils::get_app_id(req)?;
         let (sql_statement, sql_params) = GutpComment::build_get_by_id(comment_id);
         let rowset = pg_conn.query(&sql_statement, &sql_params)?;
 
-        let results = if let Some(row) = rowset.rows.into_iter().next() {
-            vec![GutpComment::from_row(row)]
-        } else {
-            bail!("no this item".to_string());
+        let results = match rowset.rows.into_iter().next().map(GutpComment::from_row) {
+            Some(comment) if comment.app_id == app_id => vec![comment],
+            _ => bail!("no this item".to_string()),
         };
 
+        let caller_id = utils::get_optional_caller_id(req);
+        let post = GutpPostModule::get_post_by_id(&pg_conn, &app_id, &results[0].post_id)?;
+        GutpMemberModule::check_readable(
+            &pg_conn,
+            &app_id,
+            &post.subspace_id,
+            caller_id.as_deref(),
+        )?;
+
+        let extra = Self::build_extra(&pg_conn, &results, caller_id.as_deref())?;
+
         let info = Info {
             model_name: GutpComment::model_name(),
             action: HandlerCRUD::GetOne,
-            extra: "".to_string(),
+            extra,
         };
 
         Ok(Response::new(Status::Successful, info, results))
@@ -53,14 +178,22 @@ impl GutpCommentModule {
         let params = req.parse_urlencoded()?;
 
         let (limit, offset) = utils::build_page_info(&params)?;
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_optional_caller_id(req);
 
         let sql = SqlBuilder::select_from(&GutpComment::model_name())
             .fields(&GutpComment::fields())
+            .and_where_eq("app_id", "$2")
+            .and_where(Self::build_readable_cond("$1"))
             .order_desc("created_time")
             .limit(limit)
             .offset(offset)
             .sql()?;
-        let rowset = pg_conn.query(&sql, &[])?;
+        let sql_params = [
+            ParameterValue::Str(caller_id.clone().unwrap_or_default()),
+            ParameterValue::Str(app_id),
+        ];
+        let rowset = pg_conn.query(&sql, &sql_params)?;
 
         let mut results: Vec<GutpComment> = vec![];
         for row in rowset.rows {
@@ -68,10 +201,12 @@ impl GutpCommentModule {
             results.push(sp);
         }
 
+        let extra = Self::build_extra(&pg_conn, &results, caller_id.as_deref())?;
+
         let info = Info {
             model_name: GutpComment::model_name(),
             action: HandlerCRUD::List,
-            extra: "".to_string(),
+            extra,
         };
 
         Ok(Response::new(Status::Successful, info, results))
@@ -88,6 +223,16 @@ impl GutpCommentModule {
             .ok_or(anyhow!("post_id is required."))?;
         let (limit, offset) = utils::build_page_info(&params)?;
 
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_optional_caller_id(req);
+        let post = GutpPostModule::get_post_by_id(&pg_conn, &app_id, post_id)?;
+        GutpMemberModule::check_readable(
+            &pg_conn,
+            &app_id,
+            &post.subspace_id,
+            caller_id.as_deref(),
+        )?;
+
         let sql = SqlBuilder::select_from(&GutpComment::model_name())
             .fields(&GutpComment::fields())
             .and_where_eq("post_id", "$1")
@@ -104,10 +249,12 @@ impl GutpCommentModule {
             results.push(sp);
         }
 
+        let extra = Self::build_extra(&pg_conn, &results, caller_id.as_deref())?;
+
         let info = Info {
             model_name: GutpComment::model_name(),
             action: HandlerCRUD::List,
-            extra: "".to_string(),
+            extra,
         };
 
         Ok(Response::new(Status::Successful, info, results))
@@ -123,16 +270,24 @@ impl GutpCommentModule {
             .get("author_id")
             .ok_or(anyhow!("author_id is required."))?;
         let (limit, offset) = utils::build_page_info(&params)?;
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_optional_caller_id(req);
 
         let sql = SqlBuilder::select_from(&GutpComment::model_name())
             .fields(&GutpComment::fields())
             .and_where_eq("author_id", "$1")
+            .and_where_eq("app_id", "$3")
+            .and_where(Self::build_readable_cond("$2"))
             .order_desc("created_time")
             .limit(limit)
             .offset(offset)
             .sql()?;
-        let sql_param = ParameterValue::Str(author_id.clone());
-        let rowset = pg_conn.query(&sql, &[sql_param])?;
+        let sql_params = [
+            ParameterValue::Str(author_id.clone()),
+            ParameterValue::Str(caller_id.clone().unwrap_or_default()),
+            ParameterValue::Str(app_id),
+        ];
+        let rowset = pg_conn.query(&sql, &sql_params)?;
 
         let mut results: Vec<GutpComment> = vec![];
         for row in rowset.rows {
@@ -140,10 +295,12 @@ impl GutpCommentModule {
             results.push(sp);
         }
 
+        let extra = Self::build_extra(&pg_conn, &results, caller_id.as_deref())?;
+
         let info = Info {
             model_name: GutpComment::model_name(),
             action: HandlerCRUD::List,
-            extra: "".to_string(),
+            extra,
         };
 
         Ok(Response::new(Status::Successful, info, results))
@@ -159,14 +316,8 @@ impl GutpCommentModule {
             .get("content")
             .ok_or(anyhow!("content is required."))?
             .to_owned();
-        let author_id = params
-            .get("author_id")
-            .ok_or(anyhow!("author_id is required."))?
-            .to_owned();
-        let author_nickname = params
-            .get("author_nickname")
-            .ok_or(anyhow!("author_nickname is required."))?
-            .to_owned();
+        let app = GutpAppModule::get_request_app(req)?;
+        GutpAppModule::check_content_len(&app, &content)?;
         let post_id = params
             .get("post_id")
             .ok_or(anyhow!("post_id required."))?
@@ -180,6 +331,14 @@ impl GutpCommentModule {
             .ok_or(anyhow!("is_public is required."))?
             .parse::<bool>()?;
 
+        let app_id = utils::get_app_id(req)?;
+
+        // author fields are derived from the caller, never trusted from the client
+        let author_id = utils::get_caller_id(req)?;
+        utils::check_param_matches(&params, "author_id", &author_id)?;
+        let author = GutpUserModule::get_user_by_id(&pg_conn, &app_id, &author_id)?;
+        let author_nickname = author.nickname;
+
         let id = req
             .ext()
             .get("random_str")
@@ -191,6 +350,10 @@ impl GutpCommentModule {
             .ok_or(anyhow!("generate time failed"))?
             .parse::<i64>()?;
 
+        let post = GutpPostModule::get_post_by_id(&pg_conn, &app_id, &post_id)?;
+        GutpMemberModule::check_can_post(&pg_conn, &app_id, &post.subspace_id, &author_id)?;
+        let content = Self::check_privacy(&pg_conn, &post, is_public, content)?;
+
         let comment = GutpComment {
             id,
             content,
@@ -201,6 +364,7 @@ impl GutpCommentModule {
             is_public,
             status: GutpCommentStatus::Normal as i16,
             weight: GutpCommentWeight::Normal as i32,
+            app_id,
             created_time: time,
         };
 
@@ -230,22 +394,8 @@ impl GutpCommentModule {
             .get("content")
             .ok_or(anyhow!("content is required."))?
             .to_owned();
-        let author_id = params
-            .get("author_id")
-            .ok_or(anyhow!("author_id is required."))?
-            .to_owned();
-        let author_nickname = params
-            .get("author_nickname")
-            .ok_or(anyhow!("author_nickname is required."))?
-            .to_owned();
-        let post_id = params
-            .get("post_id")
-            .ok_or(anyhow!("post_id is required."))?
-            .to_owned();
-        let parent_comment_id = params
-            .get("parent_comment_id")
-            .ok_or(anyhow!("parent_comment_id is required."))?
-            .to_owned();
+        let app = GutpAppModule::get_request_app(req)?;
+        GutpAppModule::check_content_len(&app, &content)?;
         let is_public = params
             .get("is_public")
             .ok_or(anyhow!("is_public is required."))?
@@ -256,40 +406,107 @@ impl GutpCommentModule {
         //     .ok_or(anyhow!("generate time failed"))?
         //     .parse::<i64>()?;
 
-        // get the item from db, check whether obj in db
-        let (sql, sql_params) = GutpComment::build_get_by_id(id);
-        let rowset = pg_conn.query(&sql, &sql_params)?;
-        match rowset.rows.into_iter().next() {
-            Some(row) => {
-                let old_comment = GutpComment::from_row(row);
-
-                let comment = GutpComment {
-                    content,
-                    author_id,
-                    author_nickname,
-                    post_id,
-                    parent_comment_id,
-                    is_public,
-                    ..old_comment
-                };
-
-                let (sql, sql_params) = comment.build_update();
-                _ = pg_conn.execute(&sql, &sql_params)?;
-
-                let results: Vec<GutpComment> = vec![comment];
-
-                let info = Info {
-                    model_name: GutpComment::model_name(),
-                    action: HandlerCRUD::Update,
-                    extra: "".to_string(),
-                };
-
-                Ok(Response::new(Status::Successful, info, results))
-            }
-            None => {
-                bail!("update action: no item in db");
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_caller_id(req)?;
+
+        let comment = utils::with_transaction(&pg_conn, |conn| {
+            let old_comment = Self::get_comment_for_update(conn, &app_id, id)?;
+
+            if caller_id != old_comment.author_id {
+                bail!("only the author can update this comment");
             }
+            // a comment stays under its post and parent comment
+            let post = GutpPostModule::get_post_by_id(conn, &app_id, &old_comment.post_id)?;
+            let content = Self::check_privacy(conn, &post, is_public, content)?;
+
+            let comment = GutpComment {
+                content,
+                is_public,
+                ..old_comment
+            };
+
+            let (sql, sql_params) = comment.build_update();
+            _ = conn.execute(&sql, &sql_params)?;
+
+            Ok(comment)
+        })?;
+
+        let results: Vec<GutpComment> = vec![comment];
+
+        let info = Info {
+            model_name: GutpComment::model_name(),
+            action: HandlerCRUD::Update,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
+    /// Promote or demote a comment, by a moderator of the subspace of its post.
+    fn set_weight(req: &mut Request) -> Result<Response> {
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let id = params.get("id").ok_or(anyhow!("id is required."))?;
+        let weight = params
+            .get("weight")
+            .ok_or(anyhow!("weight is required."))?
+            .parse::<i32>()?;
+        if !(GutpCommentWeight::SuperLow as i32..=GutpCommentWeight::SuperHigh as i32)
+            .contains(&weight)
+        {
+            bail!("weight is out of range");
         }
+
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_caller_id(req)?;
+        let actor = GutpModLogModule::get_actor(req, &params)?;
+
+        let comment = utils::with_transaction(&pg_conn, |conn| {
+            let old_comment = Self::get_comment_by_id(conn, &app_id, id)?;
+            let post = GutpPostModule::get_post_by_id(conn, &app_id, &old_comment.post_id)?;
+            GutpModeratorModule::check_subspace_moderator(
+                conn,
+                &app_id,
+                &post.subspace_id,
+                &caller_id,
+            )?;
+
+            GutpModLogModule::record(
+                conn,
+                &actor,
+                GutpModLog {
+                    subspace_id: post.subspace_id,
+                    target_model: GutpComment::model_name(),
+                    target_id: old_comment.id.clone(),
+                    action: "set_weight".to_string(),
+                    before_value: old_comment.weight.to_string(),
+                    after_value: weight.to_string(),
+                    ..Default::default()
+                },
+            )?;
+
+            let comment = GutpComment {
+                weight,
+                ..old_comment
+            };
+            let (sql, sql_params) = comment.build_update();
+            _ = conn.execute(&sql, &sql_params)?;
+
+            Ok(comment)
+        })?;
+
+        let results: Vec<GutpComment> = vec![comment];
+
+        let info = Info {
+            model_name: GutpComment::model_name(),
+            action: HandlerCRUD::Update,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
     }
 
     fn delete(req: &mut Request) -> Result<Response> {
@@ -299,9 +516,40 @@ impl GutpCommentModule {
         let params = req.parse_urlencoded()?;
 
         let id = params.get("id").ok_or(anyhow!("id is required."))?;
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_caller_id(req)?;
+        let actor = GutpModLogModule::get_actor(req, &params)?;
+
+        utils::with_transaction(&pg_conn, |conn| {
+            let comment = Self::get_comment_by_id(conn, &app_id, id)?;
+            if comment.author_id != caller_id {
+                let post = GutpPostModule::get_post_by_id(conn, &app_id, &comment.post_id)?;
+                GutpModeratorModule::check_subspace_moderator(
+                    conn,
+                    &app_id,
+                    &post.subspace_id,
+                    &caller_id,
+                )?;
+                GutpModLogModule::record(
+                    conn,
+                    &actor,
+                    GutpModLog {
+                        subspace_id: post.subspace_id,
+                        target_model: GutpComment::model_name(),
+                        target_id: comment.id.clone(),
+                        action: "delete".to_string(),
+                        before_value: comment.status.to_string(),
+                        after_value: "".to_string(),
+                        ..Default::default()
+                    },
+                )?;
+            }
+            GutpReactionModule::delete_by_target(conn, &GutpComment::model_name(), id)?;
 
-        let (sql, sql_params) = GutpComment::build_delete(id);
-        let _er = pg_conn.execute(&sql, &sql_params)?;
+            let (sql, sql_params) = GutpComment::build_delete(id);
+            let _er = conn.execute(&sql, &sql_params)?;
+            Ok(())
+        })?;
 
         let info = Info {
             model_name: GutpComment::model_name(),
@@ -322,6 +570,7 @@ impl Module for GutpCommentModule {
         router.get("/gutp/v1/comment/list_by_author", Self::list_by_author);
         router.post("/gutp/v1/comment/create", Self::new_one);
         router.post("/gutp/v1/comment/update", Self::update);
+        router.post("/gutp/v1/comment/set_weight", Self::set_weight);
         router.post("/gutp/v1/comment/delete", Self::delete);
 
         Ok(())
@@ -1,3 +1,25 @@
 pub const REDIS_URL_ENV: &str = "REDIS_URL_ENV";
 pub const DB_URL_ENV: &str = "DB_URL_ENV";
+// comma separated caller ids of the operators of this instance, the only ones who can
+// register apps. Without it no app can be registered through the api
+pub const OPERATOR_KEYS_ENV: &str = "OPERATOR_KEYS_ENV";
 pub const PAGESIZE: usize = 25;
+// the key in request ext, filled with the verified user id of the request sender
+pub const CALLER_ID_KEY: &str = "caller_id";
+// the key in request ext of the app the request comes from. The gateway fills it
+// from the `X-Gutp-App-Id` header, clients without that header pass an `app_id` param
+pub const APP_ID_KEY: &str = "app_id";
+pub const APP_ID_MAX_LEN: usize = 64;
+// the key in request ext, filled by the global filter with the registered app of the
+// request, as json. Absent while the app is not registered yet
+pub const APP_KEY: &str = "app";
+// the features an app can switch off, all of them are on for a newly registered app
+pub const APP_FEATURES: [&str; 5] = ["reactions", "bookmarks", "follows", "messages", "search"];
+pub const SUGGEST_LIMIT: u64 = 10;
+pub const SUGGEST_LIMIT_MAX: u64 = 20;
+// max length of the encrypted private profile of a user, in bytes of its envelope
+pub const PRIVATE_PROFILE_MAX_LEN: usize = 16 * 1024;
+// max length of a direct message, in bytes of its envelope
+pub const MESSAGE_MAX_LEN: usize = 64 * 1024;
+// max length of the reason of a report
+pub const REPORT_REASON_MAX_LEN: usize = 1000;
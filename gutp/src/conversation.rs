@@ -0,0 +1,268 @@
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, bail};
+use eightfish_sdk::{HandlerCRUD, Info, Module, Request, Response, Result, Router, Status};
+use spin_sdk::pg::{self, Decode, ParameterValue};
+use sql_builder::SqlBuilder;
+
+use crate::app::GutpAppModule;
+use crate::constants::DB_URL_ENV;
+use crate::utils;
+use gutp_types::GutpConversation;
+
+pub struct GutpConversationModule;
+
+impl GutpConversationModule {
+    /// The two users of a conversation in the stored order.
+    fn order_users<'a>(user_id: &'a str, other_id: &'a str) -> (&'a str, &'a str) {
+        if user_id < other_id {
+            (user_id, other_id)
+        } else {
+            (other_id, user_id)
+        }
+    }
+
+    pub(crate) fn find_between(
+        pg_conn: &pg::Connection,
+        app_id: &str,
+        user_id: &str,
+        other_id: &str,
+    ) -> Result<Option<GutpConversation>> {
+        let (user_a_id, user_b_id) = Self::order_users(user_id, other_id);
+        let sql = SqlBuilder::select_from(&GutpConversation::model_name())
+            .fields(&GutpConversation::fields())
+            .and_where_eq("app_id", "$1")
+            .and_where_eq("user_a_id", "$2")
+            .and_where_eq("user_b_id", "$3")
+            .sql()?;
+        let sql_params = [
+            ParameterValue::Str(app_id.to_owned()),
+            ParameterValue::Str(user_a_id.to_owned()),
+            ParameterValue::Str(user_b_id.to_owned()),
+        ];
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+
+        Ok(rowset
+            .rows
+            .into_iter()
+            .next()
+            .map(GutpConversation::from_row))
+    }
+
+    /// Create the conversation between two users, `user_id` being the first
+    /// to send a message in it.
+    pub(crate) fn create_between(
+        pg_conn: &pg::Connection,
+        id: String,
+        app_id: &str,
+        user_id: &str,
+        other_id: &str,
+        time: i64,
+    ) -> Result<GutpConversation> {
+        let (user_a_id, user_b_id) = Self::order_users(user_id, other_id);
+        let conversation = GutpConversation {
+            id,
+            app_id: app_id.to_owned(),
+            user_a_id: user_a_id.to_owned(),
+            user_b_id: user_b_id.to_owned(),
+            a_read_time: 0,
+            b_read_time: 0,
+            last_message_time: time,
+            created_time: time,
+        };
+        let (sql, sql_params) = conversation.build_insert();
+        _ = pg_conn.execute(&sql, &sql_params)?;
+
+        Ok(conversation)
+    }
+
+    /// Get a conversation of the app the user takes part in.
+    pub(crate) fn get_own_conversation(
+        pg_conn: &pg::Connection,
+        app_id: &str,
+        id: &str,
+        user_id: &str,
+    ) -> Result<GutpConversation> {
+        let (sql, sql_params) = GutpConversation::build_get_by_id(id);
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+        let conversation = match rowset
+            .rows
+            .into_iter()
+            .next()
+            .map(GutpConversation::from_row)
+        {
+            Some(conversation) if conversation.app_id == app_id => conversation,
+            _ => bail!("no such conversation: {id}"),
+        };
+        if conversation.user_a_id != user_id && conversation.user_b_id != user_id {
+            bail!("you are not in this conversation");
+        }
+
+        Ok(conversation)
+    }
+
+    /// Remove the conversations of a user and their messages, when the user
+    /// is deleted.
+    pub(crate) fn delete_by_user(pg_conn: &pg::Connection, user_id: &str) -> Result<()> {
+        let sql_param = ParameterValue::Str(user_id.to_owned());
+        _ = pg_conn.execute(
+            "DELETE FROM gutpmessage WHERE conversation_id IN (SELECT id FROM gutpconversation WHERE user_a_id = $1 OR user_b_id = $1)",
+            std::slice::from_ref(&sql_param),
+        )?;
+        _ = pg_conn.execute(
+            "DELETE FROM gutpconversation WHERE user_a_id = $1 OR user_b_id = $1",
+            &[sql_param],
+        )?;
+        Ok(())
+    }
+
+    /// Mark the messages of a conversation up to `time` as read by the user.
+    /// Read times only move forward.
+    pub(crate) fn set_read_time(
+        pg_conn: &pg::Connection,
+        conversation: GutpConversation,
+        user_id: &str,
+        time: i64,
+    ) -> Result<GutpConversation> {
+        let conversation = if conversation.user_a_id == user_id {
+            GutpConversation {
+                a_read_time: conversation.a_read_time.max(time),
+                ..conversation
+            }
+        } else {
+            GutpConversation {
+                b_read_time: conversation.b_read_time.max(time),
+                ..conversation
+            }
+        };
+        let (sql, sql_params) = conversation.build_update();
+        _ = pg_conn.execute(&sql, &sql_params)?;
+
+        Ok(conversation)
+    }
+
+    /// Number of unread messages of the user in each conversation, as a json
+    /// object keyed by conversation id.
+    fn build_unread_counts(
+        pg_conn: &pg::Connection,
+        conversations: &[GutpConversation],
+        user_id: &str,
+    ) -> Result<String> {
+        let mut counts: BTreeMap<String, i64> = BTreeMap::new();
+        if conversations.is_empty() {
+            return Ok(serde_json::to_string(&counts)?);
+        }
+
+        let placeholders = utils::build_placeholders(2, conversations.len());
+        let mut sql_params = vec![ParameterValue::Str(user_id.to_owned())];
+        for conversation in conversations {
+            sql_params.push(ParameterValue::Str(conversation.id.clone()));
+            counts.insert(conversation.id.clone(), 0);
+        }
+        let sql = format!(
+            "SELECT gutpmessage.conversation_id, COUNT(*) FROM gutpmessage JOIN gutpconversation ON gutpmessage.conversation_id = gutpconversation.id WHERE gutpmessage.conversation_id IN ({placeholders}) AND gutpmessage.sender_id <> $1 AND gutpmessage.created_time > (CASE WHEN gutpconversation.user_a_id = $1 THEN gutpconversation.a_read_time ELSE gutpconversation.b_read_time END) GROUP BY gutpmessage.conversation_id"
+        );
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+        for row in rowset.rows {
+            let conversation_id = String::decode(&row[0])?;
+            let count = i64::decode(&row[1])?;
+            counts.insert(conversation_id, count);
+        }
+
+        Ok(serde_json::to_string(&counts)?)
+    }
+
+    /// The caller's conversations, the latest active first. Unread message
+    /// counts go in extra.
+    fn get_list(req: &mut Request) -> Result<Response> {
+        GutpAppModule::check_feature(req, "messages")?;
+
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let app_id = utils::get_app_id(req)?;
+        let user_id = utils::get_caller_id(req)?;
+        let (limit, offset) = utils::build_page_info(&params)?;
+
+        let sql = SqlBuilder::select_from(&GutpConversation::model_name())
+            .fields(&GutpConversation::fields())
+            .and_where_eq("app_id", "$1")
+            .and_where("(user_a_id = $2 OR user_b_id = $2)")
+            .order_desc("last_message_time")
+            .limit(limit)
+            .offset(offset)
+            .sql()?;
+        let sql_params = [
+            ParameterValue::Str(app_id),
+            ParameterValue::Str(user_id.clone()),
+        ];
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+
+        let mut results: Vec<GutpConversation> = vec![];
+        for row in rowset.rows {
+            let sp = GutpConversation::from_row(row);
+            results.push(sp);
+        }
+
+        let extra = Self::build_unread_counts(&pg_conn, &results, &user_id)?;
+
+        let info = Info {
+            model_name: GutpConversation::model_name(),
+            action: HandlerCRUD::List,
+            extra,
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
+    /// Mark the messages of a conversation as read by the caller, up to
+    /// `read_time` or by default all of them.
+    fn mark_read(req: &mut Request) -> Result<Response> {
+        GutpAppModule::check_feature(req, "messages")?;
+
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let id = params.get("id").ok_or(anyhow!("id is required"))?;
+
+        let app_id = utils::get_app_id(req)?;
+        let user_id = utils::get_caller_id(req)?;
+        let time = req
+            .ext()
+            .get("time")
+            .ok_or(anyhow!("time is required"))?
+            .parse::<i64>()?;
+        let read_time = match params.get("read_time") {
+            Some(read_time) => read_time.parse::<i64>()?.min(time),
+            None => time,
+        };
+
+        let conversation = utils::with_transaction(&pg_conn, |conn| {
+            let conversation = Self::get_own_conversation(conn, &app_id, id, &user_id)?;
+            Self::set_read_time(conn, conversation, &user_id, read_time)
+        })?;
+
+        let results: Vec<GutpConversation> = vec![conversation];
+
+        let info = Info {
+            model_name: GutpConversation::model_name(),
+            action: HandlerCRUD::Update,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+}
+
+impl Module for GutpConversationModule {
+    fn router(&self, router: &mut Router) -> Result<()> {
+        router.get("/gutp/v1/conversation/list", Self::get_list);
+        router.post("/gutp/v1/conversation/mark_read", Self::mark_read);
+
+        Ok(())
+    }
+}
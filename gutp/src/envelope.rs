@@ -0,0 +1,198 @@
+use anyhow::{anyhow, bail};
+use eightfish_sdk::Result;
+use serde::{Deserialize, Serialize};
+
+const ENVELOPE_VERSION: u32 = 1;
+// size of the AEAD auth tag, the shortest possible ciphertext
+const AUTH_TAG_LEN: usize = 16;
+
+/// The envelope format of the encrypted text fields of non-public data.
+///
+/// Clients encrypt `title` and `content` with the group key of the subspace
+/// and send the envelope, as json, in place of the plaintext:
+///
+/// ```json
+/// {"v":1,"alg":"xchacha20poly1305","key_version":3,"nonce":"<base64>","ciphertext":"<base64>"}
+/// ```
+///
+/// - v: version of the envelope format, only 1 for now
+/// - alg: the AEAD cipher, `xchacha20poly1305` or `aes256gcm`
+/// - key_version: which version of the subspace group key encrypted it, new
+///   content must use the current version
+/// - nonce, ciphertext: standard base64 with padding, the ciphertext includes the auth tag
+///
+/// The server never sees the plaintext. It only checks the envelope is well
+/// formed and stores it in the canonical form.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Envelope {
+    v: u32,
+    alg: String,
+    key_version: i64,
+    nonce: String,
+    ciphertext: String,
+}
+
+impl Envelope {
+    pub(crate) fn parse(text: &str) -> Result<Envelope> {
+        let envelope: Envelope =
+            serde_json::from_str(text).map_err(|_| anyhow!("malformed encryption envelope"))?;
+
+        if envelope.v != ENVELOPE_VERSION {
+            bail!("unsupported encryption envelope version: {}", envelope.v);
+        }
+        let nonce_len = match envelope.alg.as_str() {
+            "xchacha20poly1305" => 24,
+            "aes256gcm" => 12,
+            _ => bail!("unsupported encryption algorithm: {}", envelope.alg),
+        };
+        if envelope.key_version < 1 {
+            bail!("key_version of the encryption envelope must be positive");
+        }
+        if base64_decoded_len(&envelope.nonce)? != nonce_len {
+            bail!("nonce of the encryption envelope must be {nonce_len} bytes");
+        }
+        if base64_decoded_len(&envelope.ciphertext)? < AUTH_TAG_LEN {
+            bail!("ciphertext of the encryption envelope is too short");
+        }
+
+        Ok(envelope)
+    }
+
+    /// Check `text` is an envelope under any key version, for data encrypted
+    /// with a user's own key. Return it in the canonical form to store.
+    pub(crate) fn check_own(text: &str) -> Result<String> {
+        let envelope = Self::parse(text)?;
+        Ok(serde_json::to_string(&envelope)?)
+    }
+
+    /// Check `text` is an envelope encrypted with the current group key of the
+    /// subspace, return it in the canonical form to store.
+    pub(crate) fn check(text: &str, key_version: i64) -> Result<String> {
+        let envelope = Self::parse(text)?;
+        if key_version == 0 {
+            bail!("the subspace has no group key yet");
+        }
+        if envelope.key_version != key_version {
+            bail!("content must be encrypted with the current group key, version {key_version}");
+        }
+        Ok(serde_json::to_string(&envelope)?)
+    }
+}
+
+/// Validate a standard, padded base64 string and get the length of the bytes in it.
+pub(crate) fn base64_decoded_len(text: &str) -> Result<usize> {
+    let bytes = text.as_bytes();
+    if !bytes.len().is_multiple_of(4) {
+        bail!("malformed base64");
+    }
+
+    let padding = bytes.iter().rev().take_while(|b| **b == b'=').count();
+    if padding > 2 {
+        bail!("malformed base64");
+    }
+    let valid = bytes[..bytes.len() - padding]
+        .iter()
+        .all(|b| b.is_ascii_alphanumeric() || *b == b'+' || *b == b'/');
+    if !valid {
+        bail!("malformed base64");
+    }
+
+    Ok(bytes.len() / 4 * 3 - padding)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 24, 12 and 16 bytes of base64
+    const NONCE_24: &str = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA";
+    const NONCE_12: &str = "AAAAAAAAAAAAAAAA";
+    const CIPHERTEXT_16: &str = "AAAAAAAAAAAAAAAAAAAAAA==";
+
+    fn envelope(alg: &str, key_version: i64, nonce: &str, ciphertext: &str) -> String {
+        format!(
+            r#"{{"v":1,"alg":"{alg}","key_version":{key_version},"nonce":"{nonce}","ciphertext":"{ciphertext}"}}"#
+        )
+    }
+
+    #[test]
+    fn parse_accepts_nonce_len_per_alg() {
+        assert!(
+            Envelope::parse(&envelope("xchacha20poly1305", 1, NONCE_24, CIPHERTEXT_16)).is_ok()
+        );
+        assert!(Envelope::parse(&envelope("aes256gcm", 1, NONCE_12, CIPHERTEXT_16)).is_ok());
+        assert!(
+            Envelope::parse(&envelope("xchacha20poly1305", 1, NONCE_12, CIPHERTEXT_16)).is_err()
+        );
+        assert!(Envelope::parse(&envelope("aes256gcm", 1, NONCE_24, CIPHERTEXT_16)).is_err());
+        assert!(Envelope::parse(&envelope("rot13", 1, NONCE_24, CIPHERTEXT_16)).is_err());
+    }
+
+    #[test]
+    fn parse_requires_the_auth_tag() {
+        // 15 bytes, one short of the auth tag
+        assert!(
+            Envelope::parse(&envelope("aes256gcm", 1, NONCE_12, "AAAAAAAAAAAAAAAAAAAA")).is_err()
+        );
+    }
+
+    #[test]
+    fn parse_rejects_bad_base64() {
+        assert!(
+            Envelope::parse(&envelope("aes256gcm", 1, "AAAAAAAAAAAAAAA*", CIPHERTEXT_16)).is_err()
+        );
+        assert!(Envelope::parse(&envelope(
+            "aes256gcm",
+            1,
+            NONCE_12,
+            "AAAAAAAAAAAAAAAAAAAAAAA"
+        ))
+        .is_err());
+        assert!(Envelope::parse(&envelope(
+            "aes256gcm",
+            1,
+            NONCE_12,
+            "AAAAAAAAAAAAAAAAAAAA===="
+        ))
+        .is_err());
+    }
+
+    #[test]
+    fn parse_rejects_malformed_envelopes() {
+        let unknown_field = r#"{"v":1,"alg":"aes256gcm","key_version":1,"nonce":"AAAAAAAAAAAAAAAA","ciphertext":"AAAAAAAAAAAAAAAAAAAAAA==","plaintext":"hi"}"#;
+        assert!(Envelope::parse(unknown_field).is_err());
+        let bad_version = r#"{"v":2,"alg":"aes256gcm","key_version":1,"nonce":"AAAAAAAAAAAAAAAA","ciphertext":"AAAAAAAAAAAAAAAAAAAAAA=="}"#;
+        assert!(Envelope::parse(bad_version).is_err());
+        assert!(Envelope::parse("hello").is_err());
+        assert!(Envelope::parse(&envelope("aes256gcm", 0, NONCE_12, CIPHERTEXT_16)).is_err());
+    }
+
+    #[test]
+    fn check_requires_the_current_key_version() {
+        let text = envelope("aes256gcm", 2, NONCE_12, CIPHERTEXT_16);
+        assert!(Envelope::check(&text, 2).is_ok());
+        assert!(Envelope::check(&text, 3).is_err());
+        assert!(Envelope::check(&text, 0).is_err());
+        assert!(Envelope::check_own(&text).is_ok());
+    }
+
+    #[test]
+    fn check_returns_the_canonical_form() {
+        let text = r#"{ "ciphertext":"AAAAAAAAAAAAAAAAAAAAAA==", "nonce":"AAAAAAAAAAAAAAAA", "key_version":1, "alg":"aes256gcm", "v":1 }"#;
+        assert_eq!(
+            Envelope::check(text, 1).unwrap(),
+            envelope("aes256gcm", 1, NONCE_12, CIPHERTEXT_16)
+        );
+    }
+
+    #[test]
+    fn base64_decoded_len_counts_padding() {
+        assert_eq!(base64_decoded_len("").unwrap(), 0);
+        assert_eq!(base64_decoded_len("QQ==").unwrap(), 1);
+        assert_eq!(base64_decoded_len("QUI=").unwrap(), 2);
+        assert_eq!(base64_decoded_len("QUJD").unwrap(), 3);
+        assert!(base64_decoded_len("QUJ").is_err());
+        assert!(base64_decoded_len("Q===").is_err());
+    }
+}
@@ -0,0 +1,289 @@
+use anyhow::{anyhow, bail};
+use eightfish_sdk::{HandlerCRUD, Info, Module, Request, Response, Result, Router, Status};
+use spin_sdk::pg::{self, ParameterValue};
+use sql_builder::SqlBuilder;
+
+use crate::app::GutpAppModule;
+use crate::constants::DB_URL_ENV;
+use crate::subspace::GutpSubspaceModule;
+use crate::user::GutpUserModule;
+use crate::utils;
+use gutp_types::{GutpFollow, GutpSubspace, GutpUser};
+
+pub struct GutpFollowModule;
+
+impl GutpFollowModule {
+    /// Make sure the follow target is a user or subspace of the app.
+    fn check_target(
+        pg_conn: &pg::Connection,
+        app_id: &str,
+        target_model: &str,
+        target_id: &str,
+    ) -> Result<()> {
+        if target_model == GutpUser::model_name() {
+            _ = GutpUserModule::get_user_by_id(pg_conn, app_id, target_id)?;
+        } else if target_model == GutpSubspace::model_name() {
+            _ = GutpSubspaceModule::get_subspace_by_id(pg_conn, app_id, target_id)?;
+        } else {
+            bail!("can only follow users and subspaces");
+        }
+        Ok(())
+    }
+
+    /// Remove all the follows of a user or subspace, when it is deleted.
+    pub(crate) fn delete_by_target(
+        pg_conn: &pg::Connection,
+        target_model: &str,
+        target_id: &str,
+    ) -> Result<()> {
+        let sql_params = [
+            ParameterValue::Str(target_model.to_owned()),
+            ParameterValue::Str(target_id.to_owned()),
+        ];
+        _ = pg_conn.execute(
+            "DELETE FROM gutpfollow WHERE target_model = $1 AND target_id = $2",
+            &sql_params,
+        )?;
+        Ok(())
+    }
+
+    /// Remove all the follows by a user, when the user is deleted.
+    pub(crate) fn delete_by_follower(pg_conn: &pg::Connection, follower_id: &str) -> Result<()> {
+        let sql_param = ParameterValue::Str(follower_id.to_owned());
+        _ = pg_conn.execute(
+            "DELETE FROM gutpfollow WHERE follower_id = $1",
+            &[sql_param],
+        )?;
+        Ok(())
+    }
+
+    fn find_follow(
+        pg_conn: &pg::Connection,
+        follower_id: &str,
+        target_model: &str,
+        target_id: &str,
+    ) -> Result<Option<GutpFollow>> {
+        let sql = SqlBuilder::select_from(&GutpFollow::model_name())
+            .fields(&GutpFollow::fields())
+            .and_where_eq("follower_id", "$1")
+            .and_where_eq("target_model", "$2")
+            .and_where_eq("target_id", "$3")
+            .sql()?;
+        let sql_params = [
+            ParameterValue::Str(follower_id.to_owned()),
+            ParameterValue::Str(target_model.to_owned()),
+            ParameterValue::Str(target_id.to_owned()),
+        ];
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+
+        Ok(rowset.rows.into_iter().next().map(GutpFollow::from_row))
+    }
+
+    /// Who follows a user or subspace, the latest first.
+    fn list_followers(req: &mut Request) -> Result<Response> {
+        GutpAppModule::check_feature(req, "follows")?;
+
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let target_model = params
+            .get("target_model")
+            .ok_or(anyhow!("target_model is required"))?;
+        let target_id = params
+            .get("target_id")
+            .ok_or(anyhow!("target_id is required"))?;
+        let (limit, offset) = utils::build_page_info(&params)?;
+
+        let app_id = utils::get_app_id(req)?;
+        Self::check_target(&pg_conn, &app_id, target_model, target_id)?;
+
+        let sql = SqlBuilder::select_from(&GutpFollow::model_name())
+            .fields(&GutpFollow::fields())
+            .and_where_eq("target_model", "$1")
+            .and_where_eq("target_id", "$2")
+            .order_desc("created_time")
+            .limit(limit)
+            .offset(offset)
+            .sql()?;
+        let sql_params = [
+            ParameterValue::Str(target_model.clone()),
+            ParameterValue::Str(target_id.clone()),
+        ];
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+
+        let mut results: Vec<GutpFollow> = vec![];
+        for row in rowset.rows {
+            let sp = GutpFollow::from_row(row);
+            results.push(sp);
+        }
+
+        let info = Info {
+            model_name: GutpFollow::model_name(),
+            action: HandlerCRUD::List,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
+    /// What a user follows, the latest first, optionally only one kind of
+    /// target. Defaults to the caller.
+    fn list_following(req: &mut Request) -> Result<Response> {
+        GutpAppModule::check_feature(req, "follows")?;
+
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let app_id = utils::get_app_id(req)?;
+        let follower_id = match params.get("user_id") {
+            Some(user_id) => GutpUserModule::get_user_by_id(&pg_conn, &app_id, user_id)?.id,
+            None => utils::get_caller_id(req)?,
+        };
+        let (limit, offset) = utils::build_page_info(&params)?;
+
+        let mut builder = SqlBuilder::select_from(&GutpFollow::model_name());
+        builder
+            .fields(&GutpFollow::fields())
+            .and_where_eq("follower_id", "$1");
+        let mut sql_params = vec![ParameterValue::Str(follower_id)];
+        if let Some(target_model) = params.get("target_model") {
+            sql_params.push(ParameterValue::Str(target_model.clone()));
+            builder.and_where_eq("target_model", "$2");
+        }
+        let sql = builder
+            .order_desc("created_time")
+            .limit(limit)
+            .offset(offset)
+            .sql()?;
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+
+        let mut results: Vec<GutpFollow> = vec![];
+        for row in rowset.rows {
+            let sp = GutpFollow::from_row(row);
+            results.push(sp);
+        }
+
+        let info = Info {
+            model_name: GutpFollow::model_name(),
+            action: HandlerCRUD::List,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
+    /// Follow a user or subspace as the caller. Following it again returns
+    /// the existing follow.
+    fn new_one(req: &mut Request) -> Result<Response> {
+        GutpAppModule::check_feature(req, "follows")?;
+
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let target_model = params
+            .get("target_model")
+            .ok_or(anyhow!("target_model is required"))?
+            .to_owned();
+        let target_id = params
+            .get("target_id")
+            .ok_or(anyhow!("target_id is required"))?
+            .to_owned();
+
+        let app_id = utils::get_app_id(req)?;
+        let follower_id = utils::get_caller_id(req)?;
+        if target_model == GutpUser::model_name() && target_id == follower_id {
+            bail!("can not follow yourself");
+        }
+        let id = req
+            .ext()
+            .get("random_str")
+            .ok_or(anyhow!("generate id failed"))?
+            .to_owned();
+        let time = req
+            .ext()
+            .get("time")
+            .ok_or(anyhow!("generate time failed"))?
+            .parse::<i64>()?;
+
+        let follow = utils::with_transaction(&pg_conn, |conn| {
+            if let Some(follow) = Self::find_follow(conn, &follower_id, &target_model, &target_id)?
+            {
+                return Ok(follow);
+            }
+
+            Self::check_target(conn, &app_id, &target_model, &target_id)?;
+
+            let follow = GutpFollow {
+                id,
+                follower_id,
+                target_model,
+                target_id,
+                created_time: time,
+            };
+            let (sql, sql_params) = follow.build_insert();
+            _ = conn.execute(&sql, &sql_params)?;
+
+            Ok(follow)
+        })?;
+
+        let results: Vec<GutpFollow> = vec![follow];
+
+        let info = Info {
+            model_name: GutpFollow::model_name(),
+            action: HandlerCRUD::Create,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
+    /// Unfollow a user or subspace as the caller.
+    fn delete(req: &mut Request) -> Result<Response> {
+        GutpAppModule::check_feature(req, "follows")?;
+
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let target_model = params
+            .get("target_model")
+            .ok_or(anyhow!("target_model is required"))?;
+        let target_id = params
+            .get("target_id")
+            .ok_or(anyhow!("target_id is required"))?;
+
+        let follower_id = utils::get_caller_id(req)?;
+
+        if let Some(follow) = Self::find_follow(&pg_conn, &follower_id, target_model, target_id)? {
+            let (sql, sql_params) = GutpFollow::build_delete(&follow.id);
+            _ = pg_conn.execute(&sql, &sql_params)?;
+        }
+
+        let info = Info {
+            model_name: GutpFollow::model_name(),
+            action: HandlerCRUD::Delete,
+            extra: "".to_string(),
+        };
+        let results: Vec<GutpFollow> = vec![];
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+}
+
+impl Module for GutpFollowModule {
+    fn router(&self, router: &mut Router) -> Result<()> {
+        router.get("/gutp/v1/follow/list_followers", Self::list_followers);
+        router.get("/gutp/v1/follow/list_following", Self::list_following);
+        router.post("/gutp/v1/follow/create", Self::new_one);
+        router.post("/gutp/v1/follow/delete", Self::delete);
+
+        Ok(())
+    }
+}
@@ -6,22 +6,70 @@ use spin_sdk::redis_component;
 use eightfish_sdk::{
     App as EightFishApp, GlobalFilter, Request, Response, Result as EightFishResult,
 };
+use spin_sdk::pg;
 
+use crate::constants::{APP_KEY, DB_URL_ENV};
+
+mod app;
+mod bookmark;
+mod category;
 mod comment;
 mod constants;
+mod conversation;
+mod envelope;
+mod follow;
+mod member;
+mod message;
 mod moderator;
+mod modlog;
 mod post;
 mod postdiff;
 mod posttag;
+mod reaction;
+mod reactionkind;
+mod report;
+mod reportcase;
+mod search;
 mod subspace;
+mod subspacekey;
 mod tag;
 mod user;
+mod userprofile;
 mod utils;
 
 struct MyGlobalFilter;
 
 impl GlobalFilter for MyGlobalFilter {
-    fn before(&self, _req: &mut Request) -> EightFishResult<()> {
+    /// Settle the app context of the request, and make sure the caller, if
+    /// any, is a user or an owner of that app, so no request can reach another
+    /// app's data. The registered app is loaded into the ext for the handlers.
+    fn before(&self, req: &mut Request) -> EightFishResult<()> {
+        let app_id = utils::resolve_app_id(req)?;
+
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+        // without a registered app, registering it is the only thing a request can do
+        let Some(app) = app::GutpAppModule::find_app(&pg_conn, &app_id)? else {
+            return Ok(());
+        };
+
+        match utils::get_optional_caller_id(req) {
+            Some(caller_id) => {
+                if !app::GutpAppModule::is_owner(&app, &caller_id)
+                    && user::GutpUserModule::get_user_by_id(&pg_conn, &app_id, &caller_id).is_err()
+                {
+                    anyhow::bail!("the caller is not a user of this app");
+                }
+            }
+            None => {
+                if !app.allow_anonymous_read {
+                    anyhow::bail!("this app does not allow anonymous access");
+                }
+            }
+        }
+
+        req.ext_mut()
+            .insert(APP_KEY.to_string(), serde_json::to_string(&app)?);
         Ok(())
     }
 
@@ -33,13 +81,28 @@ impl GlobalFilter for MyGlobalFilter {
 pub fn build_app() -> EightFishApp {
     let mut sapp = EightFishApp::new();
     sapp.add_global_filter(Box::new(MyGlobalFilter))
+        .add_module(Box::new(app::GutpAppModule))
         .add_module(Box::new(user::GutpUserModule))
+        .add_module(Box::new(userprofile::GutpUserProfileModule))
+        .add_module(Box::new(bookmark::GutpBookmarkModule))
+        .add_module(Box::new(category::GutpCategoryModule))
         .add_module(Box::new(comment::GutpCommentModule))
+        .add_module(Box::new(conversation::GutpConversationModule))
+        .add_module(Box::new(follow::GutpFollowModule))
+        .add_module(Box::new(member::GutpMemberModule))
+        .add_module(Box::new(message::GutpMessageModule))
         .add_module(Box::new(moderator::GutpModeratorModule))
+        .add_module(Box::new(modlog::GutpModLogModule))
         .add_module(Box::new(post::GutpPostModule))
         .add_module(Box::new(postdiff::GutpPostDiffModule))
         .add_module(Box::new(posttag::GutpPostTagModule))
+        .add_module(Box::new(reaction::GutpReactionModule))
+        .add_module(Box::new(reactionkind::GutpReactionKindModule))
+        .add_module(Box::new(report::GutpReportModule))
+        .add_module(Box::new(reportcase::GutpReportCaseModule))
+        .add_module(Box::new(search::GutpSearchModule))
         .add_module(Box::new(subspace::GutpSubspaceModule))
+        .add_module(Box::new(subspacekey::GutpSubspaceKeyModule))
         .add_module(Box::new(tag::GutpTagModule));
 
     sapp
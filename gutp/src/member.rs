@@ -0,0 +1,776 @@
+use anyhow::{anyhow, bail};
+use eightfish_sdk::{HandlerCRUD, Info, Module, Request, Response, Result, Router, Status};
+use spin_sdk::pg::{self, Decode, ParameterValue};
+use sql_builder::SqlBuilder;
+
+use crate::constants::DB_URL_ENV;
+use crate::modlog::GutpModLogModule;
+use crate::subspace::GutpSubspaceModule;
+use crate::subspacekey::GutpSubspaceKeyModule;
+use crate::user::GutpUserModule;
+use crate::utils;
+use gutp_types::{GutpMember, GutpModLog, GutpSubspace, GutpUser};
+
+enum GutpMemberRole {
+    Member = 0,
+    // manages the members of the subspace, besides the owner
+    Admin = 1,
+}
+
+enum GutpMemberStatus {
+    // asked to join a private subspace, waiting for approval
+    Pending = 0,
+    // invited by an admin, waiting for the user to join
+    Invited = 1,
+    Active = 2,
+    Banned = 3,
+}
+
+pub struct GutpMemberModule;
+
+impl GutpMemberModule {
+    fn find_member(
+        pg_conn: &pg::Connection,
+        subspace_id: &str,
+        user_id: &str,
+    ) -> Result<Option<GutpMember>> {
+        let sql = SqlBuilder::select_from(&GutpMember::model_name())
+            .fields(&GutpMember::fields())
+            .and_where_eq("subspace_id", "$1")
+            .and_where_eq("user_id", "$2")
+            .sql()?;
+        let sql_params = [
+            ParameterValue::Str(subspace_id.to_owned()),
+            ParameterValue::Str(user_id.to_owned()),
+        ];
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+
+        Ok(rowset.rows.into_iter().next().map(GutpMember::from_row))
+    }
+
+    /// The owner and the active members of a subspace.
+    pub(crate) fn get_active_member_ids(
+        pg_conn: &pg::Connection,
+        subspace: &GutpSubspace,
+    ) -> Result<Vec<String>> {
+        let sql = SqlBuilder::select_from(&GutpMember::model_name())
+            .field("user_id")
+            .and_where_eq("subspace_id", "$1")
+            .and_where_eq("status", GutpMemberStatus::Active as i16)
+            .sql()?;
+        let rowset = pg_conn.query(&sql, &[ParameterValue::Str(subspace.id.clone())])?;
+
+        let mut user_ids = vec![subspace.owner_id.clone()];
+        for row in rowset.rows {
+            let user_id = String::decode(&row[0])?;
+            if !user_ids.contains(&user_id) {
+                user_ids.push(user_id);
+            }
+        }
+
+        Ok(user_ids)
+    }
+
+    pub(crate) fn is_active_member(
+        pg_conn: &pg::Connection,
+        subspace: &GutpSubspace,
+        user_id: &str,
+    ) -> Result<bool> {
+        if subspace.owner_id == user_id {
+            return Ok(true);
+        }
+        let member = Self::find_member(pg_conn, &subspace.id, user_id)?;
+        Ok(matches!(member, Some(m) if m.status == GutpMemberStatus::Active as i16))
+    }
+
+    /// Only the owner and the admin members of a subspace may manage its members.
+    pub(crate) fn check_member_admin(
+        pg_conn: &pg::Connection,
+        subspace: &GutpSubspace,
+        user_id: &str,
+    ) -> Result<()> {
+        if subspace.owner_id == user_id {
+            return Ok(());
+        }
+        match Self::find_member(pg_conn, &subspace.id, user_id)? {
+            Some(m)
+                if m.status == GutpMemberStatus::Active as i16
+                    && m.role == GutpMemberRole::Admin as i16 =>
+            {
+                Ok(())
+            }
+            _ => bail!("only admins of this subspace can do this"),
+        }
+    }
+
+    /// Sql condition on `subspace_field` keeping the subspaces the user in
+    /// `user_placeholder` may read: the public ones, and the private ones it
+    /// owns or is an active member of. Bind an empty user id for anonymous
+    /// callers.
+    pub(crate) fn build_readable_cond(subspace_field: &str, user_placeholder: &str) -> String {
+        format!(
+            "{subspace_field} IN (SELECT id FROM gutpsubspace WHERE is_public = true OR owner_id = {user_placeholder} OR id IN (SELECT subspace_id FROM gutpmember WHERE user_id = {user_placeholder} AND status = {}))",
+            GutpMemberStatus::Active as i16
+        )
+    }
+
+    /// The content of a private subspace is only readable by its members.
+    pub(crate) fn check_readable(
+        pg_conn: &pg::Connection,
+        app_id: &str,
+        subspace_id: &str,
+        caller_id: Option<&str>,
+    ) -> Result<()> {
+        let subspace = GutpSubspaceModule::get_subspace_by_id(pg_conn, app_id, subspace_id)?;
+        if subspace.is_public {
+            return Ok(());
+        }
+        match caller_id {
+            Some(caller_id) if Self::is_active_member(pg_conn, &subspace, caller_id)? => Ok(()),
+            _ => bail!("only members can read this private subspace"),
+        }
+    }
+
+    /// Banned users can not post in a subspace, and only members can post in
+    /// a private one.
+    pub(crate) fn check_can_post(
+        pg_conn: &pg::Connection,
+        app_id: &str,
+        subspace_id: &str,
+        user_id: &str,
+    ) -> Result<()> {
+        let subspace = GutpSubspaceModule::get_subspace_by_id(pg_conn, app_id, subspace_id)?;
+        if subspace.owner_id == user_id {
+            return Ok(());
+        }
+        let member = Self::find_member(pg_conn, subspace_id, user_id)?;
+        let status = member.map(|m| m.status);
+        if status == Some(GutpMemberStatus::Banned as i16) {
+            bail!("you are banned from this subspace");
+        }
+        if !subspace.is_public && status != Some(GutpMemberStatus::Active as i16) {
+            bail!("only members can post in this private subspace");
+        }
+        Ok(())
+    }
+
+    /// Remove all the members of a subspace, when the subspace is deleted.
+    pub(crate) fn delete_by_subspace(pg_conn: &pg::Connection, subspace_id: &str) -> Result<()> {
+        let sql_param = ParameterValue::Str(subspace_id.to_owned());
+        _ = pg_conn.execute(
+            "DELETE FROM gutpmember WHERE subspace_id = $1",
+            &[sql_param],
+        )?;
+        Ok(())
+    }
+
+    fn save_member(pg_conn: &pg::Connection, member: &GutpMember, is_new: bool) -> Result<()> {
+        let (sql, sql_params) = if is_new {
+            member.build_insert()
+        } else {
+            member.build_update()
+        };
+        _ = pg_conn.execute(&sql, &sql_params)?;
+        Ok(())
+    }
+
+    /// The members of a subspace, the latest first, by default the active ones.
+    /// Listing other statuses, like the pending requests, is for admins.
+    fn list_by_subspace(req: &mut Request) -> Result<Response> {
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let subspace_id = params
+            .get("subspace_id")
+            .ok_or(anyhow!("subspace_id is required"))?;
+        let status = params
+            .get("status")
+            .unwrap_or(&(GutpMemberStatus::Active as i16).to_string())
+            .parse::<i16>()?;
+        let (limit, offset) = utils::build_page_info(&params)?;
+
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_optional_caller_id(req);
+        if status == GutpMemberStatus::Active as i16 {
+            Self::check_readable(&pg_conn, &app_id, subspace_id, caller_id.as_deref())?;
+        } else {
+            let subspace = GutpSubspaceModule::get_subspace_by_id(&pg_conn, &app_id, subspace_id)?;
+            let caller_id = caller_id.ok_or(anyhow!("caller identity is required"))?;
+            Self::check_member_admin(&pg_conn, &subspace, &caller_id)?;
+        }
+
+        let sql = SqlBuilder::select_from(&GutpMember::model_name())
+            .fields(&GutpMember::fields())
+            .and_where_eq("subspace_id", "$1")
+            .and_where_eq("status", "$2")
+            .order_desc("created_time")
+            .limit(limit)
+            .offset(offset)
+            .sql()?;
+        let sql_params = [
+            ParameterValue::Str(subspace_id.clone()),
+            ParameterValue::Int16(status),
+        ];
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+
+        let mut results: Vec<GutpMember> = vec![];
+        for row in rowset.rows {
+            let sp = GutpMember::from_row(row);
+            results.push(sp);
+        }
+
+        let info = Info {
+            model_name: GutpMember::model_name(),
+            action: HandlerCRUD::List,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
+    /// The caller's memberships, including pending requests and invitations.
+    fn list_mine(req: &mut Request) -> Result<Response> {
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let app_id = utils::get_app_id(req)?;
+        let user_id = utils::get_caller_id(req)?;
+        let (limit, offset) = utils::build_page_info(&params)?;
+
+        let sql = SqlBuilder::select_from(&GutpMember::model_name())
+            .fields(&GutpMember::fields())
+            .and_where_eq("user_id", "$1")
+            .and_where("subspace_id IN (SELECT id FROM gutpsubspace WHERE app_id = $2)")
+            .order_desc("updated_time")
+            .limit(limit)
+            .offset(offset)
+            .sql()?;
+        let sql_params = [ParameterValue::Str(user_id), ParameterValue::Str(app_id)];
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+
+        let mut results: Vec<GutpMember> = vec![];
+        for row in rowset.rows {
+            let sp = GutpMember::from_row(row);
+            results.push(sp);
+        }
+
+        let info = Info {
+            model_name: GutpMember::model_name(),
+            action: HandlerCRUD::List,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
+    /// Join a subspace as the caller. A public subspace is joined at once, a
+    /// private one needs an invitation or the approval of an admin.
+    fn join(req: &mut Request) -> Result<Response> {
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let subspace_id = params
+            .get("subspace_id")
+            .ok_or(anyhow!("subspace_id is required"))?
+            .to_owned();
+
+        let app_id = utils::get_app_id(req)?;
+        let user_id = utils::get_caller_id(req)?;
+        let id = req
+            .ext()
+            .get("random_str")
+            .ok_or(anyhow!("generate id failed"))?
+            .to_owned();
+        let time = req
+            .ext()
+            .get("time")
+            .ok_or(anyhow!("generate time failed"))?
+            .parse::<i64>()?;
+
+        let member = utils::with_transaction(&pg_conn, |conn| {
+            let subspace = GutpSubspaceModule::get_subspace_by_id(conn, &app_id, &subspace_id)?;
+            if subspace.owner_id == user_id {
+                bail!("the owner is always a member of the subspace");
+            }
+
+            let member = match Self::find_member(conn, &subspace_id, &user_id)? {
+                Some(m) if m.status == GutpMemberStatus::Banned as i16 => {
+                    bail!("you are banned from this subspace");
+                }
+                Some(m) if m.status == GutpMemberStatus::Invited as i16 => {
+                    let member = GutpMember {
+                        status: GutpMemberStatus::Active as i16,
+                        updated_time: time,
+                        ..m
+                    };
+                    Self::save_member(conn, &member, false)?;
+                    member
+                }
+                Some(m) => m,
+                None => {
+                    let status = if subspace.is_public {
+                        GutpMemberStatus::Active
+                    } else {
+                        GutpMemberStatus::Pending
+                    };
+                    let member = GutpMember {
+                        id,
+                        subspace_id,
+                        user_id,
+                        role: GutpMemberRole::Member as i16,
+                        status: status as i16,
+                        inviter_id: "".to_string(),
+                        created_time: time,
+                        updated_time: time,
+                    };
+                    Self::save_member(conn, &member, true)?;
+                    member
+                }
+            };
+
+            Ok(member)
+        })?;
+
+        let results: Vec<GutpMember> = vec![member];
+
+        let info = Info {
+            model_name: GutpMember::model_name(),
+            action: HandlerCRUD::Create,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
+    /// Leave a subspace as the caller, also withdraws a pending request or
+    /// declines an invitation.
+    fn leave(req: &mut Request) -> Result<Response> {
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let subspace_id = params
+            .get("subspace_id")
+            .ok_or(anyhow!("subspace_id is required"))?;
+
+        let app_id = utils::get_app_id(req)?;
+        let user_id = utils::get_caller_id(req)?;
+
+        utils::with_transaction(&pg_conn, |conn| {
+            let subspace = GutpSubspaceModule::get_subspace_by_id(conn, &app_id, subspace_id)?;
+            if let Some(member) = Self::find_member(conn, subspace_id, &user_id)? {
+                // keep the record of a ban
+                if member.status == GutpMemberStatus::Banned as i16 {
+                    bail!("you are banned from this subspace");
+                }
+                let (sql, sql_params) = GutpMember::build_delete(&member.id);
+                _ = conn.execute(&sql, &sql_params)?;
+                GutpSubspaceKeyModule::revoke_member(conn, &subspace, &user_id)?;
+            }
+            Ok(())
+        })?;
+
+        let info = Info {
+            model_name: GutpMember::model_name(),
+            action: HandlerCRUD::Delete,
+            extra: "".to_string(),
+        };
+        let results: Vec<GutpMember> = vec![];
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
+    /// Invite a user into a subspace, by an admin. Inviting a user who asked
+    /// to join approves the request.
+    fn invite(req: &mut Request) -> Result<Response> {
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let subspace_id = params
+            .get("subspace_id")
+            .ok_or(anyhow!("subspace_id is required"))?
+            .to_owned();
+        let user_id = params
+            .get("user_id")
+            .ok_or(anyhow!("user_id is required"))?
+            .to_owned();
+
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_caller_id(req)?;
+        let id = req
+            .ext()
+            .get("random_str")
+            .ok_or(anyhow!("generate id failed"))?
+            .to_owned();
+        let time = req
+            .ext()
+            .get("time")
+            .ok_or(anyhow!("generate time failed"))?
+            .parse::<i64>()?;
+
+        let member = utils::with_transaction(&pg_conn, |conn| {
+            let subspace = GutpSubspaceModule::get_subspace_by_id(conn, &app_id, &subspace_id)?;
+            Self::check_member_admin(conn, &subspace, &caller_id)?;
+            if subspace.owner_id == user_id {
+                bail!("the owner is always a member of the subspace");
+            }
+            _ = GutpUserModule::get_user_by_id(conn, &app_id, &user_id)?;
+
+            let member = match Self::find_member(conn, &subspace_id, &user_id)? {
+                Some(m) if m.status == GutpMemberStatus::Banned as i16 => {
+                    bail!("the user is banned from this subspace");
+                }
+                Some(m) if m.status == GutpMemberStatus::Pending as i16 => {
+                    let member = GutpMember {
+                        status: GutpMemberStatus::Active as i16,
+                        inviter_id: caller_id,
+                        updated_time: time,
+                        ..m
+                    };
+                    Self::save_member(conn, &member, false)?;
+                    member
+                }
+                Some(m) => m,
+                None => {
+                    let member = GutpMember {
+                        id,
+                        subspace_id,
+                        user_id,
+                        role: GutpMemberRole::Member as i16,
+                        status: GutpMemberStatus::Invited as i16,
+                        inviter_id: caller_id,
+                        created_time: time,
+                        updated_time: time,
+                    };
+                    Self::save_member(conn, &member, true)?;
+                    member
+                }
+            };
+
+            Ok(member)
+        })?;
+
+        let results: Vec<GutpMember> = vec![member];
+
+        let info = Info {
+            model_name: GutpMember::model_name(),
+            action: HandlerCRUD::Create,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
+    /// Approve the pending request of a user to join a subspace, by an admin.
+    fn approve(req: &mut Request) -> Result<Response> {
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let subspace_id = params
+            .get("subspace_id")
+            .ok_or(anyhow!("subspace_id is required"))?;
+        let user_id = params
+            .get("user_id")
+            .ok_or(anyhow!("user_id is required"))?;
+
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_caller_id(req)?;
+        let time = req
+            .ext()
+            .get("time")
+            .ok_or(anyhow!("time is required"))?
+            .parse::<i64>()?;
+
+        let member = utils::with_transaction(&pg_conn, |conn| {
+            let subspace = GutpSubspaceModule::get_subspace_by_id(conn, &app_id, subspace_id)?;
+            Self::check_member_admin(conn, &subspace, &caller_id)?;
+
+            let member = match Self::find_member(conn, subspace_id, user_id)? {
+                Some(m) if m.status == GutpMemberStatus::Pending as i16 => GutpMember {
+                    status: GutpMemberStatus::Active as i16,
+                    inviter_id: caller_id,
+                    updated_time: time,
+                    ..m
+                },
+                _ => bail!("no pending request from this user"),
+            };
+            Self::save_member(conn, &member, false)?;
+
+            Ok(member)
+        })?;
+
+        let results: Vec<GutpMember> = vec![member];
+
+        let info = Info {
+            model_name: GutpMember::model_name(),
+            action: HandlerCRUD::Update,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
+    /// Ban a user from a subspace, by an admin. Only the owner can ban an admin.
+    fn ban(req: &mut Request) -> Result<Response> {
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let subspace_id = params
+            .get("subspace_id")
+            .ok_or(anyhow!("subspace_id is required"))?
+            .to_owned();
+        let user_id = params
+            .get("user_id")
+            .ok_or(anyhow!("user_id is required"))?
+            .to_owned();
+
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_caller_id(req)?;
+        let actor = GutpModLogModule::get_actor(req, &params)?;
+        let random_str = req
+            .ext()
+            .get("random_str")
+            .ok_or(anyhow!("random_str is required"))?
+            .to_owned();
+        let time = req
+            .ext()
+            .get("time")
+            .ok_or(anyhow!("time is required"))?
+            .parse::<i64>()?;
+
+        let member = utils::with_transaction(&pg_conn, |conn| {
+            let subspace = GutpSubspaceModule::get_subspace_by_id(conn, &app_id, &subspace_id)?;
+            Self::check_member_admin(conn, &subspace, &caller_id)?;
+            if subspace.owner_id == user_id || caller_id == user_id {
+                bail!("can not ban the owner or yourself");
+            }
+            _ = GutpUserModule::get_user_by_id(conn, &app_id, &user_id)?;
+
+            let old_member = Self::find_member(conn, &subspace_id, &user_id)?;
+            let before_value = match &old_member {
+                Some(m) => m.status.to_string(),
+                None => "".to_string(),
+            };
+            let member = match old_member {
+                Some(m) => {
+                    if m.role == GutpMemberRole::Admin as i16 && subspace.owner_id != caller_id {
+                        bail!("only the owner can ban an admin");
+                    }
+                    let member = GutpMember {
+                        role: GutpMemberRole::Member as i16,
+                        status: GutpMemberStatus::Banned as i16,
+                        inviter_id: caller_id.clone(),
+                        updated_time: time,
+                        ..m
+                    };
+                    Self::save_member(conn, &member, false)?;
+                    member
+                }
+                None => {
+                    let member = GutpMember {
+                        id: utils::derive_id(&random_str, "member", 0),
+                        subspace_id: subspace_id.clone(),
+                        user_id: user_id.clone(),
+                        role: GutpMemberRole::Member as i16,
+                        status: GutpMemberStatus::Banned as i16,
+                        inviter_id: caller_id.clone(),
+                        created_time: time,
+                        updated_time: time,
+                    };
+                    Self::save_member(conn, &member, true)?;
+                    member
+                }
+            };
+
+            GutpSubspaceKeyModule::revoke_member(conn, &subspace, &user_id)?;
+
+            GutpModLogModule::record(
+                conn,
+                &actor,
+                GutpModLog {
+                    subspace_id: subspace_id.clone(),
+                    target_model: GutpUser::model_name(),
+                    target_id: user_id.clone(),
+                    action: "ban".to_string(),
+                    before_value,
+                    after_value: member.status.to_string(),
+                    ..Default::default()
+                },
+            )?;
+
+            Ok(member)
+        })?;
+
+        let results: Vec<GutpMember> = vec![member];
+
+        let info = Info {
+            model_name: GutpMember::model_name(),
+            action: HandlerCRUD::Update,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
+    /// Lift the ban of a user, by an admin. The user may join again afterwards.
+    fn unban(req: &mut Request) -> Result<Response> {
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let subspace_id = params
+            .get("subspace_id")
+            .ok_or(anyhow!("subspace_id is required"))?
+            .to_owned();
+        let user_id = params
+            .get("user_id")
+            .ok_or(anyhow!("user_id is required"))?
+            .to_owned();
+
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_caller_id(req)?;
+        let actor = GutpModLogModule::get_actor(req, &params)?;
+
+        utils::with_transaction(&pg_conn, |conn| {
+            let subspace = GutpSubspaceModule::get_subspace_by_id(conn, &app_id, &subspace_id)?;
+            Self::check_member_admin(conn, &subspace, &caller_id)?;
+
+            let member = match Self::find_member(conn, &subspace_id, &user_id)? {
+                Some(m) if m.status == GutpMemberStatus::Banned as i16 => m,
+                _ => bail!("the user is not banned from this subspace"),
+            };
+            let (sql, sql_params) = GutpMember::build_delete(&member.id);
+            _ = conn.execute(&sql, &sql_params)?;
+
+            GutpModLogModule::record(
+                conn,
+                &actor,
+                GutpModLog {
+                    subspace_id: subspace_id.clone(),
+                    target_model: GutpUser::model_name(),
+                    target_id: user_id.clone(),
+                    action: "unban".to_string(),
+                    before_value: member.status.to_string(),
+                    after_value: "".to_string(),
+                    ..Default::default()
+                },
+            )
+        })?;
+
+        let info = Info {
+            model_name: GutpMember::model_name(),
+            action: HandlerCRUD::Delete,
+            extra: "".to_string(),
+        };
+        let results: Vec<GutpMember> = vec![];
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
+    /// Change the role of an active member, by the owner. The change goes to
+    /// the moderation log, with the optional `reason`.
+    fn set_role(req: &mut Request) -> Result<Response> {
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let subspace_id = params
+            .get("subspace_id")
+            .ok_or(anyhow!("subspace_id is required"))?;
+        let user_id = params
+            .get("user_id")
+            .ok_or(anyhow!("user_id is required"))?;
+        let role = params
+            .get("role")
+            .ok_or(anyhow!("role is required"))?
+            .parse::<i16>()?;
+        if role != GutpMemberRole::Member as i16 && role != GutpMemberRole::Admin as i16 {
+            bail!("role is out of range");
+        }
+
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_caller_id(req)?;
+        let actor = GutpModLogModule::get_actor(req, &params)?;
+        let time = req
+            .ext()
+            .get("time")
+            .ok_or(anyhow!("time is required"))?
+            .parse::<i64>()?;
+
+        let member = utils::with_transaction(&pg_conn, |conn| {
+            let subspace = GutpSubspaceModule::get_subspace_by_id(conn, &app_id, subspace_id)?;
+            if subspace.owner_id != caller_id {
+                bail!("only the owner can change the roles of members");
+            }
+
+            let old_member = match Self::find_member(conn, subspace_id, user_id)? {
+                Some(m) if m.status == GutpMemberStatus::Active as i16 => m,
+                _ => bail!("the user is not an active member of this subspace"),
+            };
+            let before_value = old_member.role.to_string();
+            let member = GutpMember {
+                role,
+                updated_time: time,
+                ..old_member
+            };
+            Self::save_member(conn, &member, false)?;
+
+            GutpModLogModule::record(
+                conn,
+                &actor,
+                GutpModLog {
+                    subspace_id: subspace_id.clone(),
+                    target_model: GutpUser::model_name(),
+                    target_id: user_id.clone(),
+                    action: "set_role".to_string(),
+                    before_value,
+                    after_value: role.to_string(),
+                    ..Default::default()
+                },
+            )?;
+
+            Ok(member)
+        })?;
+
+        let results: Vec<GutpMember> = vec![member];
+
+        let info = Info {
+            model_name: GutpMember::model_name(),
+            action: HandlerCRUD::Update,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+}
+
+impl Module for GutpMemberModule {
+    fn router(&self, router: &mut Router) -> Result<()> {
+        router.get("/gutp/v1/member/list_by_subspace", Self::list_by_subspace);
+        router.get("/gutp/v1/member/list_mine", Self::list_mine);
+        router.post("/gutp/v1/member/join", Self::join);
+        router.post("/gutp/v1/member/leave", Self::leave);
+        router.post("/gutp/v1/member/invite", Self::invite);
+        router.post("/gutp/v1/member/approve", Self::approve);
+        router.post("/gutp/v1/member/ban", Self::ban);
+        router.post("/gutp/v1/member/unban", Self::unban);
+        router.post("/gutp/v1/member/set_role", Self::set_role);
+
+        Ok(())
+    }
+}
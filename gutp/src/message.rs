@@ -0,0 +1,197 @@
+use anyhow::{anyhow, bail};
+use eightfish_sdk::{HandlerCRUD, Info, Module, Request, Response, Result, Router, Status};
+use serde::Serialize;
+use spin_sdk::pg::{self, ParameterValue};
+use sql_builder::SqlBuilder;
+
+use crate::app::GutpAppModule;
+use crate::constants::{DB_URL_ENV, MESSAGE_MAX_LEN};
+use crate::conversation::GutpConversationModule;
+use crate::envelope::Envelope;
+use crate::user::GutpUserModule;
+use crate::utils;
+use gutp_types::{GutpConversation, GutpMessage};
+
+/// Extra info of a cursor paged message list.
+#[derive(Serialize)]
+struct CursorPageExtra {
+    // pass it as `cursor` to get the next, older page, none on the last page
+    next_cursor: Option<String>,
+}
+
+pub struct GutpMessageModule;
+
+impl GutpMessageModule {
+    /// Messages of a conversation of the caller, the latest first, paged by
+    /// `cursor`.
+    fn list_by_conversation(req: &mut Request) -> Result<Response> {
+        GutpAppModule::check_feature(req, "messages")?;
+
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let conversation_id = params
+            .get("conversation_id")
+            .ok_or(anyhow!("conversation_id is required"))?;
+        let cursor = utils::get_cursor_param(&params)?;
+        let (limit, _) = utils::build_page_info(&params)?;
+
+        let app_id = utils::get_app_id(req)?;
+        let user_id = utils::get_caller_id(req)?;
+        _ = GutpConversationModule::get_own_conversation(
+            &pg_conn,
+            &app_id,
+            conversation_id,
+            &user_id,
+        )?;
+
+        let mut builder = SqlBuilder::select_from(&GutpMessage::model_name());
+        builder
+            .fields(&GutpMessage::fields())
+            .and_where_eq("conversation_id", "$1");
+        let mut sql_params = vec![ParameterValue::Str(conversation_id.clone())];
+        if let Some((time, id)) = cursor {
+            sql_params.push(ParameterValue::Int64(time));
+            sql_params.push(ParameterValue::Str(id));
+            builder.and_where("(created_time, id) < ($2, $3)");
+        }
+        let sql = builder
+            .order_desc("created_time")
+            .order_desc("id")
+            .limit(limit)
+            .sql()?;
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+
+        let mut results: Vec<GutpMessage> = vec![];
+        for row in rowset.rows {
+            let sp = GutpMessage::from_row(row);
+            results.push(sp);
+        }
+
+        let next_cursor = match results.last() {
+            Some(message) if results.len() as u64 == limit => {
+                Some(utils::build_cursor(message.created_time, &message.id))
+            }
+            _ => None,
+        };
+
+        let info = Info {
+            model_name: GutpMessage::model_name(),
+            action: HandlerCRUD::List,
+            extra: serde_json::to_string(&CursorPageExtra { next_cursor })?,
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
+    /// Send a direct message from the caller to `recipient_id`. `content` is
+    /// an encryption envelope only the two users can open. The conversation
+    /// is created by the first message, and the conversation is carried back
+    /// in extra.
+    fn send(req: &mut Request) -> Result<Response> {
+        GutpAppModule::check_feature(req, "messages")?;
+
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let recipient_id = params
+            .get("recipient_id")
+            .ok_or(anyhow!("recipient_id is required"))?
+            .to_owned();
+        let content = params
+            .get("content")
+            .ok_or(anyhow!("content is required"))?;
+        if content.len() > MESSAGE_MAX_LEN {
+            bail!("content must be at most {MESSAGE_MAX_LEN} bytes");
+        }
+        let content = Envelope::check_own(content)?;
+
+        let app_id = utils::get_app_id(req)?;
+        let sender_id = utils::get_caller_id(req)?;
+        if sender_id == recipient_id {
+            bail!("can not send a message to yourself");
+        }
+        let id = req
+            .ext()
+            .get("random_str")
+            .ok_or(anyhow!("generate id failed"))?
+            .to_owned();
+        let time = req
+            .ext()
+            .get("time")
+            .ok_or(anyhow!("generate time failed"))?
+            .parse::<i64>()?;
+
+        let (message, conversation) = utils::with_transaction(&pg_conn, |conn| {
+            let conversation = match GutpConversationModule::find_between(
+                conn,
+                &app_id,
+                &sender_id,
+                &recipient_id,
+            )? {
+                Some(conversation) => {
+                    let conversation = GutpConversation {
+                        last_message_time: time,
+                        ..conversation
+                    };
+                    let (sql, sql_params) = conversation.build_update();
+                    _ = conn.execute(&sql, &sql_params)?;
+                    conversation
+                }
+                None => {
+                    _ = GutpUserModule::get_user_by_id(conn, &app_id, &recipient_id)?;
+                    GutpConversationModule::create_between(
+                        conn,
+                        utils::derive_id(&id, "conversation", 0),
+                        &app_id,
+                        &sender_id,
+                        &recipient_id,
+                        time,
+                    )?
+                }
+            };
+
+            let message = GutpMessage {
+                id,
+                conversation_id: conversation.id.clone(),
+                sender_id: sender_id.clone(),
+                content,
+                created_time: time,
+            };
+            let (sql, sql_params) = message.build_insert();
+            _ = conn.execute(&sql, &sql_params)?;
+
+            // the sender has read the conversation up to its own message
+            let conversation =
+                GutpConversationModule::set_read_time(conn, conversation, &sender_id, time)?;
+
+            Ok((message, conversation))
+        })?;
+
+        let results: Vec<GutpMessage> = vec![message];
+
+        let info = Info {
+            model_name: GutpMessage::model_name(),
+            action: HandlerCRUD::Create,
+            extra: serde_json::to_string(&conversation)?,
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+}
+
+impl Module for GutpMessageModule {
+    fn router(&self, router: &mut Router) -> Result<()> {
+        router.get(
+            "/gutp/v1/message/list_by_conversation",
+            Self::list_by_conversation,
+        );
+        router.post("/gutp/v1/message/send", Self::send);
+
+        Ok(())
+    }
+}
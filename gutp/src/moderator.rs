@@ -1,29 +1,93 @@
 use crate::constants::DB_URL_ENV;
+use crate::modlog::{GutpModLogModule, ModLogActor};
+use crate::subspace::GutpSubspaceModule;
+use crate::tag::GutpTagModule;
+use crate::user::GutpUserModule;
 use crate::utils;
 use anyhow::{anyhow, bail};
 use eightfish_sdk::{HandlerCRUD, Info, Module, Request, Response, Result, Router, Status};
 use spin_sdk::pg::{self, ParameterValue};
 use sql_builder::SqlBuilder;
 
-use gutp_types::GutpModerator;
+use gutp_types::{GutpModLog, GutpModerator};
 
 pub struct GutpModeratorModule;
 
 impl GutpModeratorModule {
+    /// Only the owner and the subspace-scoped moderators of a subspace may
+    /// moderate its content.
+    pub(crate) fn check_subspace_moderator(
+        pg_conn: &pg::Connection,
+        app_id: &str,
+        subspace_id: &str,
+        user_id: &str,
+    ) -> Result<()> {
+        let subspace = GutpSubspaceModule::get_subspace_by_id(pg_conn, app_id, subspace_id)?;
+        if subspace.owner_id == user_id {
+            return Ok(());
+        }
+
+        let sql = SqlBuilder::select_from(&GutpModerator::model_name())
+            .fields(&GutpModerator::fields())
+            .and_where_eq("subspace_id", "$1")
+            .and_where_eq("user_id", "$2")
+            .and_where_eq("is_subspace_moderator", "true")
+            .sql()?;
+        let sql_params = [
+            ParameterValue::Str(subspace_id.to_owned()),
+            ParameterValue::Str(user_id.to_owned()),
+        ];
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+        if rowset.rows.is_empty() {
+            bail!("only moderators of this subspace can do this");
+        }
+
+        Ok(())
+    }
+
+    fn get_moderator_by_id(
+        pg_conn: &pg::Connection,
+        app_id: &str,
+        id: &str,
+    ) -> Result<GutpModerator> {
+        let (sql, sql_params) = GutpModerator::build_get_by_id(id);
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+        match rowset.rows.into_iter().next().map(GutpModerator::from_row) {
+            Some(moderator) if moderator.app_id == app_id => Ok(moderator),
+            _ => bail!("no such moderator: {id}"),
+        }
+    }
+
+    /// The user, subspace and tag of a moderator must all be of its app.
+    fn check_app_refs(
+        pg_conn: &pg::Connection,
+        app_id: &str,
+        user_id: &str,
+        subspace_id: &str,
+        tag_id: &str,
+    ) -> Result<()> {
+        _ = GutpUserModule::get_user_by_id(pg_conn, app_id, user_id)?;
+        _ = GutpSubspaceModule::get_subspace_by_id(pg_conn, app_id, subspace_id)?;
+        if !tag_id.is_empty() {
+            _ = GutpTagModule::get_tag_by_id(pg_conn, app_id, tag_id)?;
+        }
+        Ok(())
+    }
+
     fn get_one(req: &mut Request) -> Result<Response> {
         let pg_addr = std::env::var(DB_URL_ENV)?;
         let pg_conn = pg::Connection::open(&pg_addr)?;
 
         let params = req.parse_urlencoded()?;
         let moderator_id = params.get("id").ok_or(anyhow!("id is required"))?;
+        let app_id = utils::get_app_id(req)?;
 
         let (sql, sql_params) = GutpModerator::build_get_by_id(moderator_id);
         let rowset = pg_conn.query(&sql, &sql_params)?;
 
-        let results = if let Some(row) = rowset.rows.into_iter().next() {
-            vec![GutpModerator::from_row(row)]
-        } else {
-            bail!("no this item".to_string());
+        let results = match rowset.rows.into_iter().next().map(GutpModerator::from_row) {
+            Some(moderator) if moderator.app_id == app_id => vec![moderator],
+            _ => bail!("no this item".to_string()),
         };
 
         let info = Info {
@@ -42,14 +106,17 @@ impl GutpModeratorModule {
         let params = req.parse_urlencoded()?;
 
         let (limit, offset) = utils::build_page_info(&params)?;
+        let app_id = utils::get_app_id(req)?;
 
         let sql = SqlBuilder::select_from(&GutpModerator::model_name())
             .fields(&GutpModerator::fields())
+            .and_where_eq("app_id", "$1")
             .order_desc("created_time")
             .limit(limit)
             .offset(offset)
             .sql()?;
-        let rowset = pg_conn.query(&sql, &[])?;
+        let sql_param = ParameterValue::Str(app_id);
+        let rowset = pg_conn.query(&sql, &[sql_param])?;
 
         let mut results: Vec<GutpModerator> = vec![];
         for row in rowset.rows {
@@ -76,16 +143,21 @@ impl GutpModeratorModule {
             .get("subspace_id")
             .ok_or(anyhow!("subspace_id is required"))?;
         let (limit, offset) = utils::build_page_info(&params)?;
+        let app_id = utils::get_app_id(req)?;
 
         let sql = SqlBuilder::select_from(&GutpModerator::model_name())
             .fields(&GutpModerator::fields())
             .and_where_eq("subspace_id", "$1")
+            .and_where_eq("app_id", "$2")
             .order_desc("created_time")
             .limit(limit)
             .offset(offset)
             .sql()?;
-        let sql_param = ParameterValue::Str(subspace_id.clone());
-        let rowset = pg_conn.query(&sql, &[sql_param])?;
+        let sql_params = [
+            ParameterValue::Str(subspace_id.clone()),
+            ParameterValue::Str(app_id),
+        ];
+        let rowset = pg_conn.query(&sql, &sql_params)?;
 
         let mut results: Vec<GutpModerator> = vec![];
         for row in rowset.rows {
@@ -112,16 +184,21 @@ impl GutpModeratorModule {
             .get("user_id")
             .ok_or(anyhow!("user_id is required"))?;
         let (limit, offset) = utils::build_page_info(&params)?;
+        let app_id = utils::get_app_id(req)?;
 
         let sql = SqlBuilder::select_from(&GutpModerator::model_name())
             .fields(&GutpModerator::fields())
             .and_where_eq("user_id", "$1")
+            .and_where_eq("app_id", "$2")
             .order_desc("created_time")
             .limit(limit)
             .offset(offset)
             .sql()?;
-        let sql_param = ParameterValue::Str(user_id.clone());
-        let rowset = pg_conn.query(&sql, &[sql_param])?;
+        let sql_params = [
+            ParameterValue::Str(user_id.clone()),
+            ParameterValue::Str(app_id),
+        ];
+        let rowset = pg_conn.query(&sql, &sql_params)?;
 
         let mut results: Vec<GutpModerator> = vec![];
         for row in rowset.rows {
@@ -146,16 +223,21 @@ impl GutpModeratorModule {
 
         let tag_id = params.get("tag_id").ok_or(anyhow!("tag_id is required"))?;
         let (limit, offset) = utils::build_page_info(&params)?;
+        let app_id = utils::get_app_id(req)?;
 
         let sql = SqlBuilder::select_from(&GutpModerator::model_name())
             .fields(&GutpModerator::fields())
             .and_where_eq("tag_id", "$1")
+            .and_where_eq("app_id", "$2")
             .order_desc("created_time")
             .limit(limit)
             .offset(offset)
             .sql()?;
-        let sql_param = ParameterValue::Str(tag_id.clone());
-        let rowset = pg_conn.query(&sql, &[sql_param])?;
+        let sql_params = [
+            ParameterValue::Str(tag_id.clone()),
+            ParameterValue::Str(app_id),
+        ];
+        let rowset = pg_conn.query(&sql, &sql_params)?;
 
         let mut results: Vec<GutpModerator> = vec![];
         for row in rowset.rows {
@@ -172,6 +254,44 @@ impl GutpModeratorModule {
         Ok(Response::new(Status::Successful, info, results))
     }
 
+    /// Moderators of a subspace are appointed by its owner or an app admin.
+    fn check_appointer(
+        pg_conn: &pg::Connection,
+        app_id: &str,
+        subspace_id: &str,
+        caller_id: &str,
+    ) -> Result<()> {
+        let subspace = GutpSubspaceModule::get_subspace_by_id(pg_conn, app_id, subspace_id)?;
+        if subspace.owner_id == caller_id {
+            return Ok(());
+        }
+        GutpUserModule::check_admin(pg_conn, app_id, caller_id)
+    }
+
+    /// Log a change of a moderator, with the record before and after it as
+    /// json, empty when there is none.
+    fn record_change(
+        pg_conn: &pg::Connection,
+        actor: &ModLogActor,
+        entry: GutpModLog,
+        before: Option<&GutpModerator>,
+        after: Option<&GutpModerator>,
+    ) -> Result<()> {
+        let to_value = |moderator: Option<&GutpModerator>| -> Result<String> {
+            match moderator {
+                Some(moderator) => Ok(serde_json::to_string(moderator)?),
+                None => Ok("".to_string()),
+            }
+        };
+        let entry = GutpModLog {
+            before_value: to_value(before)?,
+            after_value: to_value(after)?,
+            ..entry
+        };
+        GutpModLogModule::record(pg_conn, actor, entry)
+    }
+
+    /// Appoint a moderator, by the owner of the subspace or an app admin.
     fn new_one(req: &mut Request) -> Result<Response> {
         let pg_addr = std::env::var(DB_URL_ENV)?;
         let pg_conn = pg::Connection::open(&pg_addr)?;
@@ -209,19 +329,38 @@ impl GutpModeratorModule {
             .get("time")
             .ok_or(anyhow!("generate time failed"))?
             .parse::<i64>()?;
-
-        let moderator = GutpModerator {
-            id,
-            user_id,
-            is_subspace_moderator,
-            subspace_id,
-            tag_id,
-            permission_level,
-            created_time: time,
-        };
-
-        let (sql, sql_params) = moderator.build_insert();
-        _ = pg_conn.execute(&sql, &sql_params)?;
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_caller_id(req)?;
+        let actor = GutpModLogModule::get_actor(req, &params)?;
+
+        let moderator = utils::with_transaction(&pg_conn, |conn| {
+            Self::check_app_refs(conn, &app_id, &user_id, &subspace_id, &tag_id)?;
+            Self::check_appointer(conn, &app_id, &subspace_id, &caller_id)?;
+
+            let moderator = GutpModerator {
+                id: id.clone(),
+                user_id: user_id.clone(),
+                is_subspace_moderator,
+                subspace_id: subspace_id.clone(),
+                tag_id: tag_id.clone(),
+                permission_level,
+                app_id: app_id.clone(),
+                created_time: time,
+            };
+            let (sql, sql_params) = moderator.build_insert();
+            _ = conn.execute(&sql, &sql_params)?;
+
+            let entry = GutpModLog {
+                subspace_id: subspace_id.clone(),
+                target_model: GutpModerator::model_name(),
+                target_id: moderator.id.clone(),
+                action: "add_moderator".to_string(),
+                ..Default::default()
+            };
+            Self::record_change(conn, &actor, entry, None, Some(&moderator))?;
+
+            Ok(moderator)
+        })?;
 
         let results: Vec<GutpModerator> = vec![moderator];
 
@@ -234,6 +373,8 @@ impl GutpModeratorModule {
         Ok(Response::new(Status::Successful, info, results))
     }
 
+    /// Change a moderator, by whoever may appoint moderators in the subspace
+    /// it is in and, if it moves, in the new one.
     fn update(req: &mut Request) -> Result<Response> {
         let pg_addr = std::env::var(DB_URL_ENV)?;
         let pg_conn = pg::Connection::open(&pg_addr)?;
@@ -261,47 +402,54 @@ impl GutpModeratorModule {
             .get("permission_level")
             .ok_or(anyhow!("permission_level is required"))?
             .parse::<i16>()?;
-        // let time = req
-        //     .ext()
-        //     .get("time")
-        //     .ok_or(anyhow!("time is required"))?
-        //     .parse::<i64>()?;
 
-        // get the item from db, check whether obj in db
-        let (sql, sql_params) = GutpModerator::build_get_by_id(id);
-        let rowset = pg_conn.query(&sql, &sql_params)?;
-        match rowset.rows.into_iter().next() {
-            Some(row) => {
-                let old_moderator = GutpModerator::from_row(row);
-
-                let moderator = GutpModerator {
-                    user_id,
-                    is_subspace_moderator,
-                    subspace_id,
-                    tag_id,
-                    permission_level,
-                    ..old_moderator
-                };
-
-                let (sql, sql_params) = moderator.build_update();
-                _ = pg_conn.execute(&sql, &sql_params)?;
-
-                let results: Vec<GutpModerator> = vec![moderator];
-
-                let info = Info {
-                    model_name: GutpModerator::model_name(),
-                    action: HandlerCRUD::Update,
-                    extra: "".to_string(),
-                };
-
-                Ok(Response::new(Status::Successful, info, results))
-            }
-            None => {
-                bail!("update action: no item in db");
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_caller_id(req)?;
+        let actor = GutpModLogModule::get_actor(req, &params)?;
+
+        let moderator = utils::with_transaction(&pg_conn, |conn| {
+            Self::check_app_refs(conn, &app_id, &user_id, &subspace_id, &tag_id)?;
+            let old_moderator = Self::get_moderator_by_id(conn, &app_id, id)?;
+            Self::check_appointer(conn, &app_id, &old_moderator.subspace_id, &caller_id)?;
+            if subspace_id != old_moderator.subspace_id {
+                Self::check_appointer(conn, &app_id, &subspace_id, &caller_id)?;
             }
-        }
+
+            let moderator = GutpModerator {
+                user_id: user_id.clone(),
+                is_subspace_moderator,
+                subspace_id: subspace_id.clone(),
+                tag_id: tag_id.clone(),
+                permission_level,
+                ..old_moderator.clone()
+            };
+            let (sql, sql_params) = moderator.build_update();
+            _ = conn.execute(&sql, &sql_params)?;
+
+            let entry = GutpModLog {
+                subspace_id: old_moderator.subspace_id.clone(),
+                target_model: GutpModerator::model_name(),
+                target_id: moderator.id.clone(),
+                action: "update_moderator".to_string(),
+                ..Default::default()
+            };
+            Self::record_change(conn, &actor, entry, Some(&old_moderator), Some(&moderator))?;
+
+            Ok(moderator)
+        })?;
+
+        let results: Vec<GutpModerator> = vec![moderator];
+
+        let info = Info {
+            model_name: GutpModerator::model_name(),
+            action: HandlerCRUD::Update,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
     }
 
+    /// Remove a moderator, by the owner of the subspace or an app admin.
     fn delete(req: &mut Request) -> Result<Response> {
         let pg_addr = std::env::var(DB_URL_ENV)?;
         let pg_conn = pg::Connection::open(&pg_addr)?;
@@ -310,8 +458,26 @@ impl GutpModeratorModule {
 
         let id = params.get("id").ok_or(anyhow!("id is required"))?;
 
-        let (sql, sql_params) = GutpModerator::build_delete(id);
-        _ = pg_conn.execute(&sql, &sql_params)?;
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_caller_id(req)?;
+        let actor = GutpModLogModule::get_actor(req, &params)?;
+
+        utils::with_transaction(&pg_conn, |conn| {
+            let moderator = Self::get_moderator_by_id(conn, &app_id, id)?;
+            Self::check_appointer(conn, &app_id, &moderator.subspace_id, &caller_id)?;
+
+            let (sql, sql_params) = GutpModerator::build_delete(id);
+            _ = conn.execute(&sql, &sql_params)?;
+
+            let entry = GutpModLog {
+                subspace_id: moderator.subspace_id.clone(),
+                target_model: GutpModerator::model_name(),
+                target_id: moderator.id.clone(),
+                action: "remove_moderator".to_string(),
+                ..Default::default()
+            };
+            Self::record_change(conn, &actor, entry, Some(&moderator), None)
+        })?;
 
         let info = Info {
             model_name: GutpModerator::model_name(),
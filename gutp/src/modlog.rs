@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+use eightfish_sdk::{HandlerCRUD, Info, Module, Request, Response, Result, Router, Status};
+use spin_sdk::pg::{self, ParameterValue};
+use sql_builder::SqlBuilder;
+
+use crate::constants::DB_URL_ENV;
+use crate::member::GutpMemberModule;
+use crate::utils;
+use gutp_types::GutpModLog;
+
+/// Who takes a moderation action, when and why, as given by the request.
+pub(crate) struct ModLogActor {
+    id: String,
+    app_id: String,
+    actor_id: String,
+    reason: String,
+    time: i64,
+}
+
+pub struct GutpModLogModule;
+
+impl GutpModLogModule {
+    /// The caller of a request as the actor of the log entry it writes, with
+    /// the optional `reason` param.
+    pub(crate) fn get_actor(
+        req: &Request,
+        params: &HashMap<String, String>,
+    ) -> Result<ModLogActor> {
+        let random_str = req
+            .ext()
+            .get("random_str")
+            .ok_or(anyhow!("random_str is required"))?;
+        let time = req
+            .ext()
+            .get("time")
+            .ok_or(anyhow!("time is required"))?
+            .parse::<i64>()?;
+
+        Ok(ModLogActor {
+            id: utils::derive_id(random_str, "modlog", 0),
+            app_id: utils::get_app_id(req)?,
+            actor_id: utils::get_caller_id(req)?,
+            reason: params.get("reason").unwrap_or(&"".to_string()).to_owned(),
+            time,
+        })
+    }
+
+    /// Append an entry to the moderation log, in the same transaction as the
+    /// moderation action itself. `entry` tells what was done to which target,
+    /// the rest comes from the actor. Acting on someone else's content or
+    /// account is moderation, removing one's own is not logged.
+    pub(crate) fn record(
+        pg_conn: &pg::Connection,
+        actor: &ModLogActor,
+        entry: GutpModLog,
+    ) -> Result<()> {
+        let modlog = GutpModLog {
+            id: actor.id.clone(),
+            app_id: actor.app_id.clone(),
+            actor_id: actor.actor_id.clone(),
+            reason: actor.reason.clone(),
+            created_time: actor.time,
+            ..entry
+        };
+        let (sql, sql_params) = modlog.build_insert();
+        _ = pg_conn.execute(&sql, &sql_params)?;
+        Ok(())
+    }
+
+    fn list_by_target(req: &mut Request) -> Result<Response> {
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let target_model = params
+            .get("target_model")
+            .ok_or(anyhow!("target_model is required"))?;
+        let target_id = params
+            .get("target_id")
+            .ok_or(anyhow!("target_id is required"))?;
+        let (limit, offset) = utils::build_page_info(&params)?;
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_optional_caller_id(req);
+
+        // App wide entries have no subspace, the others follow its privacy.
+        let sql = SqlBuilder::select_from(&GutpModLog::model_name())
+            .fields(&GutpModLog::fields())
+            .and_where_eq("target_model", "$1")
+            .and_where_eq("target_id", "$2")
+            .and_where_eq("app_id", "$3")
+            .and_where(format!(
+                "(subspace_id = '' OR {})",
+                GutpMemberModule::build_readable_cond("subspace_id", "$4")
+            ))
+            .order_desc("created_time")
+            .limit(limit)
+            .offset(offset)
+            .sql()?;
+        let sql_params = [
+            ParameterValue::Str(target_model.clone()),
+            ParameterValue::Str(target_id.clone()),
+            ParameterValue::Str(app_id),
+            ParameterValue::Str(caller_id.unwrap_or_default()),
+        ];
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+
+        let mut results: Vec<GutpModLog> = vec![];
+        for row in rowset.rows {
+            let sp = GutpModLog::from_row(row);
+            results.push(sp);
+        }
+
+        let info = Info {
+            model_name: GutpModLog::model_name(),
+            action: HandlerCRUD::List,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
+    /// The moderation log of a subspace, newest first, readable by whoever
+    /// can read the subspace. Without `subspace_id` it is the log of the app
+    /// wide actions, on users and subspaces.
+    fn list_by_subspace(req: &mut Request) -> Result<Response> {
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let subspace_id = params
+            .get("subspace_id")
+            .unwrap_or(&"".to_string())
+            .to_owned();
+        let (limit, offset) = utils::build_page_info(&params)?;
+        let app_id = utils::get_app_id(req)?;
+        if !subspace_id.is_empty() {
+            let caller_id = utils::get_optional_caller_id(req);
+            GutpMemberModule::check_readable(
+                &pg_conn,
+                &app_id,
+                &subspace_id,
+                caller_id.as_deref(),
+            )?;
+        }
+
+        let sql = SqlBuilder::select_from(&GutpModLog::model_name())
+            .fields(&GutpModLog::fields())
+            .and_where_eq("app_id", "$1")
+            .and_where_eq("subspace_id", "$2")
+            .order_desc("created_time")
+            .limit(limit)
+            .offset(offset)
+            .sql()?;
+        let sql_params = [
+            ParameterValue::Str(app_id),
+            ParameterValue::Str(subspace_id),
+        ];
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+
+        let mut results: Vec<GutpModLog> = vec![];
+        for row in rowset.rows {
+            let sp = GutpModLog::from_row(row);
+            results.push(sp);
+        }
+
+        let info = Info {
+            model_name: GutpModLog::model_name(),
+            action: HandlerCRUD::List,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+}
+
+impl Module for GutpModLogModule {
+    fn router(&self, router: &mut Router) -> Result<()> {
+        router.get("/gutp/v1/modlog/list_by_target", Self::list_by_target);
+        router.get("/gutp/v1/modlog/list_by_subspace", Self::list_by_subspace);
+
+        Ok(())
+    }
+}
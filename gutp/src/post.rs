@@ -1,13 +1,28 @@
+use std::collections::BTreeMap;
+
+use crate::app::GutpAppModule;
+use crate::bookmark::GutpBookmarkModule;
+use crate::category::GutpCategoryModule;
 use crate::constants::DB_URL_ENV;
+use crate::envelope::Envelope;
+use crate::member::GutpMemberModule;
+use crate::moderator::GutpModeratorModule;
+use crate::modlog::GutpModLogModule;
+use crate::posttag::GutpPostTagModule;
+use crate::reaction::{GutpReactionModule, ReactionSummary};
+use crate::subspace::GutpSubspaceModule;
+use crate::tag::GutpTagModule;
+use crate::user::GutpUserModule;
 use crate::utils;
 use anyhow::{anyhow, bail};
 use eightfish_sdk::{HandlerCRUD, Info, Module, Request, Response, Result, Router, Status};
+use serde::Serialize;
 use spin_sdk::pg::{self, ParameterValue};
 use sql_builder::SqlBuilder;
 
-use gutp_types::GutpPost;
+use gutp_types::{GutpModLog, GutpPost, GutpSubspace, GutpUser};
 
-enum GutpPostStatus {
+pub(crate) enum GutpPostStatus {
     Normal = 0,
     Frozen = 1,
     Forbidden = 2,
@@ -24,29 +39,193 @@ enum GutpPostWeight {
     SuperHigh = 3,
 }
 
+// ranking score of a post: its own weight, plus the weight of its subspace,
+// plus the scores of the reactions on it
+// stored in the score column whenever one of its parts changes, the hot_rank
+// column is generated from it, so the feed sorts on indexed columns
+const POST_SCORE_SQL: &str = "(weight + COALESCE((SELECT weight FROM gutpsubspace WHERE gutpsubspace.id = gutppost.subspace_id), 0) + COALESCE((SELECT SUM(score) FROM gutpreaction WHERE gutpreaction.target_model = 'gutppost' AND gutpreaction.target_id = gutppost.id), 0))";
+
+/// What a post looks like to the caller, returned in extra along with it.
+#[derive(Serialize, Default)]
+struct PostExtra {
+    reactions: ReactionSummary,
+    bookmarked: bool,
+}
+
+/// Extra info of a cursor paged post list.
+#[derive(Serialize)]
+struct CursorPageExtra {
+    posts: BTreeMap<String, PostExtra>,
+    // pass it as `cursor` to get the next page, none on the last page
+    next_cursor: Option<String>,
+}
+
 pub struct GutpPostModule;
 
 impl GutpPostModule {
+    /// Build the extra info of a list of posts, as a json object keyed by post id.
+    fn build_extra(
+        pg_conn: &pg::Connection,
+        posts: &[GutpPost],
+        caller_id: Option<&str>,
+    ) -> Result<String> {
+        let extras = Self::get_extras(pg_conn, posts, caller_id)?;
+        Ok(serde_json::to_string(&extras)?)
+    }
+
+    fn get_extras(
+        pg_conn: &pg::Connection,
+        posts: &[GutpPost],
+        caller_id: Option<&str>,
+    ) -> Result<BTreeMap<String, PostExtra>> {
+        let post_ids: Vec<String> = posts.iter().map(|post| post.id.clone()).collect();
+        let mut reactions = GutpReactionModule::get_summaries(
+            pg_conn,
+            &GutpPost::model_name(),
+            &post_ids,
+            caller_id,
+        )?;
+        let bookmarked = match caller_id {
+            Some(caller_id) => GutpBookmarkModule::get_bookmarked(pg_conn, caller_id, &post_ids)?,
+            None => vec![],
+        };
+
+        let mut extras: BTreeMap<String, PostExtra> = BTreeMap::new();
+        for post_id in post_ids {
+            let extra = PostExtra {
+                reactions: reactions.remove(&post_id).unwrap_or_default(),
+                bookmarked: bookmarked.contains(&post_id),
+            };
+            extras.insert(post_id, extra);
+        }
+
+        Ok(extras)
+    }
+
+    /// Text fields of a non-public post must be encryption envelopes, and a
+    /// post in a private subspace can not be public.
+    fn check_privacy(
+        pg_conn: &pg::Connection,
+        app_id: &str,
+        subspace_id: &str,
+        is_public: bool,
+        title: String,
+        content: String,
+    ) -> Result<(String, String)> {
+        let subspace = GutpSubspaceModule::get_subspace_by_id(pg_conn, app_id, subspace_id)?;
+        if is_public {
+            if !subspace.is_public {
+                bail!("posts in a private subspace must not be public");
+            }
+            return Ok((title, content));
+        }
+        Ok((
+            Envelope::check(&title, subspace.key_version)?,
+            Envelope::check(&content, subspace.key_version)?,
+        ))
+    }
+
+    /// Load a post of an app. Posts of other apps are not found.
+    pub(crate) fn get_post_by_id(
+        pg_conn: &pg::Connection,
+        app_id: &str,
+        id: &str,
+    ) -> Result<GutpPost> {
+        let (sql, sql_params) = GutpPost::build_get_by_id(id);
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+        match rowset.rows.into_iter().next().map(GutpPost::from_row) {
+            Some(post) if post.app_id == app_id => Ok(post),
+            _ => bail!("no such post: {id}"),
+        }
+    }
+
+    /// Load a post of an app and lock its row until the transaction ends, so
+    /// it can not change between a check on it and its update.
+    fn get_post_for_update(pg_conn: &pg::Connection, app_id: &str, id: &str) -> Result<GutpPost> {
+        let sql = format!(
+            "SELECT {} FROM gutppost WHERE id = $1 FOR UPDATE",
+            GutpPost::fields().join(", ")
+        );
+        let rowset = pg_conn.query(&sql, &[ParameterValue::Str(id.to_owned())])?;
+        match rowset.rows.into_iter().next().map(GutpPost::from_row) {
+            Some(post) if post.app_id == app_id => Ok(post),
+            _ => bail!("no such post: {id}"),
+        }
+    }
+
+    /// Recompute the stored score of a post, after its weight or reactions
+    /// changed.
+    pub(crate) fn refresh_score(pg_conn: &pg::Connection, id: &str) -> Result<()> {
+        let sql_param = ParameterValue::Str(id.to_owned());
+        _ = pg_conn.execute(
+            &format!("UPDATE gutppost SET score = {POST_SCORE_SQL} WHERE id = $1"),
+            &[sql_param],
+        )?;
+        Ok(())
+    }
+
+    /// Recompute the stored scores of all the posts of a subspace, after the
+    /// weight of the subspace changed.
+    pub(crate) fn refresh_subspace_scores(
+        pg_conn: &pg::Connection,
+        subspace_id: &str,
+    ) -> Result<()> {
+        let sql_param = ParameterValue::Str(subspace_id.to_owned());
+        _ = pg_conn.execute(
+            &format!("UPDATE gutppost SET score = {POST_SCORE_SQL} WHERE subspace_id = $1"),
+            &[sql_param],
+        )?;
+        Ok(())
+    }
+
+    /// Change the status of a post from moderation, returning the status
+    /// it had before.
+    pub(crate) fn set_status(
+        pg_conn: &pg::Connection,
+        app_id: &str,
+        id: &str,
+        status: i16,
+    ) -> Result<i16> {
+        if !(GutpPostStatus::Normal as i16..=GutpPostStatus::Deleted as i16).contains(&status) {
+            bail!("status is out of range");
+        }
+        let old_post = Self::get_post_by_id(pg_conn, app_id, id)?;
+        let old_status = old_post.status;
+        let post = GutpPost { status, ..old_post };
+        let (sql, sql_params) = post.build_update();
+        _ = pg_conn.execute(&sql, &sql_params)?;
+        Ok(old_status)
+    }
+
     fn get_one(req: &mut Request) -> Result<Response> {
         let pg_addr = std::env::var(DB_URL_ENV)?;
         let pg_conn = pg::Connection::open(&pg_addr)?;
 
         let params = req.parse_urlencoded()?;
         let post_id = params.get("id").ok_or(anyhow!("id is required"))?;
+        let app_id = utils::get_app_id(req)?;
 
         let (sql, sql_params) = GutpPost::build_get_by_id(post_id);
         let rowset = pg_conn.query(&sql, &sql_params)?;
 
-        let results = if let Some(row) = rowset.rows.into_iter().next() {
-            vec![GutpPost::from_row(row)]
-        } else {
-            bail!("no this item".to_string());
+        let results = match rowset.rows.into_iter().next().map(GutpPost::from_row) {
+            Some(post) if post.app_id == app_id => vec![post],
+            _ => bail!("no this item".to_string()),
         };
 
+        let caller_id = utils::get_optional_caller_id(req);
+        GutpMemberModule::check_readable(
+            &pg_conn,
+            &app_id,
+            &results[0].subspace_id,
+            caller_id.as_deref(),
+        )?;
+        let extra = Self::build_extra(&pg_conn, &results, caller_id.as_deref())?;
+
         let info = Info {
             model_name: GutpPost::model_name(),
             action: HandlerCRUD::GetOne,
-            extra: "".to_string(),
+            extra,
         };
 
         Ok(Response::new(Status::Successful, info, results))
@@ -59,14 +238,22 @@ impl GutpPostModule {
         let params = req.parse_urlencoded()?;
 
         let (limit, offset) = utils::build_page_info(&params)?;
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_optional_caller_id(req);
 
         let sql = SqlBuilder::select_from(&GutpPost::model_name())
             .fields(&GutpPost::fields())
+            .and_where_eq("app_id", "$2")
+            .and_where(GutpMemberModule::build_readable_cond("subspace_id", "$1"))
             .order_desc("created_time")
             .limit(limit)
             .offset(offset)
             .sql()?;
-        let rowset = pg_conn.query(&sql, &[])?;
+        let sql_params = [
+            ParameterValue::Str(caller_id.clone().unwrap_or_default()),
+            ParameterValue::Str(app_id),
+        ];
+        let rowset = pg_conn.query(&sql, &sql_params)?;
 
         let mut results: Vec<GutpPost> = vec![];
         for row in rowset.rows {
@@ -74,10 +261,150 @@ impl GutpPostModule {
             results.push(sp);
         }
 
+        let extra = Self::build_extra(&pg_conn, &results, caller_id.as_deref())?;
+
         let info = Info {
             model_name: GutpPost::model_name(),
             action: HandlerCRUD::List,
-            extra: "".to_string(),
+            extra,
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
+    /// Ranked post feed of the app, or of one subspace by `subspace_id`.
+    /// `sort` is one of:
+    /// - new: the latest first
+    /// - top: the highest score first
+    /// - hot: score decayed by age
+    ///
+    /// With `pinned_first` and `subspace_id`, the pinned posts of the subspace
+    /// go before all others, the latest pinned first.
+    fn feed(req: &mut Request) -> Result<Response> {
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let sort = params.get("sort").map(|s| s.as_str()).unwrap_or("new");
+        let pinned_first = params
+            .get("pinned_first")
+            .unwrap_or(&"false".to_string())
+            .parse::<bool>()?;
+        let (limit, offset) = utils::build_page_info(&params)?;
+
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_optional_caller_id(req);
+
+        let mut builder = SqlBuilder::select_from(&GutpPost::model_name());
+        builder
+            .fields(&GutpPost::fields())
+            .and_where_eq("status", GutpPostStatus::Normal as i16)
+            .and_where_eq("app_id", "$2")
+            .and_where(GutpMemberModule::build_readable_cond("subspace_id", "$1"));
+
+        let mut sql_params = vec![
+            ParameterValue::Str(caller_id.clone().unwrap_or_default()),
+            ParameterValue::Str(app_id),
+        ];
+        if let Some(subspace_id) = params.get("subspace_id") {
+            sql_params.push(ParameterValue::Str(subspace_id.clone()));
+            builder.and_where_eq("subspace_id", "$3");
+            // pins are per subspace, they do not rank a post over the whole app
+            if pinned_first {
+                builder.order_desc("pinned_time");
+            }
+        }
+        match sort {
+            "new" => builder.order_desc("created_time"),
+            "top" => builder.order_desc("score").order_desc("created_time"),
+            "hot" => builder.order_desc("hot_rank"),
+            _ => bail!("sort must be one of new, top, hot"),
+        };
+
+        let sql = builder.limit(limit).offset(offset).sql()?;
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+
+        let mut results: Vec<GutpPost> = vec![];
+        for row in rowset.rows {
+            let sp = GutpPost::from_row(row);
+            results.push(sp);
+        }
+
+        let extra = Self::build_extra(&pg_conn, &results, caller_id.as_deref())?;
+
+        let info = Info {
+            model_name: GutpPost::model_name(),
+            action: HandlerCRUD::List,
+            extra,
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
+    /// Posts by the users and in the subspaces the caller follows, the latest
+    /// first. Paged by `cursor`, so new posts do not shift the pages.
+    fn feed_following(req: &mut Request) -> Result<Response> {
+        GutpAppModule::check_feature(req, "follows")?;
+
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_caller_id(req)?;
+        let cursor = utils::get_cursor_param(&params)?;
+        let (limit, _) = utils::build_page_info(&params)?;
+
+        let mut builder = SqlBuilder::select_from(&GutpPost::model_name());
+        builder
+            .fields(&GutpPost::fields())
+            .and_where_eq("status", GutpPostStatus::Normal as i16)
+            .and_where_eq("app_id", "$2")
+            .and_where(format!(
+                "(author_id IN (SELECT target_id FROM gutpfollow WHERE follower_id = $1 AND target_model = '{}') OR subspace_id IN (SELECT target_id FROM gutpfollow WHERE follower_id = $1 AND target_model = '{}'))",
+                GutpUser::model_name(),
+                GutpSubspace::model_name()
+            ))
+            .and_where(GutpMemberModule::build_readable_cond("subspace_id", "$1"));
+        let mut sql_params = vec![
+            ParameterValue::Str(caller_id.clone()),
+            ParameterValue::Str(app_id),
+        ];
+        if let Some((time, id)) = cursor {
+            sql_params.push(ParameterValue::Int64(time));
+            sql_params.push(ParameterValue::Str(id));
+            builder.and_where("(created_time, id) < ($3, $4)");
+        }
+        let sql = builder
+            .order_desc("created_time")
+            .order_desc("id")
+            .limit(limit)
+            .sql()?;
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+
+        let mut results: Vec<GutpPost> = vec![];
+        for row in rowset.rows {
+            let sp = GutpPost::from_row(row);
+            results.push(sp);
+        }
+
+        let next_cursor = match results.last() {
+            Some(post) if results.len() as u64 == limit => {
+                Some(utils::build_cursor(post.created_time, &post.id))
+            }
+            _ => None,
+        };
+        let extra = CursorPageExtra {
+            posts: Self::get_extras(&pg_conn, &results, Some(&caller_id))?,
+            next_cursor,
+        };
+
+        let info = Info {
+            model_name: GutpPost::model_name(),
+            action: HandlerCRUD::List,
+            extra: serde_json::to_string(&extra)?,
         };
 
         Ok(Response::new(Status::Successful, info, results))
@@ -94,6 +421,10 @@ impl GutpPostModule {
             .ok_or(anyhow!("subspace_id is required"))?;
         let (limit, offset) = utils::build_page_info(&params)?;
 
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_optional_caller_id(req);
+        GutpMemberModule::check_readable(&pg_conn, &app_id, subspace_id, caller_id.as_deref())?;
+
         let sql = SqlBuilder::select_from(&GutpPost::model_name())
             .fields(&GutpPost::fields())
             .and_where_eq("subspace_id", "$1")
@@ -110,10 +441,12 @@ impl GutpPostModule {
             results.push(sp);
         }
 
+        let extra = Self::build_extra(&pg_conn, &results, caller_id.as_deref())?;
+
         let info = Info {
             model_name: GutpPost::model_name(),
             action: HandlerCRUD::List,
-            extra: "".to_string(),
+            extra,
         };
 
         Ok(Response::new(Status::Successful, info, results))
@@ -129,16 +462,24 @@ impl GutpPostModule {
             .get("author_id")
             .ok_or(anyhow!("author_id is required"))?;
         let (limit, offset) = utils::build_page_info(&params)?;
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_optional_caller_id(req);
 
         let sql = SqlBuilder::select_from(&GutpPost::model_name())
             .fields(&GutpPost::fields())
             .and_where_eq("author_id", "$1")
+            .and_where_eq("app_id", "$3")
+            .and_where(GutpMemberModule::build_readable_cond("subspace_id", "$2"))
             .order_desc("created_time")
             .limit(limit)
             .offset(offset)
             .sql()?;
-        let sql_param = ParameterValue::Str(author_id.clone());
-        let rowset = pg_conn.query(&sql, &[sql_param])?;
+        let sql_params = [
+            ParameterValue::Str(author_id.clone()),
+            ParameterValue::Str(caller_id.clone().unwrap_or_default()),
+            ParameterValue::Str(app_id),
+        ];
+        let rowset = pg_conn.query(&sql, &sql_params)?;
 
         let mut results: Vec<GutpPost> = vec![];
         for row in rowset.rows {
@@ -146,10 +487,149 @@ impl GutpPostModule {
             results.push(sp);
         }
 
+        let extra = Self::build_extra(&pg_conn, &results, caller_id.as_deref())?;
+
         let info = Info {
             model_name: GutpPost::model_name(),
             action: HandlerCRUD::List,
-            extra: "".to_string(),
+            extra,
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
+    fn list_by_tag(req: &mut Request) -> Result<Response> {
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let tag_ids = utils::get_list_param(&params, "tag_ids").unwrap_or_default();
+        if tag_ids.is_empty() {
+            bail!("tag_ids is required");
+        }
+        // "or": posts with any of the tags, "and": posts with all of them
+        let op = params.get("op").map(|op| op.as_str()).unwrap_or("or");
+        // also match posts tagged with any descendant of the given tags
+        let include_descendants = params
+            .get("include_descendants")
+            .unwrap_or(&"false".to_string())
+            .parse::<bool>()?;
+        let (limit, offset) = utils::build_page_info(&params)?;
+
+        // tagtree maps every matching tag to the requested (root) tag it stands for,
+        // aliases in the request are resolved to their canonical tags
+        let placeholders = utils::build_placeholders(1, tag_ids.len());
+        let mut tag_tree = format!(
+            "WITH RECURSIVE tagtree(root_id, id) AS (SELECT COALESCE(NULLIF(alias_of, ''), id), COALESCE(NULLIF(alias_of, ''), id) FROM gutptag WHERE id IN ({placeholders})"
+        );
+        if include_descendants {
+            tag_tree.push_str(
+                " UNION SELECT tagtree.root_id, gutptag.id FROM gutptag JOIN tagtree ON gutptag.parent_tag_id = tagtree.id",
+            );
+        }
+        tag_tree.push(')');
+
+        let post_ids_query = match op {
+            "or" => format!(
+                "{tag_tree} SELECT gutpposttag.post_id FROM gutpposttag JOIN tagtree ON gutpposttag.tag_id = tagtree.id"
+            ),
+            // counted against the resolved roots, so duplicates, aliases of
+            // requested tags and unknown ids do not raise the bar
+            "and" => format!(
+                "{tag_tree} SELECT gutpposttag.post_id FROM gutpposttag JOIN tagtree ON gutpposttag.tag_id = tagtree.id GROUP BY gutpposttag.post_id HAVING COUNT(DISTINCT tagtree.root_id) = (SELECT COUNT(DISTINCT root_id) FROM tagtree)"
+            ),
+            _ => bail!("op must be and or or"),
+        };
+
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_optional_caller_id(req);
+        let caller_placeholder = format!("${}", tag_ids.len() + 1);
+
+        let sql = SqlBuilder::select_from(&GutpPost::model_name())
+            .fields(&GutpPost::fields())
+            .and_where(format!("id IN ({post_ids_query})"))
+            .and_where_eq("app_id", format!("${}", tag_ids.len() + 2))
+            .and_where(GutpMemberModule::build_readable_cond(
+                "subspace_id",
+                &caller_placeholder,
+            ))
+            .order_desc("created_time")
+            .limit(limit)
+            .offset(offset)
+            .sql()?;
+        let mut sql_params: Vec<ParameterValue> =
+            tag_ids.into_iter().map(ParameterValue::Str).collect();
+        sql_params.push(ParameterValue::Str(caller_id.clone().unwrap_or_default()));
+        sql_params.push(ParameterValue::Str(app_id));
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+
+        let mut results: Vec<GutpPost> = vec![];
+        for row in rowset.rows {
+            let sp = GutpPost::from_row(row);
+            results.push(sp);
+        }
+
+        let extra = Self::build_extra(&pg_conn, &results, caller_id.as_deref())?;
+
+        let info = Info {
+            model_name: GutpPost::model_name(),
+            action: HandlerCRUD::List,
+            extra,
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
+    /// The posts the caller bookmarked, the latest bookmarked first,
+    /// optionally in one folder.
+    fn list_bookmarked(req: &mut Request) -> Result<Response> {
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let app_id = utils::get_app_id(req)?;
+        let user_id = utils::get_caller_id(req)?;
+        let (limit, offset) = utils::build_page_info(&params)?;
+
+        let mut bookmark_conds = "gutpbookmark.user_id = $1".to_string();
+        let mut sql_params = vec![
+            ParameterValue::Str(user_id.clone()),
+            ParameterValue::Str(app_id),
+        ];
+        if let Some(folder) = params.get("folder") {
+            sql_params.push(ParameterValue::Str(folder.clone()));
+            bookmark_conds.push_str(" AND gutpbookmark.folder = $3");
+        }
+        let sql = SqlBuilder::select_from(&GutpPost::model_name())
+            .fields(&GutpPost::fields())
+            .and_where_eq("app_id", "$2")
+            .and_where(format!(
+                "id IN (SELECT post_id FROM gutpbookmark WHERE {bookmark_conds})"
+            ))
+            // bookmarks kept after leaving a private subspace are hidden
+            .and_where(GutpMemberModule::build_readable_cond("subspace_id", "$1"))
+            .order_desc(format!(
+                "(SELECT created_time FROM gutpbookmark WHERE gutpbookmark.post_id = gutppost.id AND {bookmark_conds})"
+            ))
+            .limit(limit)
+            .offset(offset)
+            .sql()?;
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+
+        let mut results: Vec<GutpPost> = vec![];
+        for row in rowset.rows {
+            let sp = GutpPost::from_row(row);
+            results.push(sp);
+        }
+
+        let extra = Self::build_extra(&pg_conn, &results, Some(&user_id))?;
+
+        let info = Info {
+            model_name: GutpPost::model_name(),
+            action: HandlerCRUD::List,
+            extra,
         };
 
         Ok(Response::new(Status::Successful, info, results))
@@ -237,14 +717,6 @@ impl GutpPostModule {
             .get("content")
             .ok_or(anyhow!("content is required"))?
             .to_owned();
-        let author_id = params
-            .get("author_id")
-            .ok_or(anyhow!("author_id is required"))?
-            .to_owned();
-        let author_nickname = params
-            .get("author_nickname")
-            .ok_or(anyhow!("author_nickname is required"))?
-            .to_owned();
         let subspace_id = params
             .get("subspace_id")
             .ok_or(anyhow!("subspace_id is required"))?
@@ -257,14 +729,25 @@ impl GutpPostModule {
             .get("category")
             .ok_or(anyhow!("category is required"))?
             .to_owned();
-        let app_id = params
-            .get("app_id")
-            .ok_or(anyhow!("app_id is required"))?
-            .to_owned();
+        let app = GutpAppModule::get_request_app(req)?;
+        GutpAppModule::check_content_len(&app, &content)?;
         let is_public = params
             .get("is_public")
             .ok_or(anyhow!("is_public is required"))?
             .parse::<bool>()?;
+        let tag_entries = utils::get_list_param(&params, "tags").unwrap_or_default();
+        let app_id = utils::get_app_id(req)?;
+        GutpCategoryModule::check_category(&pg_conn, &app_id, &category)?;
+        let create_tags = params
+            .get("create_tags")
+            .unwrap_or(&"false".to_string())
+            .parse::<bool>()?;
+
+        // author fields are derived from the caller, never trusted from the client
+        let author_id = utils::get_caller_id(req)?;
+        utils::check_param_matches(&params, "author_id", &author_id)?;
+        let author = GutpUserModule::get_user_by_id(&pg_conn, &app_id, &author_id)?;
+        let author_nickname = author.nickname;
 
         let id = req
             .ext()
@@ -277,6 +760,10 @@ impl GutpPostModule {
             .ok_or(anyhow!("generate time failed"))?
             .parse::<i64>()?;
 
+        GutpMemberModule::check_can_post(&pg_conn, &app_id, &subspace_id, &author_id)?;
+        let (title, content) =
+            Self::check_privacy(&pg_conn, &app_id, &subspace_id, is_public, title, content)?;
+
         let post = GutpPost {
             id,
             title,
@@ -291,19 +778,36 @@ impl GutpPostModule {
             is_public,
             status: GutpPostStatus::Normal as i16,
             weight: GutpPostWeight::Normal as i16,
+            pinned_time: 0,
             created_time: time,
             updated_time: time,
         };
 
-        let (sql_statement, sql_params) = post.build_insert();
-        _ = pg_conn.execute(&sql_statement, &sql_params)?;
+        let tags = utils::with_transaction(&pg_conn, |conn| {
+            let (sql_statement, sql_params) = post.build_insert();
+            _ = conn.execute(&sql_statement, &sql_params)?;
+            Self::refresh_score(conn, &post.id)?;
+
+            let tags = GutpTagModule::resolve_tags(
+                conn,
+                &post,
+                &tag_entries,
+                create_tags,
+                &post.id,
+                time,
+            )?;
+            GutpPostTagModule::set_post_tags(conn, &post.id, &tags, &post.id, time)?;
+
+            Ok(tags)
+        })?;
 
         let results: Vec<GutpPost> = vec![post];
 
+        // the tags of the post are carried back in extra
         let info = Info {
             model_name: GutpPost::model_name(),
             action: HandlerCRUD::Create,
-            extra: "".to_string(),
+            extra: serde_json::to_string(&tags)?,
         };
 
         Ok(Response::new(Status::Successful, info, results))
@@ -324,10 +828,8 @@ impl GutpPostModule {
             .get("content")
             .ok_or(anyhow!("contnet is required"))?
             .to_owned();
-        let author_id = params
-            .get("author_id")
-            .ok_or(anyhow!("author_id is required"))?
-            .to_owned();
+        let app = GutpAppModule::get_request_app(req)?;
+        GutpAppModule::check_content_len(&app, &content)?;
         let ext_link = params
             .get("ext_link")
             .ok_or(anyhow!("ext_link is required"))?
@@ -336,45 +838,219 @@ impl GutpPostModule {
             .get("is_public")
             .ok_or(anyhow!("is_public is required"))?
             .parse::<bool>()?;
+        // if category or tags is absent, that of the post is left untouched
+        let category = params.get("category").cloned();
+        let tag_entries = utils::get_list_param(&params, "tags");
+        let create_tags = params
+            .get("create_tags")
+            .unwrap_or(&"false".to_string())
+            .parse::<bool>()?;
+        let random_str = req
+            .ext()
+            .get("random_str")
+            .ok_or(anyhow!("random_str is required"))?
+            .to_owned();
         let time = req
             .ext()
             .get("time")
             .ok_or(anyhow!("time is required"))?
             .parse::<i64>()?;
-        // get the item from db, check whether obj in db
-        let (sql, sql_params) = GutpPost::build_get_by_id(id.as_str());
-        let rowset = pg_conn.query(&sql, &sql_params)?;
-        match rowset.rows.into_iter().next() {
-            Some(row) => {
-                let old_post = GutpPost::from_row(row);
-
-                let post = GutpPost {
-                    title,
-                    content,
-                    author_id,
-                    ext_link,
-                    is_public,
-                    updated_time: time,
-                    ..old_post
-                };
-
-                let (sql, sql_params) = post.build_update();
-                _ = pg_conn.execute(&sql, &sql_params)?;
-
-                let results: Vec<GutpPost> = vec![post];
-
-                let info = Info {
-                    model_name: GutpPost::model_name(),
-                    action: HandlerCRUD::Update,
-                    extra: "".to_string(),
-                };
-
-                Ok(Response::new(Status::Successful, info, results))
-            }
-            None => {
-                bail!("update action: no item in db");
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_caller_id(req)?;
+        if let Some(category) = &category {
+            GutpCategoryModule::check_category(&pg_conn, &app_id, category)?;
+        }
+
+        let (post, tags) = utils::with_transaction(&pg_conn, |conn| {
+            let old_post = Self::get_post_for_update(conn, &app_id, id)?;
+
+            if caller_id != old_post.author_id {
+                bail!("only the author can update this post");
             }
+            let (title, content) = Self::check_privacy(
+                conn,
+                &app_id,
+                &old_post.subspace_id,
+                is_public,
+                title,
+                content,
+            )?;
+
+            let post = GutpPost {
+                title,
+                content,
+                ext_link,
+                is_public,
+                category: category.unwrap_or(old_post.category.clone()),
+                updated_time: time,
+                ..old_post
+            };
+
+            let (sql, sql_params) = post.build_update();
+            _ = conn.execute(&sql, &sql_params)?;
+
+            let tags = match tag_entries {
+                Some(tag_entries) => {
+                    let tags = GutpTagModule::resolve_tags(
+                        conn,
+                        &post,
+                        &tag_entries,
+                        create_tags,
+                        &random_str,
+                        time,
+                    )?;
+                    GutpPostTagModule::set_post_tags(conn, &post.id, &tags, &random_str, time)?;
+                    Some(tags)
+                }
+                None => None,
+            };
+
+            Ok((post, tags))
+        })?;
+
+        let results: Vec<GutpPost> = vec![post];
+
+        // the new tags of the post, if changed, are carried back in extra
+        let extra = match tags {
+            Some(tags) => serde_json::to_string(&tags)?,
+            None => "".to_string(),
+        };
+        let info = Info {
+            model_name: GutpPost::model_name(),
+            action: HandlerCRUD::Update,
+            extra,
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
+    /// Promote or demote a post, by a moderator of its subspace.
+    fn set_weight(req: &mut Request) -> Result<Response> {
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let id = params.get("id").ok_or(anyhow!("id is required"))?;
+        let weight = params
+            .get("weight")
+            .ok_or(anyhow!("weight is required"))?
+            .parse::<i16>()?;
+        if !(GutpPostWeight::SuperLow as i16..=GutpPostWeight::SuperHigh as i16).contains(&weight) {
+            bail!("weight is out of range");
         }
+
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_caller_id(req)?;
+        let actor = GutpModLogModule::get_actor(req, &params)?;
+
+        let post = utils::with_transaction(&pg_conn, |conn| {
+            let old_post = Self::get_post_by_id(conn, &app_id, id)?;
+            GutpModeratorModule::check_subspace_moderator(
+                conn,
+                &app_id,
+                &old_post.subspace_id,
+                &caller_id,
+            )?;
+
+            GutpModLogModule::record(
+                conn,
+                &actor,
+                GutpModLog {
+                    subspace_id: old_post.subspace_id.clone(),
+                    target_model: GutpPost::model_name(),
+                    target_id: old_post.id.clone(),
+                    action: "set_weight".to_string(),
+                    before_value: old_post.weight.to_string(),
+                    after_value: weight.to_string(),
+                    ..Default::default()
+                },
+            )?;
+
+            let post = GutpPost { weight, ..old_post };
+            let (sql, sql_params) = post.build_update();
+            _ = conn.execute(&sql, &sql_params)?;
+            Self::refresh_score(conn, &post.id)?;
+
+            Ok(post)
+        })?;
+
+        let results: Vec<GutpPost> = vec![post];
+
+        let info = Info {
+            model_name: GutpPost::model_name(),
+            action: HandlerCRUD::Update,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
+    /// Pin a post to the top of its subspace, or unpin it, by a moderator of the subspace.
+    fn set_pinned(req: &mut Request) -> Result<Response> {
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let id = params.get("id").ok_or(anyhow!("id is required"))?;
+        let pinned = params
+            .get("pinned")
+            .ok_or(anyhow!("pinned is required"))?
+            .parse::<bool>()?;
+
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_caller_id(req)?;
+        let actor = GutpModLogModule::get_actor(req, &params)?;
+        let time = req
+            .ext()
+            .get("time")
+            .ok_or(anyhow!("time is required"))?
+            .parse::<i64>()?;
+
+        let post = utils::with_transaction(&pg_conn, |conn| {
+            let old_post = Self::get_post_by_id(conn, &app_id, id)?;
+            GutpModeratorModule::check_subspace_moderator(
+                conn,
+                &app_id,
+                &old_post.subspace_id,
+                &caller_id,
+            )?;
+
+            let pinned_time = if pinned { time } else { 0 };
+            GutpModLogModule::record(
+                conn,
+                &actor,
+                GutpModLog {
+                    subspace_id: old_post.subspace_id.clone(),
+                    target_model: GutpPost::model_name(),
+                    target_id: old_post.id.clone(),
+                    action: if pinned { "pin" } else { "unpin" }.to_string(),
+                    before_value: old_post.pinned_time.to_string(),
+                    after_value: pinned_time.to_string(),
+                    ..Default::default()
+                },
+            )?;
+
+            let post = GutpPost {
+                pinned_time,
+                ..old_post
+            };
+            let (sql, sql_params) = post.build_update();
+            _ = conn.execute(&sql, &sql_params)?;
+
+            Ok(post)
+        })?;
+
+        let results: Vec<GutpPost> = vec![post];
+
+        let info = Info {
+            model_name: GutpPost::model_name(),
+            action: HandlerCRUD::Update,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
     }
 
     fn delete(req: &mut Request) -> Result<Response> {
@@ -384,9 +1060,41 @@ impl GutpPostModule {
         let params = req.parse_urlencoded()?;
 
         let id = params.get("id").ok_or(anyhow!("id is required"))?;
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_caller_id(req)?;
+        let actor = GutpModLogModule::get_actor(req, &params)?;
+
+        utils::with_transaction(&pg_conn, |conn| {
+            let post = Self::get_post_by_id(conn, &app_id, id)?;
+            if post.author_id != caller_id {
+                GutpModeratorModule::check_subspace_moderator(
+                    conn,
+                    &app_id,
+                    &post.subspace_id,
+                    &caller_id,
+                )?;
+                GutpModLogModule::record(
+                    conn,
+                    &actor,
+                    GutpModLog {
+                        subspace_id: post.subspace_id.clone(),
+                        target_model: GutpPost::model_name(),
+                        target_id: post.id.clone(),
+                        action: "delete".to_string(),
+                        before_value: post.status.to_string(),
+                        after_value: "".to_string(),
+                        ..Default::default()
+                    },
+                )?;
+            }
+            GutpPostTagModule::delete_by_post(conn, id)?;
+            GutpReactionModule::delete_by_target(conn, &GutpPost::model_name(), id)?;
+            GutpBookmarkModule::delete_by_post(conn, id)?;
 
-        let (sql, sql_params) = GutpPost::build_delete(id.as_str());
-        _ = pg_conn.execute(&sql, &sql_params)?;
+            let (sql, sql_params) = GutpPost::build_delete(id.as_str());
+            _ = conn.execute(&sql, &sql_params)?;
+            Ok(())
+        })?;
 
         let info = Info {
             model_name: GutpPost::model_name(),
@@ -403,12 +1111,18 @@ impl Module for GutpPostModule {
     fn router(&self, router: &mut Router) -> Result<()> {
         router.get("/gutp/v1/post", Self::get_one);
         router.get("/gutp/v1/post/list", Self::get_list);
+        router.get("/gutp/v1/post/feed", Self::feed);
+        router.get("/gutp/v1/post/feed/following", Self::feed_following);
         router.get("/gutp/v1/post/list_by_subspace", Self::list_by_subspace);
         router.get("/gutp/v1/post/list_by_author", Self::list_by_author);
+        router.get("/gutp/v1/post/list_by_tag", Self::list_by_tag);
+        router.get("/gutp/v1/post/list_bookmarked", Self::list_bookmarked);
         // router.get("/gutp/v1/post/list_by_profession", Self::list_by_profession);
         // router.get("/gutp/v1/post/list_by_appid", Self::list_by_appid);
         router.post("/gutp/v1/post/create", Self::new_one);
         router.post("/gutp/v1/post/update", Self::update);
+        router.post("/gutp/v1/post/set_weight", Self::set_weight);
+        router.post("/gutp/v1/post/set_pinned", Self::set_pinned);
         router.post("/gutp/v1/post/delete", Self::delete);
 
         Ok(())
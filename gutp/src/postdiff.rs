@@ -1,4 +1,8 @@
 use crate::constants::DB_URL_ENV;
+use crate::envelope::Envelope;
+use crate::member::GutpMemberModule;
+use crate::post::GutpPostModule;
+use crate::subspace::GutpSubspaceModule;
 use crate::utils;
 use anyhow::{anyhow, bail};
 use eightfish_sdk::{HandlerCRUD, Info, Module, Request, Response, Result, Router, Status};
@@ -15,12 +19,22 @@ impl GutpPostDiffModule {
 
         let params = req.parse_urlencoded()?;
         let postdiff_id = params.get("id").ok_or(anyhow!("postdiff_id is required"))?;
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_optional_caller_id(req);
 
         let (sql, sql_params) = GutpPostDiff::build_get_by_id(postdiff_id);
         let rowset = pg_conn.query(&sql, &sql_params)?;
 
         let results = if let Some(row) = rowset.rows.into_iter().next() {
-            vec![GutpPostDiff::from_row(row)]
+            let postdiff = GutpPostDiff::from_row(row);
+            let post = GutpPostModule::get_post_by_id(&pg_conn, &app_id, &postdiff.post_id)?;
+            GutpMemberModule::check_readable(
+                &pg_conn,
+                &app_id,
+                &post.subspace_id,
+                caller_id.as_deref(),
+            )?;
+            vec![postdiff]
         } else {
             bail!("no this item".to_string());
         };
@@ -40,14 +54,25 @@ impl GutpPostDiffModule {
 
         let params = req.parse_urlencoded()?;
 
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_optional_caller_id(req);
+
         let (limit, offset) = utils::build_page_info(&params)?;
         let sql = SqlBuilder::select_from(&GutpPostDiff::model_name())
             .fields(&GutpPostDiff::fields())
+            .and_where(format!(
+                "post_id IN (SELECT id FROM gutppost WHERE app_id = $1 AND {})",
+                GutpMemberModule::build_readable_cond("subspace_id", "$2")
+            ))
             .order_desc("created_time")
             .limit(limit)
             .offset(offset)
             .sql()?;
-        let rowset = pg_conn.query(&sql, &[])?;
+        let sql_params = [
+            ParameterValue::Str(app_id),
+            ParameterValue::Str(caller_id.unwrap_or_default()),
+        ];
+        let rowset = pg_conn.query(&sql, &sql_params)?;
 
         let mut results: Vec<GutpPostDiff> = vec![];
         for row in rowset.rows {
@@ -75,6 +100,16 @@ impl GutpPostDiffModule {
             .ok_or(anyhow!("post_id is required"))?;
         let (limit, offset) = utils::build_page_info(&params)?;
 
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_optional_caller_id(req);
+        let post = GutpPostModule::get_post_by_id(&pg_conn, &app_id, post_id)?;
+        GutpMemberModule::check_readable(
+            &pg_conn,
+            &app_id,
+            &post.subspace_id,
+            caller_id.as_deref(),
+        )?;
+
         let sql = SqlBuilder::select_from(&GutpPostDiff::model_name())
             .fields(&GutpPostDiff::fields())
             .and_where_eq("post_id", "$1")
@@ -119,6 +154,7 @@ impl GutpPostDiffModule {
             .ok_or(anyhow!("version_num is required"))?
             .parse::<i32>()?;
 
+        let app_id = utils::get_app_id(req)?;
         let id = req
             .ext()
             .get("random_str")
@@ -130,6 +166,16 @@ impl GutpPostDiffModule {
             .ok_or(anyhow!("generate time failed"))?
             .parse::<i64>()?;
 
+        // the diff of a non-public post would leak its plaintext, so it is encrypted too
+        let post = GutpPostModule::get_post_by_id(&pg_conn, &app_id, &post_id)?;
+        let diff = if post.is_public {
+            diff
+        } else {
+            let subspace =
+                GutpSubspaceModule::get_subspace_by_id(&pg_conn, &app_id, &post.subspace_id)?;
+            Envelope::check(&diff, subspace.key_version)?
+        };
+
         let postdiff = GutpPostDiff {
             id,
             post_id,
@@ -171,6 +217,8 @@ impl GutpPostDiffModule {
             .get("version_num")
             .ok_or(anyhow!("version_num is required"))?
             .parse::<i32>()?;
+        let app_id = utils::get_app_id(req)?;
+        _ = GutpPostModule::get_post_by_id(&pg_conn, &app_id, &post_id)?;
 
         // get the item from db, check whether obj in db
         let (sql, sql_params) = GutpPostDiff::build_get_by_id(id);
@@ -178,6 +226,7 @@ impl GutpPostDiffModule {
         match rowset.rows.into_iter().next() {
             Some(row) => {
                 let old_postdiff = GutpPostDiff::from_row(row);
+                _ = GutpPostModule::get_post_by_id(&pg_conn, &app_id, &old_postdiff.post_id)?;
 
                 let postdiff = GutpPostDiff {
                     post_id,
@@ -212,6 +261,14 @@ impl GutpPostDiffModule {
         let params = req.parse_urlencoded()?;
 
         let id = params.get("id").ok_or(anyhow!("id is required."))?;
+        let app_id = utils::get_app_id(req)?;
+
+        let (sql, sql_params) = GutpPostDiff::build_get_by_id(id);
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+        if let Some(row) = rowset.rows.into_iter().next() {
+            let postdiff = GutpPostDiff::from_row(row);
+            _ = GutpPostModule::get_post_by_id(&pg_conn, &app_id, &postdiff.post_id)?;
+        }
 
         let (sql, sql_params) = GutpPostDiff::build_delete(id);
         _ = pg_conn.execute(&sql, &sql_params)?;
@@ -4,24 +4,164 @@ use spin_sdk::pg::{self, ParameterValue};
 use sql_builder::SqlBuilder;
 
 use crate::constants::DB_URL_ENV;
+use crate::member::GutpMemberModule;
+use crate::moderator::GutpModeratorModule;
+use crate::post::GutpPostModule;
+use crate::tag::GutpTagModule;
 
 use crate::utils;
-use gutp_types::GutpPostTag;
+use gutp_types::{GutpPostTag, GutpTag};
 pub struct GutpPostTagModule;
 
 impl GutpPostTagModule {
+    /// Make the tag links of a post exactly `tags`: links not in the list are
+    /// removed, missing ones are added.
+    pub(crate) fn set_post_tags(
+        pg_conn: &pg::Connection,
+        post_id: &str,
+        tags: &[GutpTag],
+        id_base: &str,
+        time: i64,
+    ) -> Result<()> {
+        let existing = Self::get_post_links(pg_conn, post_id)?;
+
+        for posttag in existing.iter() {
+            if !tags.iter().any(|tag| tag.id == posttag.tag_id) {
+                let (sql, sql_params) = GutpPostTag::build_delete(&posttag.id);
+                _ = pg_conn.execute(&sql, &sql_params)?;
+                GutpTagModule::add_post_count(pg_conn, &posttag.tag_id, -1)?;
+            }
+        }
+
+        for (index, tag) in tags.iter().enumerate() {
+            if !existing.iter().any(|posttag| posttag.tag_id == tag.id) {
+                let posttag = GutpPostTag {
+                    id: utils::derive_id(id_base, "posttag", index),
+                    post_id: post_id.to_owned(),
+                    tag_id: tag.id.clone(),
+                    created_time: time,
+                };
+                let (sql, sql_params) = posttag.build_insert();
+                _ = pg_conn.execute(&sql, &sql_params)?;
+                GutpTagModule::add_post_count(pg_conn, &tag.id, 1)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Drop the tag links of a post, when the post is deleted, keeping the
+    /// tag counts right.
+    pub(crate) fn delete_by_post(pg_conn: &pg::Connection, post_id: &str) -> Result<()> {
+        for posttag in Self::get_post_links(pg_conn, post_id)? {
+            let (sql, sql_params) = GutpPostTag::build_delete(&posttag.id);
+            _ = pg_conn.execute(&sql, &sql_params)?;
+            GutpTagModule::add_post_count(pg_conn, &posttag.tag_id, -1)?;
+        }
+        Ok(())
+    }
+
+    /// Drop all the post links of a tag, when the tag is deleted.
+    pub(crate) fn delete_by_tag(pg_conn: &pg::Connection, tag_id: &str) -> Result<()> {
+        let sql_param = ParameterValue::Str(tag_id.to_owned());
+        _ = pg_conn.execute("DELETE FROM gutpposttag WHERE tag_id = $1", &[sql_param])?;
+        Ok(())
+    }
+
+    /// The tags of a post are set by its author or a moderator of its
+    /// subspace.
+    fn check_tagger(
+        pg_conn: &pg::Connection,
+        app_id: &str,
+        post_id: &str,
+        caller_id: &str,
+    ) -> Result<()> {
+        let post = GutpPostModule::get_post_by_id(pg_conn, app_id, post_id)?;
+        if post.author_id == caller_id {
+            return Ok(());
+        }
+        GutpModeratorModule::check_subspace_moderator(pg_conn, app_id, &post.subspace_id, caller_id)
+    }
+
+    /// Links to an alias go to its canonical tag, as the tag trees of
+    /// `post/list_by_tag` are built from canonical tags only.
+    fn get_canonical_id(pg_conn: &pg::Connection, app_id: &str, tag_id: &str) -> Result<String> {
+        let tag = GutpTagModule::get_tag_by_id(pg_conn, app_id, tag_id)?;
+        Ok(GutpTagModule::to_canonical(pg_conn, tag)?.id)
+    }
+
+    /// A tag can only be linked to posts in the subspace it belongs to.
+    fn check_same_subspace(
+        pg_conn: &pg::Connection,
+        app_id: &str,
+        post_id: &str,
+        tag_id: &str,
+    ) -> Result<()> {
+        let post = GutpPostModule::get_post_by_id(pg_conn, app_id, post_id)?;
+        let tag = GutpTagModule::get_tag_by_id(pg_conn, app_id, tag_id)?;
+        if post.subspace_id != tag.subspace_id {
+            bail!("tag {tag_id} does not belong to the subspace of post {post_id}");
+        }
+        Ok(())
+    }
+
+    fn find_link(
+        pg_conn: &pg::Connection,
+        post_id: &str,
+        tag_id: &str,
+    ) -> Result<Option<GutpPostTag>> {
+        let sql = SqlBuilder::select_from(&GutpPostTag::model_name())
+            .fields(&GutpPostTag::fields())
+            .and_where_eq("post_id", "$1")
+            .and_where_eq("tag_id", "$2")
+            .sql()?;
+        let sql_params = [
+            ParameterValue::Str(post_id.to_owned()),
+            ParameterValue::Str(tag_id.to_owned()),
+        ];
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+
+        Ok(rowset.rows.into_iter().next().map(GutpPostTag::from_row))
+    }
+
+    fn get_post_links(pg_conn: &pg::Connection, post_id: &str) -> Result<Vec<GutpPostTag>> {
+        let sql = SqlBuilder::select_from(&GutpPostTag::model_name())
+            .fields(&GutpPostTag::fields())
+            .and_where_eq("post_id", "$1")
+            .sql()?;
+        let sql_param = ParameterValue::Str(post_id.to_owned());
+        let rowset = pg_conn.query(&sql, &[sql_param])?;
+
+        let mut results: Vec<GutpPostTag> = vec![];
+        for row in rowset.rows {
+            results.push(GutpPostTag::from_row(row));
+        }
+
+        Ok(results)
+    }
+
     fn get_one(req: &mut Request) -> Result<Response> {
         let pg_addr = std::env::var(DB_URL_ENV)?;
         let pg_conn = pg::Connection::open(&pg_addr)?;
 
         let params = req.parse_urlencoded()?;
         let posttag_id = params.get("id").ok_or(anyhow!("posttag_id is required"))?;
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_optional_caller_id(req);
 
         let (sql, sql_params) = GutpPostTag::build_get_by_id(posttag_id);
         let rowset = pg_conn.query(&sql, &sql_params)?;
 
         let results = if let Some(row) = rowset.rows.into_iter().next() {
-            vec![GutpPostTag::from_row(row)]
+            let posttag = GutpPostTag::from_row(row);
+            let post = GutpPostModule::get_post_by_id(&pg_conn, &app_id, &posttag.post_id)?;
+            GutpMemberModule::check_readable(
+                &pg_conn,
+                &app_id,
+                &post.subspace_id,
+                caller_id.as_deref(),
+            )?;
+            vec![posttag]
         } else {
             bail!("no this item".to_string())
         };
@@ -40,14 +180,25 @@ impl GutpPostTagModule {
         let pg_conn = pg::Connection::open(&pg_addr)?;
 
         let params = req.parse_urlencoded()?;
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_optional_caller_id(req);
+
         let (limit, offset) = utils::build_page_info(&params)?;
         let sql = SqlBuilder::select_from(&GutpPostTag::model_name())
             .fields(&GutpPostTag::fields())
+            .and_where(format!(
+                "post_id IN (SELECT id FROM gutppost WHERE app_id = $1 AND {})",
+                GutpMemberModule::build_readable_cond("subspace_id", "$2")
+            ))
             .order_desc("created_time")
             .limit(limit)
             .offset(offset)
             .sql()?;
-        let rowset = pg_conn.query(&sql, &[])?;
+        let sql_params = [
+            ParameterValue::Str(app_id),
+            ParameterValue::Str(caller_id.unwrap_or_default()),
+        ];
+        let rowset = pg_conn.query(&sql, &sql_params)?;
 
         let mut results: Vec<GutpPostTag> = vec![];
         for row in rowset.rows {
@@ -73,6 +224,15 @@ impl GutpPostTagModule {
         let post_id = params
             .get("post_id")
             .ok_or(anyhow!("post_id is required"))?;
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_optional_caller_id(req);
+        let post = GutpPostModule::get_post_by_id(&pg_conn, &app_id, post_id)?;
+        GutpMemberModule::check_readable(
+            &pg_conn,
+            &app_id,
+            &post.subspace_id,
+            caller_id.as_deref(),
+        )?;
 
         let (limit, offset) = utils::build_page_info(&params)?;
         let sql = SqlBuilder::select_from(&GutpPostTag::model_name())
@@ -107,6 +267,16 @@ impl GutpPostTagModule {
         let params = req.parse_urlencoded()?;
 
         let tag_id = params.get("tag_id").ok_or(anyhow!("tag_id is required"))?;
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_optional_caller_id(req);
+        let tag = GutpTagModule::get_tag_by_id(&pg_conn, &app_id, tag_id)?;
+        GutpMemberModule::check_readable(
+            &pg_conn,
+            &app_id,
+            &tag.subspace_id,
+            caller_id.as_deref(),
+        )?;
+
         let (limit, offset) = utils::build_page_info(&params)?;
         let sql = SqlBuilder::select_from(&GutpPostTag::model_name())
             .fields(&GutpPostTag::fields())
@@ -148,6 +318,8 @@ impl GutpPostTagModule {
             .ok_or(anyhow!("tag_id is required"))?
             .to_owned();
 
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_caller_id(req)?;
         let id = req
             .ext()
             .get("random_str")
@@ -159,16 +331,30 @@ impl GutpPostTagModule {
             .ok_or(anyhow!("failed get time"))?
             .parse::<i64>()?;
 
-        let posttag = GutpPostTag {
-            id,
-            post_id,
-            tag_id,
-            created_time: time,
-        };
+        let posttag = utils::with_transaction(&pg_conn, |conn| {
+            Self::check_tagger(conn, &app_id, &post_id, &caller_id)?;
+            let tag_id = Self::get_canonical_id(conn, &app_id, &tag_id)?;
+            Self::check_same_subspace(conn, &app_id, &post_id, &tag_id)?;
+
+            // linking the same pair again returns the existing link
+            if let Some(posttag) = Self::find_link(conn, &post_id, &tag_id)? {
+                return Ok(posttag);
+            }
+
+            let posttag = GutpPostTag {
+                id,
+                post_id,
+                tag_id,
+                created_time: time,
+            };
 
-        // construct a sql statement and param
-        let (sql, sql_params) = posttag.build_insert();
-        _ = pg_conn.execute(&sql, &sql_params)?;
+            // construct a sql statement and param
+            let (sql, sql_params) = posttag.build_insert();
+            _ = conn.execute(&sql, &sql_params)?;
+            GutpTagModule::add_post_count(conn, &posttag.tag_id, 1)?;
+
+            Ok(posttag)
+        })?;
 
         let results: Vec<GutpPostTag> = vec![posttag];
 
@@ -201,37 +387,102 @@ impl GutpPostTagModule {
         //     .get("time")
         //     .ok_or(anyhow!("time is required"))?
         //     .parse::<i64>()?;
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_caller_id(req)?;
+
+        let posttag = utils::with_transaction(&pg_conn, |conn| {
+            Self::check_tagger(conn, &app_id, &post_id, &caller_id)?;
+            let tag_id = Self::get_canonical_id(conn, &app_id, &tag_id)?;
+            Self::check_same_subspace(conn, &app_id, &post_id, &tag_id)?;
+            if let Some(other) = Self::find_link(conn, &post_id, &tag_id)? {
+                if &other.id != id {
+                    bail!("the post is already linked to this tag");
+                }
+            }
 
-        // get the item from db, check whether obj in db
-        let (sql, sql_params) = GutpPostTag::build_get_by_id(id);
-        let rowset = pg_conn.query(&sql, &sql_params)?;
-        match rowset.rows.into_iter().next() {
-            Some(row) => {
-                let old_posttag = GutpPostTag::from_row(row);
+            // get the item from db, check whether obj in db
+            let (sql, sql_params) = GutpPostTag::build_get_by_id(id);
+            let rowset = conn.query(&sql, &sql_params)?;
+            let old_posttag = match rowset.rows.into_iter().next() {
+                Some(row) => GutpPostTag::from_row(row),
+                None => bail!("update action: no item in db"),
+            };
+            if old_posttag.post_id != post_id {
+                Self::check_tagger(conn, &app_id, &old_posttag.post_id, &caller_id)?;
+            }
 
-                let posttag = GutpPostTag {
-                    post_id,
-                    tag_id,
-                    ..old_posttag
-                };
+            if old_posttag.tag_id != tag_id {
+                GutpTagModule::add_post_count(conn, &old_posttag.tag_id, -1)?;
+                GutpTagModule::add_post_count(conn, &tag_id, 1)?;
+            }
 
-                let (sql, sql_params) = posttag.build_update();
-                _ = pg_conn.execute(&sql, &sql_params)?;
+            let posttag = GutpPostTag {
+                post_id,
+                tag_id,
+                ..old_posttag
+            };
 
-                let results: Vec<GutpPostTag> = vec![posttag];
+            let (sql, sql_params) = posttag.build_update();
+            _ = conn.execute(&sql, &sql_params)?;
 
-                let info = Info {
-                    model_name: GutpPostTag::model_name(),
-                    action: HandlerCRUD::Update,
-                    extra: "".to_string(),
-                };
+            Ok(posttag)
+        })?;
 
-                Ok(Response::new(Status::Successful, info, results))
-            }
-            None => {
-                bail!("update action: no item in db");
-            }
-        }
+        let results: Vec<GutpPostTag> = vec![posttag];
+
+        let info = Info {
+            model_name: GutpPostTag::model_name(),
+            action: HandlerCRUD::Update,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
+    /// Replace all the tags of a post in one request.
+    fn set_by_post(req: &mut Request) -> Result<Response> {
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let post_id = params
+            .get("post_id")
+            .ok_or(anyhow!("post_id is required"))?
+            .to_owned();
+        let tag_ids =
+            utils::get_list_param(&params, "tag_ids").ok_or(anyhow!("tag_ids is required"))?;
+
+        let id_base = req
+            .ext()
+            .get("random_str")
+            .ok_or(anyhow!("failed get id"))?
+            .to_owned();
+        let time = req
+            .ext()
+            .get("time")
+            .ok_or(anyhow!("failed get time"))?
+            .parse::<i64>()?;
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_caller_id(req)?;
+
+        let results = utils::with_transaction(&pg_conn, |conn| {
+            Self::check_tagger(conn, &app_id, &post_id, &caller_id)?;
+            let post = GutpPostModule::get_post_by_id(conn, &app_id, &post_id)?;
+            // only tags of the post's own subspace are resolved
+            let tags = GutpTagModule::resolve_tags(conn, &post, &tag_ids, false, &id_base, time)?;
+            Self::set_post_tags(conn, &post_id, &tags, &id_base, time)?;
+
+            Self::get_post_links(conn, &post_id)
+        })?;
+
+        let info = Info {
+            model_name: GutpPostTag::model_name(),
+            action: HandlerCRUD::Update,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
     }
 
     fn delete(req: &mut Request) -> Result<Response> {
@@ -241,9 +492,22 @@ impl GutpPostTagModule {
         let params = req.parse_urlencoded()?;
 
         let id = params.get("id").ok_or(anyhow!("delete action: no id"))?;
-
-        let (sql, sql_params) = GutpPostTag::build_delete(id.as_str());
-        let _er = pg_conn.execute(&sql, &sql_params)?;
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_caller_id(req)?;
+
+        utils::with_transaction(&pg_conn, |conn| {
+            let (sql, sql_params) = GutpPostTag::build_get_by_id(id);
+            let rowset = conn.query(&sql, &sql_params)?;
+            if let Some(row) = rowset.rows.into_iter().next() {
+                let posttag = GutpPostTag::from_row(row);
+                Self::check_tagger(conn, &app_id, &posttag.post_id, &caller_id)?;
+
+                let (sql, sql_params) = GutpPostTag::build_delete(id.as_str());
+                let _er = conn.execute(&sql, &sql_params)?;
+                GutpTagModule::add_post_count(conn, &posttag.tag_id, -1)?;
+            }
+            Ok(())
+        })?;
 
         let info = Info {
             model_name: GutpPostTag::model_name(),
@@ -264,6 +528,7 @@ impl Module for GutpPostTagModule {
         router.get("/gutp/v1/posttag/list_by_tag", Self::list_by_tag);
         router.post("/gutp/v1/posttag/create", Self::new_one);
         router.post("/gutp/v1/posttag/update", Self::update);
+        router.post("/gutp/v1/posttag/set_by_post", Self::set_by_post);
         router.post("/gutp/v1/posttag/delete", Self::delete);
 
         Ok(())
@@ -0,0 +1,337 @@
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, bail};
+use eightfish_sdk::{HandlerCRUD, Info, Module, Request, Response, Result, Router, Status};
+use serde::Serialize;
+use spin_sdk::pg::{self, Decode, ParameterValue};
+use sql_builder::SqlBuilder;
+
+use crate::app::GutpAppModule;
+use crate::comment::GutpCommentModule;
+use crate::constants::DB_URL_ENV;
+use crate::member::GutpMemberModule;
+use crate::post::GutpPostModule;
+use crate::reactionkind::GutpReactionKindModule;
+use crate::utils;
+use gutp_types::{GutpComment, GutpPost, GutpReaction};
+
+/// Reactions on one post or comment, returned in extra along with it.
+#[derive(Serialize, Default)]
+pub(crate) struct ReactionSummary {
+    // reaction count per kind
+    counts: BTreeMap<String, i64>,
+    // the kinds the caller reacted with
+    mine: Vec<String>,
+}
+
+pub struct GutpReactionModule;
+
+impl GutpReactionModule {
+    /// Build the reaction summaries of a list of targets, as a json object
+    /// keyed by target id.
+    pub(crate) fn build_summary(
+        pg_conn: &pg::Connection,
+        target_model: &str,
+        target_ids: &[String],
+        caller_id: Option<&str>,
+    ) -> Result<String> {
+        let summaries = Self::get_summaries(pg_conn, target_model, target_ids, caller_id)?;
+        Ok(serde_json::to_string(&summaries)?)
+    }
+
+    pub(crate) fn get_summaries(
+        pg_conn: &pg::Connection,
+        target_model: &str,
+        target_ids: &[String],
+        caller_id: Option<&str>,
+    ) -> Result<BTreeMap<String, ReactionSummary>> {
+        let mut summaries: BTreeMap<String, ReactionSummary> = BTreeMap::new();
+        if target_ids.is_empty() {
+            return Ok(summaries);
+        }
+
+        let placeholders = utils::build_placeholders(2, target_ids.len());
+        let mut sql_params = vec![ParameterValue::Str(target_model.to_owned())];
+        for target_id in target_ids {
+            sql_params.push(ParameterValue::Str(target_id.clone()));
+        }
+
+        let sql = format!("SELECT target_id, kind, COUNT(*) FROM gutpreaction WHERE target_model = $1 AND target_id IN ({placeholders}) GROUP BY target_id, kind");
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+        for row in rowset.rows {
+            let target_id = String::decode(&row[0])?;
+            let kind = String::decode(&row[1])?;
+            let count = i64::decode(&row[2])?;
+            summaries
+                .entry(target_id)
+                .or_default()
+                .counts
+                .insert(kind, count);
+        }
+
+        if let Some(caller_id) = caller_id {
+            sql_params.push(ParameterValue::Str(caller_id.to_owned()));
+            let sql = format!(
+                "SELECT target_id, kind FROM gutpreaction WHERE target_model = $1 AND target_id IN ({placeholders}) AND user_id = ${}",
+                sql_params.len()
+            );
+            let rowset = pg_conn.query(&sql, &sql_params)?;
+            for row in rowset.rows {
+                let target_id = String::decode(&row[0])?;
+                let kind = String::decode(&row[1])?;
+                summaries.entry(target_id).or_default().mine.push(kind);
+            }
+        }
+
+        Ok(summaries)
+    }
+
+    /// Remove all the reactions on a target, when the target is deleted.
+    pub(crate) fn delete_by_target(
+        pg_conn: &pg::Connection,
+        target_model: &str,
+        target_id: &str,
+    ) -> Result<()> {
+        let sql_params = [
+            ParameterValue::Str(target_model.to_owned()),
+            ParameterValue::Str(target_id.to_owned()),
+        ];
+        _ = pg_conn.execute(
+            "DELETE FROM gutpreaction WHERE target_model = $1 AND target_id = $2",
+            &sql_params,
+        )?;
+        Ok(())
+    }
+
+    /// Make sure the reaction target is a post or comment of the app, in a
+    /// subspace the caller may read.
+    fn check_target(
+        pg_conn: &pg::Connection,
+        app_id: &str,
+        target_model: &str,
+        target_id: &str,
+        caller_id: Option<&str>,
+    ) -> Result<()> {
+        let post = if target_model == GutpPost::model_name() {
+            GutpPostModule::get_post_by_id(pg_conn, app_id, target_id)?
+        } else if target_model == GutpComment::model_name() {
+            let comment = GutpCommentModule::get_comment_by_id(pg_conn, app_id, target_id)?;
+            GutpPostModule::get_post_by_id(pg_conn, app_id, &comment.post_id)?
+        } else {
+            bail!("can only react to posts and comments");
+        };
+        GutpMemberModule::check_readable(pg_conn, app_id, &post.subspace_id, caller_id)
+    }
+
+    fn find_reaction(
+        pg_conn: &pg::Connection,
+        user_id: &str,
+        target_model: &str,
+        target_id: &str,
+        kind: &str,
+    ) -> Result<Option<GutpReaction>> {
+        let sql = SqlBuilder::select_from(&GutpReaction::model_name())
+            .fields(&GutpReaction::fields())
+            .and_where_eq("user_id", "$1")
+            .and_where_eq("target_model", "$2")
+            .and_where_eq("target_id", "$3")
+            .and_where_eq("kind", "$4")
+            .sql()?;
+        let sql_params = [
+            ParameterValue::Str(user_id.to_owned()),
+            ParameterValue::Str(target_model.to_owned()),
+            ParameterValue::Str(target_id.to_owned()),
+            ParameterValue::Str(kind.to_owned()),
+        ];
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+
+        Ok(rowset.rows.into_iter().next().map(GutpReaction::from_row))
+    }
+
+    fn list_by_target(req: &mut Request) -> Result<Response> {
+        GutpAppModule::check_feature(req, "reactions")?;
+
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let target_model = params
+            .get("target_model")
+            .ok_or(anyhow!("target_model is required"))?;
+        let target_id = params
+            .get("target_id")
+            .ok_or(anyhow!("target_id is required"))?;
+        let (limit, offset) = utils::build_page_info(&params)?;
+
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_optional_caller_id(req);
+        Self::check_target(
+            &pg_conn,
+            &app_id,
+            target_model,
+            target_id,
+            caller_id.as_deref(),
+        )?;
+
+        let sql = SqlBuilder::select_from(&GutpReaction::model_name())
+            .fields(&GutpReaction::fields())
+            .and_where_eq("target_model", "$1")
+            .and_where_eq("target_id", "$2")
+            .order_desc("created_time")
+            .limit(limit)
+            .offset(offset)
+            .sql()?;
+        let sql_params = [
+            ParameterValue::Str(target_model.clone()),
+            ParameterValue::Str(target_id.clone()),
+        ];
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+
+        let mut results: Vec<GutpReaction> = vec![];
+        for row in rowset.rows {
+            let sp = GutpReaction::from_row(row);
+            results.push(sp);
+        }
+
+        let info = Info {
+            model_name: GutpReaction::model_name(),
+            action: HandlerCRUD::List,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
+    /// React to a post or comment. Reacting twice with the same kind returns
+    /// the existing reaction.
+    fn new_one(req: &mut Request) -> Result<Response> {
+        GutpAppModule::check_feature(req, "reactions")?;
+
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let target_model = params
+            .get("target_model")
+            .ok_or(anyhow!("target_model is required"))?
+            .to_owned();
+        let target_id = params
+            .get("target_id")
+            .ok_or(anyhow!("target_id is required"))?
+            .to_owned();
+        let kind = params
+            .get("kind")
+            .ok_or(anyhow!("kind is required"))?
+            .trim()
+            .to_lowercase();
+
+        let app_id = utils::get_app_id(req)?;
+        let user_id = utils::get_caller_id(req)?;
+        let id = req
+            .ext()
+            .get("random_str")
+            .ok_or(anyhow!("generate id failed"))?
+            .to_owned();
+        let time = req
+            .ext()
+            .get("time")
+            .ok_or(anyhow!("generate time failed"))?
+            .parse::<i64>()?;
+
+        let reaction = utils::with_transaction(&pg_conn, |conn| {
+            if let Some(reaction) =
+                Self::find_reaction(conn, &user_id, &target_model, &target_id, &kind)?
+            {
+                return Ok(reaction);
+            }
+
+            Self::check_target(conn, &app_id, &target_model, &target_id, Some(&user_id))?;
+            let reaction_kind = GutpReactionKindModule::get_kind_by_name(conn, &app_id, &kind)?;
+
+            let reaction = GutpReaction {
+                id,
+                user_id,
+                target_model,
+                target_id,
+                kind,
+                score: reaction_kind.score,
+                created_time: time,
+            };
+            let (sql, sql_params) = reaction.build_insert();
+            _ = conn.execute(&sql, &sql_params)?;
+            if reaction.target_model == GutpPost::model_name() {
+                GutpPostModule::refresh_score(conn, &reaction.target_id)?;
+            }
+
+            Ok(reaction)
+        })?;
+
+        let results: Vec<GutpReaction> = vec![reaction];
+
+        let info = Info {
+            model_name: GutpReaction::model_name(),
+            action: HandlerCRUD::Create,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
+    /// Take back the caller's reaction of one kind on a target.
+    fn delete(req: &mut Request) -> Result<Response> {
+        GutpAppModule::check_feature(req, "reactions")?;
+
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let target_model = params
+            .get("target_model")
+            .ok_or(anyhow!("target_model is required"))?;
+        let target_id = params
+            .get("target_id")
+            .ok_or(anyhow!("target_id is required"))?;
+        let kind = params
+            .get("kind")
+            .ok_or(anyhow!("kind is required"))?
+            .trim()
+            .to_lowercase();
+
+        let user_id = utils::get_caller_id(req)?;
+
+        utils::with_transaction(&pg_conn, |conn| {
+            if let Some(reaction) =
+                Self::find_reaction(conn, &user_id, target_model, target_id, &kind)?
+            {
+                let (sql, sql_params) = GutpReaction::build_delete(&reaction.id);
+                _ = conn.execute(&sql, &sql_params)?;
+                if reaction.target_model == GutpPost::model_name() {
+                    GutpPostModule::refresh_score(conn, &reaction.target_id)?;
+                }
+            }
+            Ok(())
+        })?;
+
+        let info = Info {
+            model_name: GutpReaction::model_name(),
+            action: HandlerCRUD::Delete,
+            extra: "".to_string(),
+        };
+        let results: Vec<GutpReaction> = vec![];
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+}
+
+impl Module for GutpReactionModule {
+    fn router(&self, router: &mut Router) -> Result<()> {
+        router.get("/gutp/v1/reaction/list_by_target", Self::list_by_target);
+        router.post("/gutp/v1/reaction/create", Self::new_one);
+        router.post("/gutp/v1/reaction/delete", Self::delete);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,202 @@
+use anyhow::{anyhow, bail};
+use eightfish_sdk::{HandlerCRUD, Info, Module, Request, Response, Result, Router, Status};
+use spin_sdk::pg::{self, Decode, ParameterValue};
+use sql_builder::SqlBuilder;
+
+use crate::app::GutpAppModule;
+use crate::constants::DB_URL_ENV;
+use crate::utils;
+use gutp_types::GutpReactionKind;
+
+pub struct GutpReactionKindModule;
+
+impl GutpReactionKindModule {
+    pub(crate) fn get_kind_by_name(
+        pg_conn: &pg::Connection,
+        app_id: &str,
+        name: &str,
+    ) -> Result<GutpReactionKind> {
+        let sql = SqlBuilder::select_from(&GutpReactionKind::model_name())
+            .fields(&GutpReactionKind::fields())
+            .and_where_eq("app_id", "$1")
+            .and_where_eq("name", "$2")
+            .sql()?;
+        let sql_params = [
+            ParameterValue::Str(app_id.to_owned()),
+            ParameterValue::Str(name.to_owned()),
+        ];
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+        match rowset.rows.into_iter().next() {
+            Some(row) => Ok(GutpReactionKind::from_row(row)),
+            None => bail!("reaction {name} is not enabled in this app"),
+        }
+    }
+
+    fn check_owner(req: &Request) -> Result<()> {
+        let app = GutpAppModule::get_request_app(req)?;
+        let caller_id = utils::get_caller_id(req)?;
+        if !GutpAppModule::is_owner(&app, &caller_id) {
+            bail!("only the owners of the app can manage its reaction kinds");
+        }
+        Ok(())
+    }
+
+    fn list_by_app(req: &mut Request) -> Result<Response> {
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let app_id = utils::get_app_id(req)?;
+        let (limit, offset) = utils::build_page_info(&params)?;
+
+        let sql = SqlBuilder::select_from(&GutpReactionKind::model_name())
+            .fields(&GutpReactionKind::fields())
+            .and_where_eq("app_id", "$1")
+            .order_asc("created_time")
+            .limit(limit)
+            .offset(offset)
+            .sql()?;
+        let sql_param = ParameterValue::Str(app_id);
+        let rowset = pg_conn.query(&sql, &[sql_param])?;
+
+        let mut results: Vec<GutpReactionKind> = vec![];
+        for row in rowset.rows {
+            let sp = GutpReactionKind::from_row(row);
+            results.push(sp);
+        }
+
+        let info = Info {
+            model_name: GutpReactionKind::model_name(),
+            action: HandlerCRUD::List,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
+    /// Add a reaction kind to the app, by one of its owners.
+    fn new_one(req: &mut Request) -> Result<Response> {
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let app_id = utils::get_app_id(req)?;
+        Self::check_owner(req)?;
+        let name = params
+            .get("name")
+            .ok_or(anyhow!("name is required"))?
+            .trim()
+            .to_lowercase();
+        if name.is_empty() {
+            bail!("name can not be empty");
+        }
+        let score = params
+            .get("score")
+            .ok_or(anyhow!("score is required"))?
+            .parse::<i16>()?;
+
+        let id = req
+            .ext()
+            .get("random_str")
+            .ok_or(anyhow!("generate id failed"))?
+            .to_owned();
+        let time = req
+            .ext()
+            .get("time")
+            .ok_or(anyhow!("generate time failed"))?
+            .parse::<i64>()?;
+
+        if Self::get_kind_by_name(&pg_conn, &app_id, &name).is_ok() {
+            bail!("reaction {name} already exists in this app");
+        }
+
+        let kind = GutpReactionKind {
+            id,
+            app_id,
+            name,
+            score,
+            created_time: time,
+        };
+
+        let (sql, sql_params) = kind.build_insert();
+        _ = pg_conn.execute(&sql, &sql_params)?;
+
+        let results: Vec<GutpReactionKind> = vec![kind];
+
+        let info = Info {
+            model_name: GutpReactionKind::model_name(),
+            action: HandlerCRUD::Create,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
+    /// Remove a reaction kind no reaction in the app uses any more, by one of
+    /// the app owners.
+    fn delete(req: &mut Request) -> Result<Response> {
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let id = params.get("id").ok_or(anyhow!("id is required"))?;
+        let app_id = utils::get_app_id(req)?;
+        Self::check_owner(req)?;
+
+        utils::with_transaction(&pg_conn, |conn| {
+            let (sql, sql_params) = GutpReactionKind::build_get_by_id(id);
+            let rowset = conn.query(&sql, &sql_params)?;
+            let kind = match rowset
+                .rows
+                .into_iter()
+                .next()
+                .map(GutpReactionKind::from_row)
+            {
+                Some(kind) if kind.app_id == app_id => kind,
+                _ => bail!("no such reaction kind: {id}"),
+            };
+
+            let sql_params = [
+                ParameterValue::Str(kind.name.clone()),
+                ParameterValue::Str(app_id.clone()),
+            ];
+            let rowset = conn.query(
+                "SELECT COUNT(*) FROM gutpreaction WHERE kind = $1 AND ((target_model = 'gutppost' AND target_id IN (SELECT id FROM gutppost WHERE app_id = $2)) OR (target_model = 'gutpcomment' AND target_id IN (SELECT id FROM gutpcomment WHERE app_id = $2)))",
+                &sql_params,
+            )?;
+            let in_use = match rowset.rows.first() {
+                Some(row) => i64::decode(&row[0])?,
+                None => 0,
+            };
+            if in_use > 0 {
+                bail!("reaction {} is still in use", kind.name);
+            }
+
+            let (sql, sql_params) = GutpReactionKind::build_delete(id);
+            _ = conn.execute(&sql, &sql_params)?;
+            Ok(())
+        })?;
+
+        let info = Info {
+            model_name: GutpReactionKind::model_name(),
+            action: HandlerCRUD::Delete,
+            extra: "".to_string(),
+        };
+        let results: Vec<GutpReactionKind> = vec![];
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+}
+
+impl Module for GutpReactionKindModule {
+    fn router(&self, router: &mut Router) -> Result<()> {
+        router.get("/gutp/v1/reactionkind/list_by_app", Self::list_by_app);
+        router.post("/gutp/v1/reactionkind/create", Self::new_one);
+        router.post("/gutp/v1/reactionkind/delete", Self::delete);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,215 @@
+use anyhow::{anyhow, bail};
+use eightfish_sdk::{HandlerCRUD, Info, Module, Request, Response, Result, Router, Status};
+use spin_sdk::pg::{self, ParameterValue};
+use sql_builder::SqlBuilder;
+
+use crate::comment::GutpCommentModule;
+use crate::constants::{DB_URL_ENV, REPORT_REASON_MAX_LEN};
+use crate::member::GutpMemberModule;
+use crate::post::GutpPostModule;
+use crate::reportcase::{GutpReportCaseModule, GutpReportCaseStatus};
+use crate::subspace::GutpSubspaceModule;
+use crate::user::GutpUserModule;
+use crate::utils;
+use gutp_types::{GutpComment, GutpPost, GutpReport, GutpReportCase, GutpSubspace, GutpUser};
+
+pub struct GutpReportModule;
+
+impl GutpReportModule {
+    /// The subspace whose moderators handle reports on a target. Users and
+    /// subspaces are not in one, their reports go to the app admins.
+    fn get_target_subspace_id(
+        pg_conn: &pg::Connection,
+        app_id: &str,
+        target_model: &str,
+        target_id: &str,
+        caller_id: &str,
+    ) -> Result<String> {
+        let subspace_id = if target_model == GutpPost::model_name() {
+            GutpPostModule::get_post_by_id(pg_conn, app_id, target_id)?.subspace_id
+        } else if target_model == GutpComment::model_name() {
+            let comment = GutpCommentModule::get_comment_by_id(pg_conn, app_id, target_id)?;
+            GutpPostModule::get_post_by_id(pg_conn, app_id, &comment.post_id)?.subspace_id
+        } else if target_model == GutpUser::model_name() {
+            if target_id == caller_id {
+                bail!("can not report yourself");
+            }
+            _ = GutpUserModule::get_user_by_id(pg_conn, app_id, target_id)?;
+            return Ok("".to_string());
+        } else if target_model == GutpSubspace::model_name() {
+            _ = GutpSubspaceModule::get_subspace_by_id(pg_conn, app_id, target_id)?;
+            return Ok("".to_string());
+        } else {
+            bail!("only posts, comments, users and subspaces can be reported");
+        };
+
+        GutpMemberModule::check_readable(pg_conn, app_id, &subspace_id, Some(caller_id))?;
+        Ok(subspace_id)
+    }
+
+    /// Report a target. Reports on the same target are grouped into one case
+    /// of the moderation queue while it is open, which is returned in extra.
+    fn new_one(req: &mut Request) -> Result<Response> {
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let target_model = params
+            .get("target_model")
+            .ok_or(anyhow!("target_model is required"))?
+            .to_owned();
+        let target_id = params
+            .get("target_id")
+            .ok_or(anyhow!("target_id is required"))?
+            .to_owned();
+        let reason = params
+            .get("reason")
+            .ok_or(anyhow!("reason is required"))?
+            .trim()
+            .to_string();
+        if reason.is_empty() {
+            bail!("reason can not be empty");
+        }
+        if reason.len() > REPORT_REASON_MAX_LEN {
+            bail!("reason must be at most {REPORT_REASON_MAX_LEN} bytes");
+        }
+
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_caller_id(req)?;
+        let id = req
+            .ext()
+            .get("random_str")
+            .ok_or(anyhow!("generate id failed"))?
+            .to_owned();
+        let time = req
+            .ext()
+            .get("time")
+            .ok_or(anyhow!("generate time failed"))?
+            .parse::<i64>()?;
+
+        let subspace_id =
+            Self::get_target_subspace_id(&pg_conn, &app_id, &target_model, &target_id, &caller_id)?;
+
+        let (report, case) = utils::with_transaction(&pg_conn, |conn| {
+            let old_case =
+                GutpReportCaseModule::find_open_case(conn, &app_id, &target_model, &target_id)?;
+            let is_new_case = old_case.is_none();
+            let old_case = old_case.unwrap_or_else(|| GutpReportCase {
+                id: utils::derive_id(&id, "reportcase", 0),
+                app_id: app_id.clone(),
+                subspace_id: subspace_id.clone(),
+                target_model: target_model.clone(),
+                target_id: target_id.clone(),
+                report_count: 0,
+                status: GutpReportCaseStatus::Open as i16,
+                resolver_id: "".to_string(),
+                resolution: "".to_string(),
+                created_time: time,
+                updated_time: time,
+            });
+
+            let sql_params = [
+                ParameterValue::Str(old_case.id.clone()),
+                ParameterValue::Str(caller_id.clone()),
+            ];
+            let rowset = conn.query(
+                "SELECT id FROM gutpreport WHERE case_id = $1 AND reporter_id = $2",
+                &sql_params,
+            )?;
+            if !rowset.rows.is_empty() {
+                bail!("you have already reported this");
+            }
+
+            let report = GutpReport {
+                id: id.clone(),
+                case_id: old_case.id.clone(),
+                reporter_id: caller_id.clone(),
+                reason,
+                created_time: time,
+            };
+            let (sql, sql_params) = report.build_insert();
+            _ = conn.execute(&sql, &sql_params)?;
+
+            let case = GutpReportCase {
+                report_count: old_case.report_count + 1,
+                updated_time: time,
+                ..old_case
+            };
+            let (sql, sql_params) = if is_new_case {
+                case.build_insert()
+            } else {
+                case.build_update()
+            };
+            _ = conn.execute(&sql, &sql_params)?;
+
+            Ok((report, case))
+        })?;
+
+        let results: Vec<GutpReport> = vec![report];
+
+        let info = Info {
+            model_name: GutpReport::model_name(),
+            action: HandlerCRUD::Create,
+            extra: serde_json::to_string(&case)?,
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
+    /// The reports of a case, for the moderators handling it.
+    fn list_by_case(req: &mut Request) -> Result<Response> {
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let case_id = params
+            .get("case_id")
+            .ok_or(anyhow!("case_id is required"))?;
+        let (limit, offset) = utils::build_page_info(&params)?;
+
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_caller_id(req)?;
+        let case = GutpReportCaseModule::get_case_by_id(&pg_conn, &app_id, case_id)?;
+        GutpReportCaseModule::check_queue_moderator(
+            &pg_conn,
+            &app_id,
+            &case.subspace_id,
+            &caller_id,
+        )?;
+
+        let sql = SqlBuilder::select_from(&GutpReport::model_name())
+            .fields(&GutpReport::fields())
+            .and_where_eq("case_id", "$1")
+            .order_desc("created_time")
+            .limit(limit)
+            .offset(offset)
+            .sql()?;
+        let sql_param = ParameterValue::Str(case.id);
+        let rowset = pg_conn.query(&sql, &[sql_param])?;
+
+        let mut results: Vec<GutpReport> = vec![];
+        for row in rowset.rows {
+            let sp = GutpReport::from_row(row);
+            results.push(sp);
+        }
+
+        let info = Info {
+            model_name: GutpReport::model_name(),
+            action: HandlerCRUD::List,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+}
+
+impl Module for GutpReportModule {
+    fn router(&self, router: &mut Router) -> Result<()> {
+        router.get("/gutp/v1/report/list_by_case", Self::list_by_case);
+        router.post("/gutp/v1/report/create", Self::new_one);
+
+        Ok(())
+    }
+}
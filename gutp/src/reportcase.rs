@@ -0,0 +1,251 @@
+use anyhow::{anyhow, bail};
+use eightfish_sdk::{HandlerCRUD, Info, Module, Request, Response, Result, Router, Status};
+use spin_sdk::pg::{self, ParameterValue};
+use sql_builder::SqlBuilder;
+
+use crate::comment::GutpCommentModule;
+use crate::constants::DB_URL_ENV;
+use crate::moderator::GutpModeratorModule;
+use crate::modlog::GutpModLogModule;
+use crate::post::GutpPostModule;
+use crate::subspace::GutpSubspaceModule;
+use crate::user::GutpUserModule;
+use crate::utils;
+use gutp_types::{GutpComment, GutpModLog, GutpPost, GutpReportCase, GutpSubspace, GutpUser};
+
+pub(crate) enum GutpReportCaseStatus {
+    Open = 0,
+    Dismissed = 1,
+    Actioned = 2,
+}
+
+pub struct GutpReportCaseModule;
+
+impl GutpReportCaseModule {
+    pub(crate) fn get_case_by_id(
+        pg_conn: &pg::Connection,
+        app_id: &str,
+        id: &str,
+    ) -> Result<GutpReportCase> {
+        let (sql, sql_params) = GutpReportCase::build_get_by_id(id);
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+        match rowset.rows.into_iter().next().map(GutpReportCase::from_row) {
+            Some(case) if case.app_id == app_id => Ok(case),
+            _ => bail!("no such report case: {id}"),
+        }
+    }
+
+    /// The case of a target still waiting in the queue, if there is one.
+    pub(crate) fn find_open_case(
+        pg_conn: &pg::Connection,
+        app_id: &str,
+        target_model: &str,
+        target_id: &str,
+    ) -> Result<Option<GutpReportCase>> {
+        let sql = SqlBuilder::select_from(&GutpReportCase::model_name())
+            .fields(&GutpReportCase::fields())
+            .and_where_eq("app_id", "$1")
+            .and_where_eq("target_model", "$2")
+            .and_where_eq("target_id", "$3")
+            .and_where_eq("status", GutpReportCaseStatus::Open as i16)
+            .sql()?;
+        let sql_params = [
+            ParameterValue::Str(app_id.to_owned()),
+            ParameterValue::Str(target_model.to_owned()),
+            ParameterValue::Str(target_id.to_owned()),
+        ];
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+
+        Ok(rowset.rows.into_iter().next().map(GutpReportCase::from_row))
+    }
+
+    /// Cases in a subspace are handled by its moderators, the ones outside of
+    /// any subspace, on users and subspaces, by the app admins.
+    pub(crate) fn check_queue_moderator(
+        pg_conn: &pg::Connection,
+        app_id: &str,
+        subspace_id: &str,
+        user_id: &str,
+    ) -> Result<()> {
+        if subspace_id.is_empty() {
+            GutpUserModule::check_admin(pg_conn, app_id, user_id)
+        } else {
+            GutpModeratorModule::check_subspace_moderator(pg_conn, app_id, subspace_id, user_id)
+        }
+    }
+
+    /// Set the status of a reported target, returning the status it had.
+    fn set_target_status(
+        pg_conn: &pg::Connection,
+        app_id: &str,
+        case: &GutpReportCase,
+        status: i16,
+    ) -> Result<i16> {
+        let target_id = &case.target_id;
+        if case.target_model == GutpPost::model_name() {
+            GutpPostModule::set_status(pg_conn, app_id, target_id, status)
+        } else if case.target_model == GutpComment::model_name() {
+            GutpCommentModule::set_status(pg_conn, app_id, target_id, status)
+        } else if case.target_model == GutpUser::model_name() {
+            GutpUserModule::set_status(pg_conn, app_id, target_id, status)
+        } else if case.target_model == GutpSubspace::model_name() {
+            GutpSubspaceModule::set_status(pg_conn, app_id, target_id, status)
+        } else {
+            bail!("unknown report target: {}", case.target_model);
+        }
+    }
+
+    /// The moderation queue of a subspace, or without `subspace_id` the one
+    /// of the app admins. Open cases by default, the most reported first.
+    fn queue(req: &mut Request) -> Result<Response> {
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let subspace_id = params
+            .get("subspace_id")
+            .unwrap_or(&"".to_string())
+            .to_owned();
+        let status = params
+            .get("status")
+            .unwrap_or(&(GutpReportCaseStatus::Open as i16).to_string())
+            .parse::<i16>()?;
+        let (limit, offset) = utils::build_page_info(&params)?;
+
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_caller_id(req)?;
+        Self::check_queue_moderator(&pg_conn, &app_id, &subspace_id, &caller_id)?;
+
+        let sql = SqlBuilder::select_from(&GutpReportCase::model_name())
+            .fields(&GutpReportCase::fields())
+            .and_where_eq("app_id", "$1")
+            .and_where_eq("subspace_id", "$2")
+            .and_where_eq("status", "$3")
+            .order_desc("report_count")
+            .order_desc("updated_time")
+            .limit(limit)
+            .offset(offset)
+            .sql()?;
+        let sql_params = [
+            ParameterValue::Str(app_id),
+            ParameterValue::Str(subspace_id),
+            ParameterValue::Int16(status),
+        ];
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+
+        let mut results: Vec<GutpReportCase> = vec![];
+        for row in rowset.rows {
+            let sp = GutpReportCase::from_row(row);
+            results.push(sp);
+        }
+
+        let info = Info {
+            model_name: GutpReportCase::model_name(),
+            action: HandlerCRUD::List,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
+    /// Resolve an open case, by a moderator of its queue. `action` is
+    /// `dismiss`, or `set_status` which sets `status` on the target.
+    fn resolve(req: &mut Request) -> Result<Response> {
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let id = params.get("id").ok_or(anyhow!("id is required"))?;
+        let action = params.get("action").ok_or(anyhow!("action is required"))?;
+        let status = match action.as_str() {
+            "dismiss" => None,
+            "set_status" => Some(
+                params
+                    .get("status")
+                    .ok_or(anyhow!("status is required"))?
+                    .parse::<i16>()?,
+            ),
+            _ => bail!("action must be dismiss or set_status"),
+        };
+
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_caller_id(req)?;
+        let actor = GutpModLogModule::get_actor(req, &params)?;
+        let time = req
+            .ext()
+            .get("time")
+            .ok_or(anyhow!("time is required"))?
+            .parse::<i64>()?;
+
+        let case = utils::with_transaction(&pg_conn, |conn| {
+            let old_case = Self::get_case_by_id(conn, &app_id, id)?;
+            Self::check_queue_moderator(conn, &app_id, &old_case.subspace_id, &caller_id)?;
+            if old_case.status != GutpReportCaseStatus::Open as i16 {
+                bail!("this report case is already resolved");
+            }
+
+            let (case_status, resolution) = match status {
+                Some(status) => {
+                    let old_status = Self::set_target_status(conn, &app_id, &old_case, status)?;
+                    let subspace_id = if old_case.target_model == GutpSubspace::model_name() {
+                        old_case.target_id.clone()
+                    } else {
+                        old_case.subspace_id.clone()
+                    };
+                    GutpModLogModule::record(
+                        conn,
+                        &actor,
+                        GutpModLog {
+                            subspace_id,
+                            target_model: old_case.target_model.clone(),
+                            target_id: old_case.target_id.clone(),
+                            action: "set_status".to_string(),
+                            before_value: old_status.to_string(),
+                            after_value: status.to_string(),
+                            ..Default::default()
+                        },
+                    )?;
+
+                    (
+                        GutpReportCaseStatus::Actioned,
+                        format!("set_status:{status}"),
+                    )
+                }
+                None => (GutpReportCaseStatus::Dismissed, "dismiss".to_string()),
+            };
+
+            let case = GutpReportCase {
+                status: case_status as i16,
+                resolver_id: caller_id.clone(),
+                resolution,
+                updated_time: time,
+                ..old_case
+            };
+            let (sql, sql_params) = case.build_update();
+            _ = conn.execute(&sql, &sql_params)?;
+
+            Ok(case)
+        })?;
+
+        let results: Vec<GutpReportCase> = vec![case];
+
+        let info = Info {
+            model_name: GutpReportCase::model_name(),
+            action: HandlerCRUD::Update,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+}
+
+impl Module for GutpReportCaseModule {
+    fn router(&self, router: &mut Router) -> Result<()> {
+        router.get("/gutp/v1/reportcase/queue", Self::queue);
+        router.post("/gutp/v1/reportcase/resolve", Self::resolve);
+
+        Ok(())
+    }
+}
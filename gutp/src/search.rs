@@ -0,0 +1,166 @@
+use anyhow::{anyhow, bail};
+use eightfish_sdk::{HandlerCRUD, Info, Module, Request, Response, Result, Router, Status};
+use serde::Serialize;
+use spin_sdk::pg::{self, DbValue, Decode, ParameterValue};
+use sql_builder::SqlBuilder;
+
+use crate::app::GutpAppModule;
+use crate::comment::GutpCommentStatus;
+use crate::constants::DB_URL_ENV;
+use crate::member::GutpMemberModule;
+use crate::post::GutpPostStatus;
+use crate::utils;
+use gutp_types::{GutpComment, GutpPost};
+
+// text search configuration, `simple` does no language specific stemming,
+// so it works for the content of every app
+const SEARCH_TS_CONFIG: &str = "simple";
+
+/// Rank and highlighted snippet of one search result, returned in extra.
+#[derive(Serialize)]
+struct SearchHit {
+    id: String,
+    rank: f64,
+    snippet: String,
+}
+
+pub struct GutpSearchModule;
+
+impl GutpSearchModule {
+    /// Full text search over posts (title and content) or comments (content),
+    /// only public content in normal status is searched.
+    fn search(req: &mut Request) -> Result<Response> {
+        GutpAppModule::check_feature(req, "search")?;
+
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let q = params.get("q").ok_or(anyhow!("q is required"))?;
+        let target = params.get("target").map(|t| t.as_str()).unwrap_or("post");
+        let (limit, offset) = utils::build_page_info(&params)?;
+
+        let (table, document, status) = match target {
+            "post" => (
+                GutpPost::model_name(),
+                "title || ' ' || content",
+                GutpPostStatus::Normal as i16,
+            ),
+            "comment" => (
+                GutpComment::model_name(),
+                "content",
+                GutpCommentStatus::Normal as i16,
+            ),
+            _ => bail!("target must be post or comment"),
+        };
+        let fields = match target {
+            "post" => GutpPost::fields(),
+            _ => GutpComment::fields(),
+        };
+        let vector = format!("to_tsvector('{SEARCH_TS_CONFIG}', {document})");
+
+        let mut sql_params = vec![ParameterValue::Str(q.clone())];
+        let mut builder = SqlBuilder::select_from(format!(
+            "{table}, websearch_to_tsquery('{SEARCH_TS_CONFIG}', $1) AS query"
+        ));
+        builder
+            .fields(&fields)
+            .field(format!("ts_rank({vector}, query)::float8 AS rank"))
+            .field(format!(
+                "ts_headline('{SEARCH_TS_CONFIG}', content, query, 'MaxFragments=2, StartSel=<mark>, StopSel=</mark>') AS snippet"
+            ))
+            .and_where(format!("{vector} @@ query"))
+            .and_where_eq("is_public", "true")
+            .and_where_eq("status", status);
+
+        // filters on the post itself, for comments they go through the post they belong to
+        sql_params.push(ParameterValue::Str(utils::get_app_id(req)?));
+        let mut post_conds: Vec<String> = vec![format!("app_id = ${}", sql_params.len())];
+        if let Some(subspace_id) = params.get("subspace_id") {
+            sql_params.push(ParameterValue::Str(subspace_id.clone()));
+            post_conds.push(format!("subspace_id = ${}", sql_params.len()));
+        }
+        if let Some(tag_id) = params.get("tag_id") {
+            sql_params.push(ParameterValue::Str(tag_id.clone()));
+            post_conds.push(format!(
+                "id IN (SELECT post_id FROM gutpposttag WHERE tag_id = ${})",
+                sql_params.len()
+            ));
+        }
+        // private subspaces are only searchable by their members
+        let caller_id = utils::get_optional_caller_id(req).unwrap_or_default();
+        sql_params.push(ParameterValue::Str(caller_id));
+        post_conds.push(GutpMemberModule::build_readable_cond(
+            "subspace_id",
+            &format!("${}", sql_params.len()),
+        ));
+        if target == "post" {
+            for cond in post_conds {
+                builder.and_where(cond);
+            }
+        } else {
+            builder.and_where(format!(
+                "post_id IN (SELECT id FROM gutppost WHERE {})",
+                post_conds.join(" AND ")
+            ));
+        }
+
+        if let Some(author_id) = params.get("author_id") {
+            sql_params.push(ParameterValue::Str(author_id.clone()));
+            builder.and_where_eq("author_id", format!("${}", sql_params.len()));
+        }
+        if let Some(from) = params.get("from") {
+            sql_params.push(ParameterValue::Int64(from.parse::<i64>()?));
+            builder.and_where_ge("created_time", format!("${}", sql_params.len()));
+        }
+        if let Some(to) = params.get("to") {
+            sql_params.push(ParameterValue::Int64(to.parse::<i64>()?));
+            builder.and_where_lt("created_time", format!("${}", sql_params.len()));
+        }
+
+        let sql = builder
+            .order_desc("rank")
+            .order_desc("created_time")
+            .limit(limit)
+            .offset(offset)
+            .sql()?;
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+
+        let mut hits: Vec<SearchHit> = vec![];
+        let mut posts: Vec<GutpPost> = vec![];
+        let mut comments: Vec<GutpComment> = vec![];
+        for mut row in rowset.rows {
+            // the rank and snippet columns come after the model fields
+            let snippet = String::decode(&row.pop().unwrap_or(DbValue::DbNull))?;
+            let rank = f64::decode(&row.pop().unwrap_or(DbValue::DbNull))?;
+            let id = String::decode(&row[0])?;
+            hits.push(SearchHit { id, rank, snippet });
+            if target == "post" {
+                posts.push(GutpPost::from_row(row));
+            } else {
+                comments.push(GutpComment::from_row(row));
+            }
+        }
+
+        let info = Info {
+            model_name: table,
+            action: HandlerCRUD::List,
+            extra: serde_json::to_string(&hits)?,
+        };
+
+        if target == "post" {
+            Ok(Response::new(Status::Successful, info, posts))
+        } else {
+            Ok(Response::new(Status::Successful, info, comments))
+        }
+    }
+}
+
+impl Module for GutpSearchModule {
+    fn router(&self, router: &mut Router) -> Result<()> {
+        router.get("/gutp/v1/search", Self::search);
+
+        Ok(())
+    }
+}
@@ -3,10 +3,17 @@ use eightfish_sdk::{HandlerCRUD, Info, Module, Request, Response, Result, Router
 use spin_sdk::pg::{self, ParameterValue};
 use sql_builder::SqlBuilder;
 
+use crate::category::GutpCategoryModule;
 use crate::constants::DB_URL_ENV;
+use crate::follow::GutpFollowModule;
+use crate::member::GutpMemberModule;
+use crate::modlog::GutpModLogModule;
+use crate::post::GutpPostModule;
+use crate::subspacekey::GutpSubspaceKeyModule;
+use crate::user::GutpUserModule;
 
 use crate::utils;
-use gutp_types::GutpSubspace;
+use gutp_types::{GutpModLog, GutpSubspace};
 
 enum GutpSubspaceStatus {
     Normal = 0,
@@ -28,6 +35,44 @@ enum GutpSubspaceWeight {
 pub struct GutpSubspaceModule;
 
 impl GutpSubspaceModule {
+    /// Load a subspace of an app. Subspaces of other apps are not found.
+    pub(crate) fn get_subspace_by_id(
+        pg_conn: &pg::Connection,
+        app_id: &str,
+        id: &str,
+    ) -> Result<GutpSubspace> {
+        let (sql, sql_params) = GutpSubspace::build_get_by_id(id);
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+        match rowset.rows.into_iter().next().map(GutpSubspace::from_row) {
+            Some(subspace) if subspace.app_id == app_id => Ok(subspace),
+            _ => bail!("no such subspace: {id}"),
+        }
+    }
+
+    /// Change the status of a subspace from moderation, returning the status
+    /// it had before.
+    pub(crate) fn set_status(
+        pg_conn: &pg::Connection,
+        app_id: &str,
+        id: &str,
+        status: i16,
+    ) -> Result<i16> {
+        if !(GutpSubspaceStatus::Normal as i16..=GutpSubspaceStatus::Deleted as i16)
+            .contains(&status)
+        {
+            bail!("status is out of range");
+        }
+        let old_subspace = Self::get_subspace_by_id(pg_conn, app_id, id)?;
+        let old_status = old_subspace.status;
+        let subspace = GutpSubspace {
+            status,
+            ..old_subspace
+        };
+        let (sql, sql_params) = subspace.build_update();
+        _ = pg_conn.execute(&sql, &sql_params)?;
+        Ok(old_status)
+    }
+
     fn get_one(req: &mut Request) -> Result<Response> {
         let pg_addr = std::env::var(DB_URL_ENV)?;
         let pg_conn = pg::Connection::open(&pg_addr)?;
@@ -35,6 +80,7 @@ impl GutpSubspaceModule {
         let params = req.parse_urlencoded()?;
 
         let subspace_id = params.get("id").ok_or(anyhow!("no id"))?;
+        let app_id = utils::get_app_id(req)?;
 
         let (sql, sql_params) = GutpSubspace::build_get_by_id(subspace_id);
         let rowset = pg_conn.query(&sql, &sql_params)?;
@@ -42,7 +88,9 @@ impl GutpSubspaceModule {
         let mut results: Vec<GutpSubspace> = vec![];
         for row in rowset.rows {
             let sp = GutpSubspace::from_row(row);
-            results.push(sp);
+            if sp.app_id == app_id {
+                results.push(sp);
+            }
         }
         // println!("in handler subspace get_one: results: {:?}", results);
 
@@ -61,14 +109,18 @@ impl GutpSubspaceModule {
 
         let params = req.parse_urlencoded()?;
 
+        let app_id = utils::get_app_id(req)?;
+
         let (limit, offset) = utils::build_page_info(&params)?;
         let sql = SqlBuilder::select_from(&GutpSubspace::model_name())
             .fields(&GutpSubspace::fields())
+            .and_where_eq("app_id", "$1")
             .order_desc("created_time")
             .limit(limit)
             .offset(offset)
             .sql()?;
-        let rowset = pg_conn.query(&sql, &[])?;
+        let sql_param = ParameterValue::Str(app_id);
+        let rowset = pg_conn.query(&sql, &[sql_param])?;
 
         let mut results: Vec<GutpSubspace> = vec![];
         for row in rowset.rows {
@@ -95,17 +147,22 @@ impl GutpSubspaceModule {
         let owner_id = params
             .get("owner_id")
             .ok_or(anyhow!("owner_id is required"))?;
+        let app_id = utils::get_app_id(req)?;
 
         let (limit, offset) = utils::build_page_info(&params)?;
         let sql = SqlBuilder::select_from(&GutpSubspace::model_name())
             .fields(&GutpSubspace::fields())
             .and_where_eq("owner_id", "$1")
+            .and_where_eq("app_id", "$2")
             .order_desc("created_time")
             .limit(limit)
             .offset(offset)
             .sql()?;
-        let sql_param = ParameterValue::Str(owner_id.clone());
-        let rowset = pg_conn.query(&sql, &[sql_param])?;
+        let sql_params = [
+            ParameterValue::Str(owner_id.clone()),
+            ParameterValue::Str(app_id),
+        ];
+        let rowset = pg_conn.query(&sql, &sql_params)?;
 
         let mut results: Vec<GutpSubspace> = vec![];
         for row in rowset.rows {
@@ -132,17 +189,22 @@ impl GutpSubspaceModule {
         let category = params
             .get("category")
             .ok_or(anyhow!("category is required"))?;
+        let app_id = utils::get_app_id(req)?;
 
         let (limit, offset) = utils::build_page_info(&params)?;
         let sql = SqlBuilder::select_from(&GutpSubspace::model_name())
             .fields(&GutpSubspace::fields())
             .and_where_eq("category", "$1")
+            .and_where_eq("app_id", "$2")
             .order_desc("created_time")
             .limit(limit)
             .offset(offset)
             .sql()?;
-        let sql_param = ParameterValue::Str(category.clone());
-        let rowset = pg_conn.query(&sql, &[sql_param])?;
+        let sql_params = [
+            ParameterValue::Str(category.clone()),
+            ParameterValue::Str(app_id),
+        ];
+        let rowset = pg_conn.query(&sql, &sql_params)?;
 
         let mut results: Vec<GutpSubspace> = vec![];
         for row in rowset.rows {
@@ -165,7 +227,8 @@ impl GutpSubspaceModule {
 
         let params = req.parse_urlencoded()?;
 
-        let app_id = params.get("app_id").ok_or(anyhow!("app_id is required"))?;
+        // the global filter already checked it against the app context
+        let app_id = utils::get_app_id(req)?;
 
         let (limit, offset) = utils::build_page_info(&params)?;
         let sql = SqlBuilder::select_from(&GutpSubspace::model_name())
@@ -175,7 +238,7 @@ impl GutpSubspaceModule {
             .limit(limit)
             .offset(offset)
             .sql()?;
-        let sql_param = ParameterValue::Str(app_id.clone());
+        let sql_param = ParameterValue::Str(app_id);
         let rowset = pg_conn.query(&sql, &[sql_param])?;
 
         let mut results: Vec<GutpSubspace> = vec![];
@@ -193,6 +256,7 @@ impl GutpSubspaceModule {
         Ok(Response::new(Status::Successful, info, results))
     }
 
+    /// Create a subspace owned by the caller.
     fn new_one(req: &mut Request) -> Result<Response> {
         let pg_addr = std::env::var(DB_URL_ENV)?;
         let pg_conn = pg::Connection::open(&pg_addr)?;
@@ -211,17 +275,9 @@ impl GutpSubspaceModule {
             .get("banner")
             .ok_or(anyhow!("missing banner"))?
             .to_owned();
-        let owner_id = params
-            .get("owner_id")
-            .ok_or(anyhow!("missing owner_id"))?
-            .to_owned();
         let category = params
             .get("category")
-            .ok_or(anyhow!("missing profession"))?
-            .to_owned();
-        let app_id = params
-            .get("app_id")
-            .ok_or(anyhow!("missing appid"))?
+            .ok_or(anyhow!("missing category"))?
             .to_owned();
         let is_public = params
             .get("is_public")
@@ -241,6 +297,11 @@ impl GutpSubspaceModule {
             .get("time")
             .ok_or(anyhow!("failed get time"))?
             .parse::<i64>()?;
+        let app_id = utils::get_app_id(req)?;
+        let owner_id = utils::get_caller_id(req)?;
+        _ = GutpUserModule::get_user_by_id(&pg_conn, &app_id, &owner_id)?;
+        GutpCategoryModule::check_category(&pg_conn, &app_id, &category)?;
+
         let subspace = GutpSubspace {
             id,
             title,
@@ -250,6 +311,8 @@ impl GutpSubspaceModule {
             category,
             app_id,
             is_public,
+            key_version: 0,
+            rekey_required: false,
             status: GutpSubspaceStatus::Normal as i16,
             weight: GutpSubspaceWeight::Normal as i16,
             created_time: time,
@@ -270,6 +333,7 @@ impl GutpSubspaceModule {
         Ok(Response::new(Status::Successful, info, results))
     }
 
+    /// Change the description of a subspace, by its owner or an app admin.
     fn update(req: &mut Request) -> Result<Response> {
         let pg_addr = std::env::var(DB_URL_ENV)?;
         let pg_conn = pg::Connection::open(&pg_addr)?;
@@ -289,22 +353,13 @@ impl GutpSubspaceModule {
             .get("banner")
             .ok_or(anyhow!("banner is required"))?
             .to_owned();
-        let owner_id = params
-            .get("owner_id")
-            .ok_or(anyhow!("owner_id is required"))?
-            .to_owned();
         let category = params
             .get("category")
-            .ok_or(anyhow!("profession is required"))?
-            .to_owned();
-        let app_id = params
-            .get("app_id")
-            .ok_or(anyhow!("appid is required"))?
+            .ok_or(anyhow!("category is required"))?
             .to_owned();
-        let is_public = params
-            .get("is_public")
-            .ok_or(anyhow!("is_public is required"))?
-            .parse::<bool>()?;
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_caller_id(req)?;
+        GutpCategoryModule::check_category(&pg_conn, &app_id, &category)?;
         // let time = req
         //     .ext()
         //     .get("time")
@@ -314,19 +369,19 @@ impl GutpSubspaceModule {
         // get the item from db, check whether obj in db
         let (sql, sql_params) = GutpSubspace::build_get_by_id(id);
         let rowset = pg_conn.query(&sql, &sql_params)?;
-        match rowset.rows.into_iter().next() {
-            Some(row) => {
-                let old_subspace = GutpSubspace::from_row(row);
-
-                // TODO: update new obj with old
+        match rowset.rows.into_iter().next().map(GutpSubspace::from_row) {
+            // a subspace never moves to another app
+            Some(old_subspace) if old_subspace.app_id == app_id => {
+                if old_subspace.owner_id != caller_id {
+                    GutpUserModule::check_admin(&pg_conn, &app_id, &caller_id)?;
+                }
+                // the owner and privacy stay, as membership and the group key
+                // depend on them
                 let subspace = GutpSubspace {
                     title,
                     description,
                     banner,
-                    owner_id,
                     category,
-                    app_id,
-                    is_public,
                     ..old_subspace
                 };
 
@@ -343,12 +398,74 @@ impl GutpSubspaceModule {
 
                 Ok(Response::new(Status::Successful, info, results))
             }
-            None => {
+            _ => {
                 bail!("update action: no item in db");
             }
         }
     }
 
+    /// Promote or demote a subspace in the network, by an admin.
+    fn set_weight(req: &mut Request) -> Result<Response> {
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let id = params.get("id").ok_or(anyhow!("id is required"))?;
+        let weight = params
+            .get("weight")
+            .ok_or(anyhow!("weight is required"))?
+            .parse::<i16>()?;
+        if !(GutpSubspaceWeight::SuperLow as i16..=GutpSubspaceWeight::SuperHigh as i16)
+            .contains(&weight)
+        {
+            bail!("weight is out of range");
+        }
+
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_caller_id(req)?;
+        let actor = GutpModLogModule::get_actor(req, &params)?;
+
+        let subspace = utils::with_transaction(&pg_conn, |conn| {
+            GutpUserModule::check_admin(conn, &app_id, &caller_id)?;
+            let old_subspace = Self::get_subspace_by_id(conn, &app_id, id)?;
+
+            GutpModLogModule::record(
+                conn,
+                &actor,
+                GutpModLog {
+                    subspace_id: old_subspace.id.clone(),
+                    target_model: GutpSubspace::model_name(),
+                    target_id: old_subspace.id.clone(),
+                    action: "set_weight".to_string(),
+                    before_value: old_subspace.weight.to_string(),
+                    after_value: weight.to_string(),
+                    ..Default::default()
+                },
+            )?;
+
+            let subspace = GutpSubspace {
+                weight,
+                ..old_subspace
+            };
+            let (sql, sql_params) = subspace.build_update();
+            _ = conn.execute(&sql, &sql_params)?;
+            GutpPostModule::refresh_subspace_scores(conn, &subspace.id)?;
+
+            Ok(subspace)
+        })?;
+
+        let results: Vec<GutpSubspace> = vec![subspace];
+
+        let info = Info {
+            model_name: GutpSubspace::model_name(),
+            action: HandlerCRUD::Update,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
     fn delete(req: &mut Request) -> Result<Response> {
         let pg_addr = std::env::var(DB_URL_ENV)?;
         let pg_conn = pg::Connection::open(&pg_addr)?;
@@ -356,9 +473,34 @@ impl GutpSubspaceModule {
         let params = req.parse_urlencoded()?;
 
         let id = params.get("id").ok_or(anyhow!("missing id"))?;
-
-        let (sql_statement, sql_params) = GutpSubspace::build_delete(id);
-        _ = pg_conn.execute(&sql_statement, &sql_params)?;
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_caller_id(req)?;
+        let actor = GutpModLogModule::get_actor(req, &params)?;
+
+        utils::with_transaction(&pg_conn, |conn| {
+            let subspace = Self::get_subspace_by_id(conn, &app_id, id)?;
+            if subspace.owner_id != caller_id {
+                GutpUserModule::check_admin(conn, &app_id, &caller_id)?;
+                GutpModLogModule::record(
+                    conn,
+                    &actor,
+                    GutpModLog {
+                        subspace_id: "".to_string(),
+                        target_model: GutpSubspace::model_name(),
+                        target_id: subspace.id.clone(),
+                        action: "delete".to_string(),
+                        before_value: subspace.status.to_string(),
+                        after_value: "".to_string(),
+                        ..Default::default()
+                    },
+                )?;
+            }
+            let (sql_statement, sql_params) = GutpSubspace::build_delete(id);
+            _ = conn.execute(&sql_statement, &sql_params)?;
+            GutpMemberModule::delete_by_subspace(conn, id)?;
+            GutpSubspaceKeyModule::delete_by_subspace(conn, id)?;
+            GutpFollowModule::delete_by_target(conn, &GutpSubspace::model_name(), id)
+        })?;
 
         let info = Info {
             model_name: GutpSubspace::model_name(),
@@ -380,6 +522,7 @@ impl Module for GutpSubspaceModule {
         router.get("/gutp/v1/subspace/list_by_app_id", Self::list_by_app_id);
         router.post("/gutp/v1/subspace/create", Self::new_one);
         router.post("/gutp/v1/subspace/update", Self::update);
+        router.post("/gutp/v1/subspace/set_weight", Self::set_weight);
         router.post("/gutp/v1/subspace/delete", Self::delete);
 
         Ok(())
@@ -0,0 +1,350 @@
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, bail};
+use eightfish_sdk::{HandlerCRUD, Info, Module, Request, Response, Result, Router, Status};
+use serde::Serialize;
+use spin_sdk::pg::{self, Decode, ParameterValue};
+use sql_builder::SqlBuilder;
+
+use crate::constants::DB_URL_ENV;
+use crate::envelope;
+use crate::member::GutpMemberModule;
+use crate::subspace::GutpSubspaceModule;
+use crate::user::GutpUserModule;
+use crate::utils;
+use gutp_types::{GutpSubspace, GutpSubspaceKey, GutpUser};
+
+/// Which group key version the listed members need a wrapped key of,
+/// returned in extra, with the members skipped as they have no public key
+/// to wrap it with yet.
+#[derive(Serialize)]
+struct MissingExtra {
+    key_version: i64,
+    keyless_ids: Vec<String>,
+}
+
+pub struct GutpSubspaceKeyModule;
+
+impl GutpSubspaceKeyModule {
+    /// Drop the wrapped keys of a member who left or was banned, and mark the
+    /// group key of the subspace for rotation, as the member still knows it.
+    pub(crate) fn revoke_member(
+        pg_conn: &pg::Connection,
+        subspace: &GutpSubspace,
+        user_id: &str,
+    ) -> Result<()> {
+        let sql_params = [
+            ParameterValue::Str(subspace.id.clone()),
+            ParameterValue::Str(user_id.to_owned()),
+        ];
+        _ = pg_conn.execute(
+            "DELETE FROM gutpsubspacekey WHERE subspace_id = $1 AND user_id = $2",
+            &sql_params,
+        )?;
+
+        if subspace.key_version > 0 && !subspace.rekey_required {
+            let subspace = GutpSubspace {
+                rekey_required: true,
+                ..subspace.clone()
+            };
+            let (sql, sql_params) = subspace.build_update();
+            _ = pg_conn.execute(&sql, &sql_params)?;
+        }
+
+        Ok(())
+    }
+
+    /// Remove all the wrapped keys of a subspace, when the subspace is deleted.
+    pub(crate) fn delete_by_subspace(pg_conn: &pg::Connection, subspace_id: &str) -> Result<()> {
+        let sql_param = ParameterValue::Str(subspace_id.to_owned());
+        _ = pg_conn.execute(
+            "DELETE FROM gutpsubspacekey WHERE subspace_id = $1",
+            &[sql_param],
+        )?;
+        Ok(())
+    }
+
+    /// The members who have a wrapped key of a version.
+    fn get_wrapped_user_ids(
+        pg_conn: &pg::Connection,
+        subspace_id: &str,
+        key_version: i64,
+    ) -> Result<Vec<String>> {
+        let sql = SqlBuilder::select_from(&GutpSubspaceKey::model_name())
+            .field("user_id")
+            .and_where_eq("subspace_id", "$1")
+            .and_where_eq("key_version", "$2")
+            .sql()?;
+        let sql_params = [
+            ParameterValue::Str(subspace_id.to_owned()),
+            ParameterValue::Int64(key_version),
+        ];
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+
+        let mut user_ids: Vec<String> = vec![];
+        for row in rowset.rows {
+            user_ids.push(String::decode(&row[0])?);
+        }
+
+        Ok(user_ids)
+    }
+
+    /// Split the members into the ones with a public key and the ones
+    /// without, who can not be given a wrapped key until they set one.
+    fn split_keyless(
+        pg_conn: &pg::Connection,
+        member_ids: Vec<String>,
+    ) -> Result<(Vec<String>, Vec<String>)> {
+        let placeholders = utils::build_placeholders(1, member_ids.len());
+        let sql_params: Vec<ParameterValue> = member_ids
+            .iter()
+            .map(|member_id| ParameterValue::Str(member_id.clone()))
+            .collect();
+        let sql =
+            format!("SELECT id FROM gutpuser WHERE public_key = '' AND id IN ({placeholders})");
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+
+        let mut keyless_ids: Vec<String> = vec![];
+        for row in rowset.rows {
+            keyless_ids.push(String::decode(&row[0])?);
+        }
+
+        Ok(member_ids
+            .into_iter()
+            .partition(|member_id| !keyless_ids.contains(member_id)))
+    }
+
+    /// The caller's wrapped group key of a subspace, by default of the
+    /// current version.
+    fn get_mine(req: &mut Request) -> Result<Response> {
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let subspace_id = params
+            .get("subspace_id")
+            .ok_or(anyhow!("subspace_id is required"))?;
+        let app_id = utils::get_app_id(req)?;
+        let user_id = utils::get_caller_id(req)?;
+
+        let subspace = GutpSubspaceModule::get_subspace_by_id(&pg_conn, &app_id, subspace_id)?;
+        if !GutpMemberModule::is_active_member(&pg_conn, &subspace, &user_id)? {
+            bail!("only members can get the group key of this subspace");
+        }
+        let key_version = match params.get("key_version") {
+            Some(key_version) => key_version.parse::<i64>()?,
+            None => subspace.key_version,
+        };
+
+        let sql = SqlBuilder::select_from(&GutpSubspaceKey::model_name())
+            .fields(&GutpSubspaceKey::fields())
+            .and_where_eq("subspace_id", "$1")
+            .and_where_eq("user_id", "$2")
+            .and_where_eq("key_version", "$3")
+            .sql()?;
+        let sql_params = [
+            ParameterValue::Str(subspace_id.clone()),
+            ParameterValue::Str(user_id),
+            ParameterValue::Int64(key_version),
+        ];
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+
+        let results = if let Some(row) = rowset.rows.into_iter().next() {
+            vec![GutpSubspaceKey::from_row(row)]
+        } else {
+            bail!("no group key of version {key_version} is wrapped for you yet");
+        };
+
+        let info = Info {
+            model_name: GutpSubspaceKey::model_name(),
+            action: HandlerCRUD::GetOne,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
+    /// The members an admin needs to wrap the group key for, with their
+    /// public keys. When the key has to be rotated, or there is none yet, that
+    /// is every member, for the next version. Members without a public key
+    /// are not listed but returned in extra.
+    fn list_missing(req: &mut Request) -> Result<Response> {
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let subspace_id = params
+            .get("subspace_id")
+            .ok_or(anyhow!("subspace_id is required"))?;
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_caller_id(req)?;
+
+        let subspace = GutpSubspaceModule::get_subspace_by_id(&pg_conn, &app_id, subspace_id)?;
+        GutpMemberModule::check_member_admin(&pg_conn, &subspace, &caller_id)?;
+
+        let member_ids = GutpMemberModule::get_active_member_ids(&pg_conn, &subspace)?;
+        let (member_ids, keyless_ids) = Self::split_keyless(&pg_conn, member_ids)?;
+        let (key_version, wrapped_ids) = if subspace.key_version == 0 || subspace.rekey_required {
+            (subspace.key_version + 1, vec![])
+        } else {
+            let wrapped_ids =
+                Self::get_wrapped_user_ids(&pg_conn, subspace_id, subspace.key_version)?;
+            (subspace.key_version, wrapped_ids)
+        };
+
+        let mut results: Vec<GutpUser> = vec![];
+        for member_id in member_ids {
+            if !wrapped_ids.contains(&member_id) {
+                results.push(GutpUserModule::get_user_by_id(
+                    &pg_conn, &app_id, &member_id,
+                )?);
+            }
+        }
+
+        let info = Info {
+            model_name: GutpUser::model_name(),
+            action: HandlerCRUD::List,
+            extra: serde_json::to_string(&MissingExtra {
+                key_version,
+                keyless_ids,
+            })?,
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
+    /// Submit group keys wrapped for members, by an admin. `keys` is a json
+    /// object of user id to base64 wrapped key.
+    ///
+    /// With `key_version` one above the current version it rotates the group
+    /// key, and must cover exactly the current members with a public key,
+    /// the others get theirs once they set one. With the current version it
+    /// adds the keys of members who have none yet.
+    fn rekey(req: &mut Request) -> Result<Response> {
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let subspace_id = params
+            .get("subspace_id")
+            .ok_or(anyhow!("subspace_id is required"))?
+            .to_owned();
+        let key_version = params
+            .get("key_version")
+            .ok_or(anyhow!("key_version is required"))?
+            .parse::<i64>()?;
+        let keys = params.get("keys").ok_or(anyhow!("keys is required"))?;
+        let keys: BTreeMap<String, String> =
+            serde_json::from_str(keys).map_err(|_| anyhow!("keys is malformed"))?;
+        if keys.is_empty() {
+            bail!("keys must not be empty");
+        }
+        for wrapped_key in keys.values() {
+            if envelope::base64_decoded_len(wrapped_key)? == 0 {
+                bail!("wrapped keys must not be empty");
+            }
+        }
+
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_caller_id(req)?;
+        let random_str = req
+            .ext()
+            .get("random_str")
+            .ok_or(anyhow!("random_str is required"))?
+            .to_owned();
+        let time = req
+            .ext()
+            .get("time")
+            .ok_or(anyhow!("time is required"))?
+            .parse::<i64>()?;
+
+        let subspace_keys = utils::with_transaction(&pg_conn, |conn| {
+            let subspace = GutpSubspaceModule::get_subspace_by_id(conn, &app_id, &subspace_id)?;
+            GutpMemberModule::check_member_admin(conn, &subspace, &caller_id)?;
+
+            let member_ids = GutpMemberModule::get_active_member_ids(conn, &subspace)?;
+            if let Some(user_id) = keys.keys().find(|user_id| !member_ids.contains(user_id)) {
+                bail!("{user_id} is not a member of this subspace");
+            }
+            let (member_ids, keyless_ids) = Self::split_keyless(conn, member_ids)?;
+            if let Some(user_id) = keys.keys().find(|user_id| keyless_ids.contains(user_id)) {
+                bail!("{user_id} has no public key to wrap the group key with");
+            }
+
+            let is_rotation = key_version == subspace.key_version + 1;
+            if is_rotation {
+                if member_ids
+                    .iter()
+                    .any(|member_id| !keys.contains_key(member_id))
+                {
+                    bail!("a rotated group key must be wrapped for every member with a public key");
+                }
+            } else if key_version == subspace.key_version && key_version > 0 {
+                if subspace.rekey_required {
+                    bail!("the group key must be rotated first");
+                }
+                let wrapped_ids = Self::get_wrapped_user_ids(conn, &subspace_id, key_version)?;
+                if let Some(user_id) = keys.keys().find(|user_id| wrapped_ids.contains(user_id)) {
+                    bail!("the group key is already wrapped for {user_id}");
+                }
+            } else {
+                bail!(
+                    "key_version must be {} to add keys or {} to rotate",
+                    subspace.key_version,
+                    subspace.key_version + 1
+                );
+            }
+
+            let mut subspace_keys: Vec<GutpSubspaceKey> = vec![];
+            for (index, (user_id, wrapped_key)) in keys.into_iter().enumerate() {
+                let subspace_key = GutpSubspaceKey {
+                    id: utils::derive_id(&random_str, "subspacekey", index),
+                    subspace_id: subspace_id.clone(),
+                    user_id,
+                    key_version,
+                    wrapped_key,
+                    wrapper_id: caller_id.clone(),
+                    created_time: time,
+                };
+                let (sql, sql_params) = subspace_key.build_insert();
+                _ = conn.execute(&sql, &sql_params)?;
+                subspace_keys.push(subspace_key);
+            }
+
+            if is_rotation {
+                let subspace = GutpSubspace {
+                    key_version,
+                    rekey_required: false,
+                    ..subspace
+                };
+                let (sql, sql_params) = subspace.build_update();
+                _ = conn.execute(&sql, &sql_params)?;
+            }
+
+            Ok(subspace_keys)
+        })?;
+
+        let results: Vec<GutpSubspaceKey> = subspace_keys;
+
+        let info = Info {
+            model_name: GutpSubspaceKey::model_name(),
+            action: HandlerCRUD::Create,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+}
+
+impl Module for GutpSubspaceKeyModule {
+    fn router(&self, router: &mut Router) -> Result<()> {
+        router.get("/gutp/v1/subspacekey/mine", Self::get_mine);
+        router.get("/gutp/v1/subspacekey/list_missing", Self::list_missing);
+        router.post("/gutp/v1/subspacekey/rekey", Self::rekey);
+
+        Ok(())
+    }
+}
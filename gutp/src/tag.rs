@@ -1,30 +1,318 @@
 use anyhow::{anyhow, bail};
+use caseless::Caseless;
 use eightfish_sdk::{HandlerCRUD, Info, Module, Request, Response, Result, Router, Status};
 use spin_sdk::pg::{self, ParameterValue};
 use sql_builder::SqlBuilder;
+use unicode_normalization::UnicodeNormalization;
 
 use crate::constants::DB_URL_ENV;
+use crate::member::GutpMemberModule;
+use crate::moderator::GutpModeratorModule;
+use crate::modlog::GutpModLogModule;
+use crate::posttag::GutpPostTagModule;
 use crate::utils;
-use gutp_types::GutpTag;
+use gutp_types::{GutpModLog, GutpPost, GutpTag};
 const GUTP_TAG_WEIGHT_DEFAULT: i16 = 0;
 
+/// Trim a caption and collapse its inner whitespace, this is how it is shown.
+fn clean_caption(caption: &str) -> String {
+    caption.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+/// The case folded form of a caption, unique within a subspace. This is the
+/// compatibility caseless form of Unicode, so "STRASSE" and "straße" share it.
+fn caption_key(caption: &str) -> String {
+    clean_caption(caption)
+        .chars()
+        .nfd()
+        .default_case_fold()
+        .nfkd()
+        .default_case_fold()
+        .nfkd()
+        .collect()
+}
+
 pub struct GutpTagModule;
 
 impl GutpTagModule {
+    /// Load a tag of an app. Tags of other apps are not found.
+    pub(crate) fn get_tag_by_id(
+        pg_conn: &pg::Connection,
+        app_id: &str,
+        id: &str,
+    ) -> Result<GutpTag> {
+        let (sql, sql_params) = GutpTag::build_get_by_id(id);
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+        match rowset.rows.into_iter().next().map(GutpTag::from_row) {
+            Some(tag) if tag.app_id == app_id => Ok(tag),
+            _ => bail!("no such tag: {id}"),
+        }
+    }
+
+    /// Resolve a list of tag ids or captions within the subspace of a post.
+    /// Captions unknown to the subspace become new tags when `create_missing`
+    /// is set. Not for a non-public post though, as the new caption would be
+    /// stored in clear.
+    pub(crate) fn resolve_tags(
+        pg_conn: &pg::Connection,
+        post: &GutpPost,
+        entries: &[String],
+        create_missing: bool,
+        id_base: &str,
+        time: i64,
+    ) -> Result<Vec<GutpTag>> {
+        let app_id = &post.app_id;
+        let subspace_id = &post.subspace_id;
+        let mut tags: Vec<GutpTag> = vec![];
+        for (index, entry) in entries.iter().enumerate() {
+            let tag = if let Some(tag) =
+                Self::find_in_subspace(pg_conn, app_id, subspace_id, "id", entry)?
+            {
+                tag
+            } else if let Some(tag) = Self::find_in_subspace(
+                pg_conn,
+                app_id,
+                subspace_id,
+                "caption_key",
+                &caption_key(entry),
+            )? {
+                tag
+            } else if create_missing {
+                if !post.is_public {
+                    bail!("no tag {entry} in this subspace, tags of a non-public post must exist already");
+                }
+                let tag = GutpTag {
+                    id: utils::derive_id(id_base, "tag", index),
+                    caption: clean_caption(entry),
+                    caption_key: caption_key(entry),
+                    subspace_id: subspace_id.to_owned(),
+                    is_public: post.is_public,
+                    weight: GUTP_TAG_WEIGHT_DEFAULT,
+                    parent_tag_id: "".to_string(),
+                    alias_of: "".to_string(),
+                    post_count: 0,
+                    app_id: app_id.to_owned(),
+                    created_time: time,
+                };
+                let (sql, sql_params) = tag.build_insert();
+                _ = pg_conn.execute(&sql, &sql_params)?;
+                tag
+            } else {
+                bail!("no tag {entry} in this subspace");
+            };
+            let tag = Self::to_canonical(pg_conn, tag)?;
+
+            if !tags.iter().any(|t| t.id == tag.id) {
+                tags.push(tag);
+            }
+        }
+
+        Ok(tags)
+    }
+
+    /// The canonical tag of an alias, or the tag itself. Posts are always
+    /// linked to canonical tags.
+    pub(crate) fn to_canonical(pg_conn: &pg::Connection, tag: GutpTag) -> Result<GutpTag> {
+        if tag.alias_of.is_empty() {
+            Ok(tag)
+        } else {
+            Self::get_tag_by_id(pg_conn, &tag.app_id, &tag.alias_of)
+        }
+    }
+
+    /// Keep the post count of a tag in step with its post links.
+    pub(crate) fn add_post_count(pg_conn: &pg::Connection, tag_id: &str, delta: i64) -> Result<()> {
+        let sql_params = [
+            ParameterValue::Int64(delta),
+            ParameterValue::Str(tag_id.to_owned()),
+        ];
+        _ = pg_conn.execute(
+            "UPDATE gutptag SET post_count = post_count + $1 WHERE id = $2",
+            &sql_params,
+        )?;
+        Ok(())
+    }
+
+    fn check_caption_unique(pg_conn: &pg::Connection, tag: &GutpTag) -> Result<()> {
+        if tag.caption_key.is_empty() {
+            bail!("caption can not be empty");
+        }
+        if let Some(other) = Self::find_in_subspace(
+            pg_conn,
+            &tag.app_id,
+            &tag.subspace_id,
+            "caption_key",
+            &tag.caption_key,
+        )? {
+            if other.id != tag.id {
+                bail!("tag {} already exists in this subspace", other.caption);
+            }
+        }
+        Ok(())
+    }
+
+    fn find_in_subspace(
+        pg_conn: &pg::Connection,
+        app_id: &str,
+        subspace_id: &str,
+        field: &str,
+        value: &str,
+    ) -> Result<Option<GutpTag>> {
+        let sql = SqlBuilder::select_from(&GutpTag::model_name())
+            .fields(&GutpTag::fields())
+            .and_where_eq(field, "$1")
+            .and_where_eq("subspace_id", "$2")
+            .and_where_eq("app_id", "$3")
+            .sql()?;
+        let sql_params = [
+            ParameterValue::Str(value.to_owned()),
+            ParameterValue::Str(subspace_id.to_owned()),
+            ParameterValue::Str(app_id.to_owned()),
+        ];
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+
+        Ok(rowset.rows.into_iter().next().map(GutpTag::from_row))
+    }
+
+    /// The parent and the alias target of a tag must be in the same subspace,
+    /// an alias must point to a canonical tag, and the parent chain must not
+    /// loop back to the tag itself.
+    fn check_relations(pg_conn: &pg::Connection, tag: &GutpTag) -> Result<()> {
+        if !tag.alias_of.is_empty() {
+            let target = Self::get_tag_by_id(pg_conn, &tag.app_id, &tag.alias_of)?;
+            if target.subspace_id != tag.subspace_id {
+                bail!("alias target must be in the same subspace");
+            }
+            if target.id == tag.id || !target.alias_of.is_empty() {
+                bail!("alias target must be a canonical tag");
+            }
+        }
+
+        let mut parent_id = tag.parent_tag_id.clone();
+        while !parent_id.is_empty() {
+            if parent_id == tag.id {
+                bail!("a tag can not be its own ancestor");
+            }
+            let parent = Self::get_tag_by_id(pg_conn, &tag.app_id, &parent_id)?;
+            if parent.subspace_id != tag.subspace_id {
+                bail!("parent tag must be in the same subspace");
+            }
+            parent_id = parent.parent_tag_id;
+        }
+
+        Ok(())
+    }
+
+    /// Whether `tag` is `ancestor_id` itself or somewhere below it.
+    fn is_in_subtree(pg_conn: &pg::Connection, tag: &GutpTag, ancestor_id: &str) -> Result<bool> {
+        let mut tag_id = tag.id.clone();
+        let mut parent_id = tag.parent_tag_id.clone();
+        loop {
+            if tag_id == ancestor_id {
+                return Ok(true);
+            }
+            if parent_id.is_empty() {
+                return Ok(false);
+            }
+            let parent = Self::get_tag_by_id(pg_conn, &tag.app_id, &parent_id)?;
+            tag_id = parent.id;
+            parent_id = parent.parent_tag_id;
+        }
+    }
+
+    /// Move the post links, children and aliases of a tag that becomes an
+    /// alias over to its canonical tag. The canonical tag must not be below
+    /// the alias, or its new children would loop back to it.
+    fn move_into(pg_conn: &pg::Connection, from_tag: &GutpTag, into_tag: &GutpTag) -> Result<()> {
+        if Self::is_in_subtree(pg_conn, into_tag, &from_tag.id)? {
+            bail!("can not turn a tag into an alias of its own descendant");
+        }
+
+        let sql_params = [
+            ParameterValue::Str(from_tag.id.clone()),
+            ParameterValue::Str(into_tag.id.clone()),
+        ];
+        // posts already linked to the target just lose the old link
+        _ = pg_conn.execute(
+            "DELETE FROM gutpposttag WHERE tag_id = $1 AND post_id IN (SELECT post_id FROM gutpposttag WHERE tag_id = $2)",
+            &sql_params,
+        )?;
+        _ = pg_conn.execute(
+            "UPDATE gutpposttag SET tag_id = $2 WHERE tag_id = $1",
+            &sql_params,
+        )?;
+        _ = pg_conn.execute(
+            "UPDATE gutptag SET parent_tag_id = $2 WHERE parent_tag_id = $1",
+            &sql_params,
+        )?;
+        _ = pg_conn.execute(
+            "UPDATE gutptag SET alias_of = $2 WHERE alias_of = $1",
+            &sql_params,
+        )?;
+
+        _ = pg_conn.execute(
+            "UPDATE gutptag SET post_count = (SELECT COUNT(*) FROM gutpposttag WHERE tag_id = $2) WHERE id = $2",
+            &sql_params,
+        )?;
+        Ok(())
+    }
+
     fn get_one(req: &mut Request) -> Result<Response> {
         let pg_addr = std::env::var(DB_URL_ENV)?;
         let pg_conn = pg::Connection::open(&pg_addr)?;
 
         let params = req.parse_urlencoded()?;
         let tag_id = params.get("id").ok_or(anyhow!("id is required"))?;
+        let app_id = utils::get_app_id(req)?;
 
         let (sql, sql_params) = GutpTag::build_get_by_id(tag_id);
         let rowset = pg_conn.query(&sql, &sql_params)?;
 
-        let results = if let Some(row) = rowset.rows.into_iter().next() {
-            vec![GutpTag::from_row(row)]
-        } else {
-            bail!("no this item".to_string());
+        let results = match rowset.rows.into_iter().next().map(GutpTag::from_row) {
+            Some(tag) if tag.app_id == app_id => vec![tag],
+            _ => bail!("no this item".to_string()),
+        };
+        let caller_id = utils::get_optional_caller_id(req);
+        GutpMemberModule::check_readable(
+            &pg_conn,
+            &app_id,
+            &results[0].subspace_id,
+            caller_id.as_deref(),
+        )?;
+
+        let info = Info {
+            model_name: GutpTag::model_name(),
+            action: HandlerCRUD::GetOne,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
+    fn get_by_caption(req: &mut Request) -> Result<Response> {
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+        let subspace_id = params
+            .get("subspace_id")
+            .ok_or(anyhow!("subspace_id is required"))?;
+        let caption = params
+            .get("caption")
+            .ok_or(anyhow!("caption is required"))?;
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_optional_caller_id(req);
+        GutpMemberModule::check_readable(&pg_conn, &app_id, subspace_id, caller_id.as_deref())?;
+
+        let results = match Self::find_in_subspace(
+            &pg_conn,
+            &app_id,
+            subspace_id,
+            "caption_key",
+            &caption_key(caption),
+        )? {
+            Some(tag) => vec![tag],
+            None => bail!("no this item".to_string()),
         };
 
         let info = Info {
@@ -42,14 +330,23 @@ impl GutpTagModule {
 
         let params = req.parse_urlencoded()?;
 
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_optional_caller_id(req);
+
         let (limit, offset) = utils::build_page_info(&params)?;
         let sql = SqlBuilder::select_from(&GutpTag::model_name())
             .fields(&GutpTag::fields())
+            .and_where_eq("app_id", "$1")
+            .and_where(GutpMemberModule::build_readable_cond("subspace_id", "$2"))
             .order_desc("created_time")
             .limit(limit)
             .offset(offset)
             .sql()?;
-        let rowset = pg_conn.query(&sql, &[])?;
+        let sql_params = [
+            ParameterValue::Str(app_id),
+            ParameterValue::Str(caller_id.unwrap_or_default()),
+        ];
+        let rowset = pg_conn.query(&sql, &sql_params)?;
 
         let mut results: Vec<GutpTag> = vec![];
         for row in rowset.rows {
@@ -76,16 +373,24 @@ impl GutpTagModule {
             .get("subspace_id")
             .ok_or(anyhow!("subspace_id is required"))?;
 
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_optional_caller_id(req);
+        GutpMemberModule::check_readable(&pg_conn, &app_id, subspace_id, caller_id.as_deref())?;
+
         let (limit, offset) = utils::build_page_info(&params)?;
         let sql = SqlBuilder::select_from(&GutpTag::model_name())
             .fields(&GutpTag::fields())
+            .and_where_eq("app_id", "$2")
             .and_where_eq("subspace_id", "$1")
             .order_desc("created_time")
             .limit(limit)
             .offset(offset)
             .sql()?;
-        let sql_param = ParameterValue::Str(subspace_id.clone());
-        let rowset = pg_conn.query(&sql, &[sql_param])?;
+        let sql_params = [
+            ParameterValue::Str(subspace_id.clone()),
+            ParameterValue::Str(app_id),
+        ];
+        let rowset = pg_conn.query(&sql, &sql_params)?;
 
         let mut results: Vec<GutpTag> = vec![];
         for row in rowset.rows {
@@ -112,16 +417,25 @@ impl GutpTagModule {
             .get("creator_id")
             .ok_or(anyhow!("creator_id is required"))?;
 
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_optional_caller_id(req);
+
         let (limit, offset) = utils::build_page_info(&params)?;
         let sql = SqlBuilder::select_from(&GutpTag::model_name())
             .fields(&GutpTag::fields())
+            .and_where_eq("app_id", "$2")
             .and_where_eq("creator_id", "$1")
+            .and_where(GutpMemberModule::build_readable_cond("subspace_id", "$3"))
             .order_desc("created_time")
             .limit(limit)
             .offset(offset)
             .sql()?;
-        let sql_param = ParameterValue::Str(creator_id.clone());
-        let rowset = pg_conn.query(&sql, &[sql_param])?;
+        let sql_params = [
+            ParameterValue::Str(creator_id.clone()),
+            ParameterValue::Str(app_id),
+            ParameterValue::Str(caller_id.unwrap_or_default()),
+        ];
+        let rowset = pg_conn.query(&sql, &sql_params)?;
 
         let mut results: Vec<GutpTag> = vec![];
         for row in rowset.rows {
@@ -138,6 +452,247 @@ impl GutpTagModule {
         Ok(Response::new(Status::Successful, info, results))
     }
 
+    fn list_by_post(req: &mut Request) -> Result<Response> {
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let post_id = params
+            .get("post_id")
+            .ok_or(anyhow!("post_id is required"))?;
+
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_optional_caller_id(req);
+
+        let (limit, offset) = utils::build_page_info(&params)?;
+        let sql = SqlBuilder::select_from(&GutpTag::model_name())
+            .fields(&GutpTag::fields())
+            .and_where_eq("app_id", "$2")
+            .and_where("id IN (SELECT tag_id FROM gutpposttag WHERE post_id = $1)")
+            .and_where(GutpMemberModule::build_readable_cond("subspace_id", "$3"))
+            .order_desc("created_time")
+            .limit(limit)
+            .offset(offset)
+            .sql()?;
+        let sql_params = [
+            ParameterValue::Str(post_id.clone()),
+            ParameterValue::Str(app_id),
+            ParameterValue::Str(caller_id.unwrap_or_default()),
+        ];
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+
+        let mut results: Vec<GutpTag> = vec![];
+        for row in rowset.rows {
+            let sp = GutpTag::from_row(row);
+            results.push(sp);
+        }
+
+        let info = Info {
+            model_name: GutpTag::model_name(),
+            action: HandlerCRUD::List,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
+    fn list_by_parent(req: &mut Request) -> Result<Response> {
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let parent_tag_id = params
+            .get("parent_tag_id")
+            .ok_or(anyhow!("parent_tag_id is required"))?;
+
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_optional_caller_id(req);
+
+        let (limit, offset) = utils::build_page_info(&params)?;
+        let sql = SqlBuilder::select_from(&GutpTag::model_name())
+            .fields(&GutpTag::fields())
+            .and_where_eq("app_id", "$2")
+            .and_where_eq("parent_tag_id", "$1")
+            .and_where(GutpMemberModule::build_readable_cond("subspace_id", "$3"))
+            .order_desc("created_time")
+            .limit(limit)
+            .offset(offset)
+            .sql()?;
+        let sql_params = [
+            ParameterValue::Str(parent_tag_id.clone()),
+            ParameterValue::Str(app_id),
+            ParameterValue::Str(caller_id.unwrap_or_default()),
+        ];
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+
+        let mut results: Vec<GutpTag> = vec![];
+        for row in rowset.rows {
+            let sp = GutpTag::from_row(row);
+            results.push(sp);
+        }
+
+        let info = Info {
+            model_name: GutpTag::model_name(),
+            action: HandlerCRUD::List,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
+    /// Canonical tags of a subspace, the most used first.
+    fn list_popular(req: &mut Request) -> Result<Response> {
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let subspace_id = params
+            .get("subspace_id")
+            .ok_or(anyhow!("subspace_id is required"))?;
+
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_optional_caller_id(req);
+        GutpMemberModule::check_readable(&pg_conn, &app_id, subspace_id, caller_id.as_deref())?;
+
+        let (limit, offset) = utils::build_page_info(&params)?;
+        let sql = SqlBuilder::select_from(&GutpTag::model_name())
+            .fields(&GutpTag::fields())
+            .and_where_eq("app_id", "$2")
+            .and_where_eq("subspace_id", "$1")
+            .and_where_eq("alias_of", "''")
+            .order_desc("post_count")
+            .order_desc("created_time")
+            .limit(limit)
+            .offset(offset)
+            .sql()?;
+        let sql_params = [
+            ParameterValue::Str(subspace_id.clone()),
+            ParameterValue::Str(app_id),
+        ];
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+
+        let mut results: Vec<GutpTag> = vec![];
+        for row in rowset.rows {
+            let sp = GutpTag::from_row(row);
+            results.push(sp);
+        }
+
+        let info = Info {
+            model_name: GutpTag::model_name(),
+            action: HandlerCRUD::List,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
+    /// Canonical tags of a subspace ordered by how often they were attached
+    /// to posts since the `since` timestamp.
+    fn list_trending(req: &mut Request) -> Result<Response> {
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let subspace_id = params
+            .get("subspace_id")
+            .ok_or(anyhow!("subspace_id is required"))?;
+        let since = params
+            .get("since")
+            .ok_or(anyhow!("since is required"))?
+            .parse::<i64>()?;
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_optional_caller_id(req);
+        GutpMemberModule::check_readable(&pg_conn, &app_id, subspace_id, caller_id.as_deref())?;
+
+        let (limit, offset) = utils::build_page_info(&params)?;
+        // only the recent links of this subspace's tags are counted
+        let sql = SqlBuilder::select_from(
+            "gutptag JOIN (SELECT gutpposttag.tag_id, COUNT(*) AS recent_count FROM gutpposttag JOIN gutptag AS linked ON gutpposttag.tag_id = linked.id WHERE linked.subspace_id = $1 AND gutpposttag.created_time >= $2 GROUP BY gutpposttag.tag_id) AS recent ON gutptag.id = recent.tag_id",
+        )
+        .fields(&GutpTag::fields())
+        .and_where_eq("subspace_id", "$1")
+        .and_where_eq("app_id", "$3")
+        .and_where_eq("alias_of", "''")
+        .order_desc("recent.recent_count")
+        .order_desc("post_count")
+        .limit(limit)
+        .offset(offset)
+        .sql()?;
+        let sql_params = [
+            ParameterValue::Str(subspace_id.clone()),
+            ParameterValue::Int64(since),
+            ParameterValue::Str(app_id),
+        ];
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+
+        let mut results: Vec<GutpTag> = vec![];
+        for row in rowset.rows {
+            let sp = GutpTag::from_row(row);
+            results.push(sp);
+        }
+
+        let info = Info {
+            model_name: GutpTag::model_name(),
+            action: HandlerCRUD::List,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
+    /// Tags of a subspace whose caption starts with `prefix`, for auto-completion.
+    fn suggest(req: &mut Request) -> Result<Response> {
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let subspace_id = params
+            .get("subspace_id")
+            .ok_or(anyhow!("subspace_id is required"))?;
+        let prefix = params.get("prefix").ok_or(anyhow!("prefix is required"))?;
+        let limit = utils::build_suggest_limit(&params)?;
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_optional_caller_id(req);
+        GutpMemberModule::check_readable(&pg_conn, &app_id, subspace_id, caller_id.as_deref())?;
+
+        // matched on the case folded caption, served by the (subspace_id, caption_key) index
+        let sql = SqlBuilder::select_from(&GutpTag::model_name())
+            .fields(&GutpTag::fields())
+            .and_where_eq("subspace_id", "$1")
+            .and_where("caption_key LIKE $2")
+            .and_where_eq("app_id", "$3")
+            .order_desc("post_count")
+            .order_asc("caption_key")
+            .limit(limit)
+            .sql()?;
+        let sql_params = [
+            ParameterValue::Str(subspace_id.clone()),
+            ParameterValue::Str(utils::build_like_prefix(&caption_key(prefix))),
+            ParameterValue::Str(app_id),
+        ];
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+
+        let mut results: Vec<GutpTag> = vec![];
+        for row in rowset.rows {
+            let sp = GutpTag::from_row(row);
+            results.push(sp);
+        }
+
+        let info = Info {
+            model_name: GutpTag::model_name(),
+            action: HandlerCRUD::List,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
+    /// Create a tag in a subspace, by a user who can post in it.
     fn new_one(req: &mut Request) -> Result<Response> {
         let pg_addr = std::env::var(DB_URL_ENV)?;
         let pg_conn = pg::Connection::open(&pg_addr)?;
@@ -156,6 +711,11 @@ impl GutpTagModule {
             .get("is_public")
             .ok_or(anyhow!("is_public is required"))?
             .parse::<bool>()?;
+        let parent_tag_id = params
+            .get("parent_tag_id")
+            .unwrap_or(&"".to_string())
+            .to_owned();
+        let alias_of = params.get("alias_of").unwrap_or(&"".to_string()).to_owned();
 
         let id = req
             .ext()
@@ -167,15 +727,25 @@ impl GutpTagModule {
             .get("time")
             .ok_or(anyhow!("get time failed"))?
             .parse::<i64>()?;
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_caller_id(req)?;
+        GutpMemberModule::check_can_post(&pg_conn, &app_id, &subspace_id, &caller_id)?;
 
         let tag = GutpTag {
             id,
-            caption,
+            caption: clean_caption(&caption),
+            caption_key: caption_key(&caption),
             subspace_id,
             is_public,
             weight: GUTP_TAG_WEIGHT_DEFAULT,
+            parent_tag_id,
+            alias_of,
+            post_count: 0,
+            app_id,
             created_time: time,
         };
+        Self::check_caption_unique(&pg_conn, &tag)?;
+        Self::check_relations(&pg_conn, &tag)?;
 
         let (sql, sql_params) = tag.build_insert();
         _ = pg_conn.execute(&sql, &sql_params)?;
@@ -191,6 +761,9 @@ impl GutpTagModule {
         Ok(Response::new(Status::Successful, info, results))
     }
 
+    /// Change a tag, by a moderator of its subspace. A tag turned into an
+    /// alias hands its post links, children and aliases to the new target,
+    /// as in a merge.
     fn update(req: &mut Request) -> Result<Response> {
         let pg_addr = std::env::var(DB_URL_ENV)?;
         let pg_conn = pg::Connection::open(&pg_addr)?;
@@ -202,53 +775,145 @@ impl GutpTagModule {
             .get("caption")
             .ok_or(anyhow!("caption not found"))?
             .to_owned();
-        let subspace_id = params
-            .get("subspace_id")
-            .ok_or(anyhow!("subspace_id not found"))?
-            .to_owned();
         let is_public = params
             .get("is_public")
             .ok_or(anyhow!("is_public not found"))?
             .parse::<bool>()?;
-        // let time = req
-        //     .ext()
-        //     .get("time")
-        //     .ok_or(anyhow!("time is required"))?
-        //     .parse::<i64>()?;
-
-        // get the item from db, check whether obj in db
-        let (sql, sql_params) = GutpTag::build_get_by_id(&id);
-        let rowset = pg_conn.query(&sql, &sql_params)?;
-        match rowset.rows.into_iter().next() {
-            Some(row) => {
-                let old_tag = GutpTag::from_row(row);
+        // absent relations are left as they are
+        let parent_tag_id = params.get("parent_tag_id").cloned();
+        let alias_of = params.get("alias_of").cloned();
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_caller_id(req)?;
+
+        let tag = utils::with_transaction(&pg_conn, |conn| {
+            let old_tag = Self::get_tag_by_id(conn, &app_id, &id)?;
+            GutpModeratorModule::check_subspace_moderator(
+                conn,
+                &app_id,
+                &old_tag.subspace_id,
+                &caller_id,
+            )?;
+            let old_alias_of = old_tag.alias_of.clone();
+            let tag = GutpTag {
+                caption: clean_caption(&caption),
+                caption_key: caption_key(&caption),
+                // the subspace stays, as the post links must be in the same one
+                is_public,
+                parent_tag_id: parent_tag_id.unwrap_or(old_tag.parent_tag_id.clone()),
+                alias_of: alias_of.unwrap_or(old_tag.alias_of.clone()),
+                ..old_tag.clone()
+            };
+            Self::check_caption_unique(conn, &tag)?;
+            Self::check_relations(conn, &tag)?;
+
+            let tag = if !tag.alias_of.is_empty() && tag.alias_of != old_alias_of {
+                let into_tag = Self::get_tag_by_id(conn, &app_id, &tag.alias_of)?;
+                Self::move_into(conn, &old_tag, &into_tag)?;
+                GutpTag {
+                    post_count: 0,
+                    ..tag
+                }
+            } else {
+                tag
+            };
+
+            let (sql, sql_params) = tag.build_update();
+            _ = conn.execute(&sql, &sql_params)?;
+
+            Ok(tag)
+        })?;
 
-                let tag = GutpTag {
-                    caption,
-                    subspace_id,
-                    is_public,
-                    ..old_tag
-                };
+        let results: Vec<GutpTag> = vec![tag];
 
-                let (sql, sql_params) = tag.build_update();
-                _ = pg_conn.execute(&sql, &sql_params)?;
+        let info = Info {
+            model_name: GutpTag::model_name(),
+            action: HandlerCRUD::Update,
+            extra: "".to_string(),
+        };
 
-                let results: Vec<GutpTag> = vec![tag];
+        Ok(Response::new(Status::Successful, info, results))
+    }
 
-                let info = Info {
-                    model_name: GutpTag::model_name(),
-                    action: HandlerCRUD::Update,
-                    extra: "".to_string(),
-                };
+    /// Merge one tag into another, by a moderator of the subspace: the post
+    /// links, children and aliases of `from_id` move to `into_id`, and
+    /// `from_id` stays as an alias of it.
+    fn merge(req: &mut Request) -> Result<Response> {
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
 
-                Ok(Response::new(Status::Successful, info, results))
+        let params = req.parse_urlencoded()?;
+
+        let from_id = params
+            .get("from_id")
+            .ok_or(anyhow!("from_id is required"))?
+            .to_owned();
+        let into_id = params
+            .get("into_id")
+            .ok_or(anyhow!("into_id is required"))?
+            .to_owned();
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_caller_id(req)?;
+        let actor = GutpModLogModule::get_actor(req, &params)?;
+
+        let tag = utils::with_transaction(&pg_conn, |conn| {
+            let from_tag = Self::get_tag_by_id(conn, &app_id, &from_id)?;
+            let into_tag = Self::get_tag_by_id(conn, &app_id, &into_id)?;
+            GutpModeratorModule::check_subspace_moderator(
+                conn,
+                &app_id,
+                &from_tag.subspace_id,
+                &caller_id,
+            )?;
+            if from_tag.id == into_tag.id {
+                bail!("can not merge a tag into itself");
             }
-            None => {
-                bail!("update action: no item in db");
+            if from_tag.subspace_id != into_tag.subspace_id {
+                bail!("can only merge tags of the same subspace");
             }
-        }
+            if !into_tag.alias_of.is_empty() {
+                bail!("can only merge into a canonical tag");
+            }
+
+            GutpModLogModule::record(
+                conn,
+                &actor,
+                GutpModLog {
+                    subspace_id: from_tag.subspace_id.clone(),
+                    target_model: GutpTag::model_name(),
+                    target_id: from_tag.id.clone(),
+                    action: "merge".to_string(),
+                    before_value: from_tag.alias_of.clone(),
+                    after_value: into_tag.id.clone(),
+                    ..Default::default()
+                },
+            )?;
+
+            Self::move_into(conn, &from_tag, &into_tag)?;
+
+            let tag = GutpTag {
+                alias_of: into_id.clone(),
+                parent_tag_id: "".to_string(),
+                post_count: 0,
+                ..from_tag
+            };
+            let (sql, sql_params) = tag.build_update();
+            _ = conn.execute(&sql, &sql_params)?;
+
+            Ok(tag)
+        })?;
+
+        let results: Vec<GutpTag> = vec![tag];
+
+        let info = Info {
+            model_name: GutpTag::model_name(),
+            action: HandlerCRUD::Update,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
     }
 
+    /// Delete a tag along with its post links, by a moderator of its subspace.
     fn delete(req: &mut Request) -> Result<Response> {
         let pg_addr = std::env::var(DB_URL_ENV)?;
         let pg_conn = pg::Connection::open(&pg_addr)?;
@@ -256,9 +921,47 @@ impl GutpTagModule {
         let params = req.parse_urlencoded()?;
 
         let id = params.get("id").ok_or(anyhow!("id is required"))?;
-
-        let (sql, sql_params) = GutpTag::build_delete(id);
-        _ = pg_conn.execute(&sql, &sql_params)?;
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_caller_id(req)?;
+        let actor = GutpModLogModule::get_actor(req, &params)?;
+
+        utils::with_transaction(&pg_conn, |conn| {
+            let tag = Self::get_tag_by_id(conn, &app_id, id)?;
+            GutpModeratorModule::check_subspace_moderator(
+                conn,
+                &app_id,
+                &tag.subspace_id,
+                &caller_id,
+            )?;
+            GutpModLogModule::record(
+                conn,
+                &actor,
+                GutpModLog {
+                    subspace_id: tag.subspace_id.clone(),
+                    target_model: GutpTag::model_name(),
+                    target_id: tag.id.clone(),
+                    action: "delete".to_string(),
+                    before_value: tag.caption.clone(),
+                    after_value: "".to_string(),
+                    ..Default::default()
+                },
+            )?;
+            GutpPostTagModule::delete_by_tag(conn, id)?;
+            // children move to the top level and aliases become tags of their own
+            let sql_param = ParameterValue::Str(id.to_owned());
+            _ = conn.execute(
+                "UPDATE gutptag SET parent_tag_id = '' WHERE parent_tag_id = $1",
+                std::slice::from_ref(&sql_param),
+            )?;
+            _ = conn.execute(
+                "UPDATE gutptag SET alias_of = '' WHERE alias_of = $1",
+                &[sql_param],
+            )?;
+
+            let (sql, sql_params) = GutpTag::build_delete(id);
+            _ = conn.execute(&sql, &sql_params)?;
+            Ok(())
+        })?;
 
         let info = Info {
             model_name: GutpTag::model_name(),
@@ -274,13 +977,46 @@ impl GutpTagModule {
 impl Module for GutpTagModule {
     fn router(&self, router: &mut Router) -> Result<()> {
         router.get("/gutp/v1/tag", Self::get_one);
+        router.get("/gutp/v1/tag/get_by_caption", Self::get_by_caption);
         router.get("/gutp/v1/tag/list", Self::get_list);
         router.get("/gutp/v1/tag/list_by_subspace", Self::list_by_subspace);
         router.get("/gutp/v1/tag/list_by_creator", Self::list_by_creator);
+        router.get("/gutp/v1/tag/list_by_post", Self::list_by_post);
+        router.get("/gutp/v1/tag/list_by_parent", Self::list_by_parent);
+        router.get("/gutp/v1/tag/popular", Self::list_popular);
+        router.get("/gutp/v1/tag/trending", Self::list_trending);
+        router.get("/gutp/v1/tag/suggest", Self::suggest);
         router.post("/gutp/v1/tag/create", Self::new_one);
         router.post("/gutp/v1/tag/update", Self::update);
+        router.post("/gutp/v1/tag/merge", Self::merge);
         router.post("/gutp/v1/tag/delete", Self::delete);
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clean_caption_collapses_whitespace() {
+        assert_eq!(clean_caption("  rust \t lang\n"), "rust lang");
+        assert_eq!(clean_caption("   "), "");
+    }
+
+    #[test]
+    fn caption_key_folds_case() {
+        assert_eq!(caption_key("Rust  Lang"), caption_key("rust lang"));
+        assert_eq!(caption_key("STRASSE"), caption_key("straße"));
+        assert_eq!(caption_key("ΣΊΣΥΦΟΣ"), caption_key("σίσυφος"));
+    }
+
+    #[test]
+    fn caption_key_normalizes_compatibility_forms() {
+        // precomposed and combining accents, and the "fi" ligature
+        assert_eq!(caption_key("café"), caption_key("cafe\u{301}"));
+        assert_eq!(caption_key("\u{fb01}le"), caption_key("file"));
+        assert_ne!(caption_key("cafe"), caption_key("café"));
+    }
+}
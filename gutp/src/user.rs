@@ -1,8 +1,14 @@
+use crate::app::GutpAppModule;
 use crate::constants::DB_URL_ENV;
+use crate::conversation::GutpConversationModule;
+use crate::envelope;
+use crate::follow::GutpFollowModule;
+use crate::modlog::GutpModLogModule;
+use crate::userprofile::GutpUserProfileModule;
 use crate::utils;
 use anyhow::{anyhow, bail};
 use eightfish_sdk::{HandlerCRUD, Info, Module, Request, Response, Result, Router, Status};
-use gutp_types::GutpUser;
+use gutp_types::{GutpModLog, GutpUser};
 use spin_sdk::pg::{self, ParameterValue};
 use sql_builder::SqlBuilder;
 
@@ -15,11 +21,69 @@ enum GutpUserStatus {
 
 enum GutpUserRole {
     Normal = 0,
+    // moderates the whole app, like the ranking of subspaces, made one by the app owners
+    Admin = 1,
 }
 
 pub struct GutpUserModule;
 
 impl GutpUserModule {
+    /// Load a user record of an app, used by other modules to fill in author
+    /// info. Users of other apps are not found.
+    pub(crate) fn get_user_by_id(
+        pg_conn: &pg::Connection,
+        app_id: &str,
+        id: &str,
+    ) -> Result<GutpUser> {
+        let (sql, sql_params) = GutpUser::build_get_by_id(id);
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+        match rowset.rows.into_iter().next().map(GutpUser::from_row) {
+            Some(user) if user.app_id == app_id => Ok(user),
+            _ => bail!("no such user: {id}"),
+        }
+    }
+
+    /// Change the status of a user from moderation, returning the status
+    /// it had before.
+    pub(crate) fn set_status(
+        pg_conn: &pg::Connection,
+        app_id: &str,
+        id: &str,
+        status: i16,
+    ) -> Result<i16> {
+        if !(GutpUserStatus::Normal as i16..=GutpUserStatus::Deleted as i16).contains(&status) {
+            bail!("status is out of range");
+        }
+        let old_user = Self::get_user_by_id(pg_conn, app_id, id)?;
+        let old_status = old_user.status;
+        let user = GutpUser { status, ..old_user };
+        let (sql, sql_params) = user.build_update();
+        _ = pg_conn.execute(&sql, &sql_params)?;
+        Ok(old_status)
+    }
+
+    /// The admins of an app are its owners, and the users they gave the
+    /// admin role.
+    pub(crate) fn check_admin(pg_conn: &pg::Connection, app_id: &str, user_id: &str) -> Result<()> {
+        if let Some(app) = GutpAppModule::find_app(pg_conn, app_id)? {
+            if GutpAppModule::is_owner(&app, user_id) {
+                return Ok(());
+            }
+        }
+        match Self::get_user_by_id(pg_conn, app_id, user_id) {
+            Ok(user) if user.role == GutpUserRole::Admin as i16 => Ok(()),
+            _ => bail!("only admins can do this"),
+        }
+    }
+
+    fn check_public_key(public_key: &str) -> Result<String> {
+        let public_key = public_key.trim();
+        if envelope::base64_decoded_len(public_key)? == 0 {
+            bail!("public_key must not be empty");
+        }
+        Ok(public_key.to_string())
+    }
+
     fn get_one(req: &mut Request) -> Result<Response> {
         let pg_addr = std::env::var(DB_URL_ENV)?;
         let pg_conn = pg::Connection::open(&pg_addr)?;
@@ -27,6 +91,7 @@ impl GutpUserModule {
         let params = req.parse_urlencoded()?;
 
         let entity_id = params.get("id").ok_or(anyhow!("id is required"))?;
+        let app_id = utils::get_app_id(req)?;
 
         let (sql, sql_params) = GutpUser::build_get_by_id(entity_id);
         let rowset = pg_conn.query(&sql, &sql_params)?;
@@ -34,7 +99,9 @@ impl GutpUserModule {
         let mut results: Vec<GutpUser> = vec![];
         for row in rowset.rows {
             let article = GutpUser::from_row(row);
-            results.push(article);
+            if article.app_id == app_id {
+                results.push(article);
+            }
         }
 
         let info = Info {
@@ -56,16 +123,21 @@ impl GutpUserModule {
             .get("account")
             .ok_or(anyhow!("account is required"))?;
         let (limit, offset) = utils::build_page_info(&params)?;
+        let app_id = utils::get_app_id(req)?;
 
         let sql = SqlBuilder::select_from(&GutpUser::model_name())
             .fields(&GutpUser::fields())
             .and_where_eq("account", "$1")
+            .and_where_eq("app_id", "$2")
             .order_desc("signup_time")
             .limit(limit)
             .offset(offset)
             .sql()?;
-        let sql_param = ParameterValue::Str(account.clone());
-        let rowset = pg_conn.query(&sql, &[sql_param])?;
+        let sql_params = [
+            ParameterValue::Str(account.clone()),
+            ParameterValue::Str(app_id),
+        ];
+        let rowset = pg_conn.query(&sql, &sql_params)?;
 
         let mut results: Vec<GutpUser> = vec![];
         for row in rowset.rows {
@@ -82,6 +154,48 @@ impl GutpUserModule {
         Ok(Response::new(Status::Successful, info, results))
     }
 
+    /// Users whose account or nickname starts with `prefix`, accounts first.
+    fn suggest(req: &mut Request) -> Result<Response> {
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let prefix = params.get("prefix").ok_or(anyhow!("prefix is required"))?;
+        let limit = utils::build_suggest_limit(&params)?;
+        let app_id = utils::get_app_id(req)?;
+
+        // lower(account) and lower(nickname) have text_pattern_ops indexes for this
+        let sql = SqlBuilder::select_from(&GutpUser::model_name())
+            .fields(&GutpUser::fields())
+            .and_where_eq("app_id", "$2")
+            .and_where("(lower(account) LIKE $1 OR lower(nickname) LIKE $1)")
+            .and_where_eq("status", GutpUserStatus::Normal as i16)
+            .order_desc("lower(account) LIKE $1")
+            .order_asc("account")
+            .limit(limit)
+            .sql()?;
+        let sql_params = [
+            ParameterValue::Str(utils::build_like_prefix(&prefix.to_lowercase())),
+            ParameterValue::Str(app_id),
+        ];
+        let rowset = pg_conn.query(&sql, &sql_params)?;
+
+        let mut results: Vec<GutpUser> = vec![];
+        for row in rowset.rows {
+            let user = GutpUser::from_row(row);
+            results.push(user);
+        }
+
+        let info = Info {
+            model_name: GutpUser::model_name(),
+            action: HandlerCRUD::List,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
     fn new_user(req: &mut Request) -> Result<Response> {
         let pg_addr = std::env::var(DB_URL_ENV)?;
         let pg_conn = pg::Connection::open(&pg_addr)?;
@@ -104,6 +218,11 @@ impl GutpUserModule {
             .get("avatar")
             .ok_or(anyhow!("avatar is required"))?
             .to_owned();
+        let public_key = match params.get("public_key") {
+            Some(public_key) => Self::check_public_key(public_key)?,
+            None => "".to_string(),
+        };
+        let app_id = utils::get_app_id(req)?;
 
         let id = req
             .ext()
@@ -122,8 +241,10 @@ impl GutpUserModule {
             oauth_source,
             nickname,
             avatar,
+            public_key,
             role: GutpUserRole::Normal as i16,
             status: GutpUserStatus::Normal as i16,
+            app_id,
             created_time: time,
         };
 
@@ -164,14 +285,13 @@ impl GutpUserModule {
             .get("avatar")
             .ok_or(anyhow!("avatar is required"))?
             .to_owned();
+        let app_id = utils::get_app_id(req)?;
 
         // get the item from db, check whether obj in db
         let (sql, sql_params) = GutpUser::build_get_by_id(id);
         let rowset = pg_conn.query(&sql, &sql_params)?;
-        match rowset.rows.into_iter().next() {
-            Some(row) => {
-                let old_user = GutpUser::from_row(row);
-
+        match rowset.rows.into_iter().next().map(GutpUser::from_row) {
+            Some(old_user) if old_user.app_id == app_id => {
                 let user: GutpUser = GutpUser {
                     account,
                     oauth_source,
@@ -193,12 +313,105 @@ impl GutpUserModule {
 
                 Ok(Response::new(Status::Successful, info, results))
             }
-            None => {
+            _ => {
                 bail!("update action: no item in db")
             }
         }
     }
 
+    /// Register the caller's public key. Group keys of the private subspaces
+    /// the caller is a member of are wrapped to it.
+    fn set_public_key(req: &mut Request) -> Result<Response> {
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let public_key = params
+            .get("public_key")
+            .ok_or(anyhow!("public_key is required"))?;
+        let public_key = Self::check_public_key(public_key)?;
+
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_caller_id(req)?;
+        let old_user = Self::get_user_by_id(&pg_conn, &app_id, &caller_id)?;
+        let user = GutpUser {
+            public_key,
+            ..old_user
+        };
+
+        let (sql, sql_params) = user.build_update();
+        _ = pg_conn.execute(&sql, &sql_params)?;
+
+        let results: Vec<GutpUser> = vec![user];
+
+        let info = Info {
+            model_name: GutpUser::model_name(),
+            action: HandlerCRUD::Update,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
+    /// Make a user an admin of the app, or a normal user again, by one of
+    /// the app owners.
+    fn set_role(req: &mut Request) -> Result<Response> {
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let id = params.get("id").ok_or(anyhow!("id is required"))?;
+        let role = params
+            .get("role")
+            .ok_or(anyhow!("role is required"))?
+            .parse::<i16>()?;
+        if role != GutpUserRole::Normal as i16 && role != GutpUserRole::Admin as i16 {
+            bail!("role is out of range");
+        }
+
+        let app = GutpAppModule::get_request_app(req)?;
+        let caller_id = utils::get_caller_id(req)?;
+        if !GutpAppModule::is_owner(&app, &caller_id) {
+            bail!("only the owners of the app can change the roles of users");
+        }
+        let actor = GutpModLogModule::get_actor(req, &params)?;
+
+        let user = utils::with_transaction(&pg_conn, |conn| {
+            let old_user = Self::get_user_by_id(conn, &app.id, id)?;
+            GutpModLogModule::record(
+                conn,
+                &actor,
+                GutpModLog {
+                    subspace_id: "".to_string(),
+                    target_model: GutpUser::model_name(),
+                    target_id: old_user.id.clone(),
+                    action: "set_role".to_string(),
+                    before_value: old_user.role.to_string(),
+                    after_value: role.to_string(),
+                    ..Default::default()
+                },
+            )?;
+
+            let user = GutpUser { role, ..old_user };
+            let (sql, sql_params) = user.build_update();
+            _ = conn.execute(&sql, &sql_params)?;
+
+            Ok(user)
+        })?;
+
+        let results: Vec<GutpUser> = vec![user];
+
+        let info = Info {
+            model_name: GutpUser::model_name(),
+            action: HandlerCRUD::Update,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
     fn delete(req: &mut Request) -> Result<Response> {
         let pg_addr = std::env::var(DB_URL_ENV)?;
         let pg_conn = pg::Connection::open(&pg_addr)?;
@@ -206,9 +419,36 @@ impl GutpUserModule {
         let params = req.parse_urlencoded()?;
 
         let id = params.get("id").ok_or(anyhow!("id is required"))?;
+        let app_id = utils::get_app_id(req)?;
+        let caller_id = utils::get_caller_id(req)?;
+        let actor = GutpModLogModule::get_actor(req, &params)?;
+
+        utils::with_transaction(&pg_conn, |conn| {
+            let user = Self::get_user_by_id(conn, &app_id, id)?;
+            if user.id != caller_id {
+                Self::check_admin(conn, &app_id, &caller_id)?;
+                GutpModLogModule::record(
+                    conn,
+                    &actor,
+                    GutpModLog {
+                        subspace_id: "".to_string(),
+                        target_model: GutpUser::model_name(),
+                        target_id: user.id.clone(),
+                        action: "delete".to_string(),
+                        before_value: user.status.to_string(),
+                        after_value: "".to_string(),
+                        ..Default::default()
+                    },
+                )?;
+            }
 
-        let (sql, sql_params) = GutpUser::build_delete(id);
-        _ = pg_conn.execute(&sql, &sql_params);
+            let (sql, sql_params) = GutpUser::build_delete(id);
+            _ = conn.execute(&sql, &sql_params)?;
+            GutpFollowModule::delete_by_target(conn, &GutpUser::model_name(), id)?;
+            GutpFollowModule::delete_by_follower(conn, id)?;
+            GutpUserProfileModule::delete_by_user(conn, id)?;
+            GutpConversationModule::delete_by_user(conn, id)
+        })?;
 
         let results: Vec<GutpUser> = vec![];
 
@@ -226,8 +466,11 @@ impl Module for GutpUserModule {
     fn router(&self, router: &mut Router) -> Result<()> {
         router.get("/gutp/v1/user", Self::get_one);
         router.get("/gutp/v1/user/get_by_account", Self::get_by_account);
+        router.get("/gutp/v1/user/suggest", Self::suggest);
         router.post("/gutp/v1/user/create", Self::new_user);
         router.post("/gutp/v1/user/update", Self::update);
+        router.post("/gutp/v1/user/set_public_key", Self::set_public_key);
+        router.post("/gutp/v1/user/set_role", Self::set_role);
         router.post("/gutp/v1/user/delete", Self::delete);
 
         Ok(())
@@ -0,0 +1,133 @@
+use anyhow::{anyhow, bail};
+use eightfish_sdk::{HandlerCRUD, Info, Module, Request, Response, Result, Router, Status};
+use spin_sdk::pg::{self, ParameterValue};
+use sql_builder::SqlBuilder;
+
+use crate::constants::{DB_URL_ENV, PRIVATE_PROFILE_MAX_LEN};
+use crate::envelope::Envelope;
+use crate::utils;
+use gutp_types::GutpUserProfile;
+
+pub struct GutpUserProfileModule;
+
+impl GutpUserProfileModule {
+    fn find_profile(pg_conn: &pg::Connection, user_id: &str) -> Result<Option<GutpUserProfile>> {
+        let sql = SqlBuilder::select_from(&GutpUserProfile::model_name())
+            .fields(&GutpUserProfile::fields())
+            .and_where_eq("user_id", "$1")
+            .sql()?;
+        let rowset = pg_conn.query(&sql, &[ParameterValue::Str(user_id.to_owned())])?;
+
+        Ok(rowset
+            .rows
+            .into_iter()
+            .next()
+            .map(GutpUserProfile::from_row))
+    }
+
+    /// Remove the private profile of a user, when the user is deleted.
+    pub(crate) fn delete_by_user(pg_conn: &pg::Connection, user_id: &str) -> Result<()> {
+        let sql_param = ParameterValue::Str(user_id.to_owned());
+        _ = pg_conn.execute(
+            "DELETE FROM gutpuserprofile WHERE user_id = $1",
+            &[sql_param],
+        )?;
+        Ok(())
+    }
+
+    /// The caller's own private profile, none if it was never set.
+    fn get_mine(req: &mut Request) -> Result<Response> {
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let user_id = utils::get_caller_id(req)?;
+
+        let results: Vec<GutpUserProfile> = Self::find_profile(&pg_conn, &user_id)?
+            .into_iter()
+            .collect();
+
+        let info = Info {
+            model_name: GutpUserProfile::model_name(),
+            action: HandlerCRUD::GetOne,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+
+    /// Replace the caller's private profile. `blob` is an encryption envelope
+    /// of the profile, encrypted with the caller's own key.
+    fn update(req: &mut Request) -> Result<Response> {
+        let pg_addr = std::env::var(DB_URL_ENV)?;
+        let pg_conn = pg::Connection::open(&pg_addr)?;
+
+        let params = req.parse_urlencoded()?;
+
+        let blob = params.get("blob").ok_or(anyhow!("blob is required"))?;
+        if blob.len() > PRIVATE_PROFILE_MAX_LEN {
+            bail!("blob must be at most {PRIVATE_PROFILE_MAX_LEN} bytes");
+        }
+        let blob = Envelope::check_own(blob)?;
+
+        let user_id = utils::get_caller_id(req)?;
+        let id = req
+            .ext()
+            .get("random_str")
+            .ok_or(anyhow!("generate id failed"))?
+            .to_owned();
+        let time = req
+            .ext()
+            .get("time")
+            .ok_or(anyhow!("generate time failed"))?
+            .parse::<i64>()?;
+
+        let profile = utils::with_transaction(&pg_conn, |conn| {
+            let (profile, is_new) = match Self::find_profile(conn, &user_id)? {
+                Some(old_profile) => {
+                    let profile = GutpUserProfile {
+                        blob,
+                        updated_time: time,
+                        ..old_profile
+                    };
+                    (profile, false)
+                }
+                None => {
+                    let profile = GutpUserProfile {
+                        id,
+                        user_id,
+                        blob,
+                        updated_time: time,
+                    };
+                    (profile, true)
+                }
+            };
+            let (sql, sql_params) = if is_new {
+                profile.build_insert()
+            } else {
+                profile.build_update()
+            };
+            _ = conn.execute(&sql, &sql_params)?;
+
+            Ok(profile)
+        })?;
+
+        let results: Vec<GutpUserProfile> = vec![profile];
+
+        let info = Info {
+            model_name: GutpUserProfile::model_name(),
+            action: HandlerCRUD::Update,
+            extra: "".to_string(),
+        };
+
+        Ok(Response::new(Status::Successful, info, results))
+    }
+}
+
+impl Module for GutpUserProfileModule {
+    fn router(&self, router: &mut Router) -> Result<()> {
+        router.get("/gutp/v1/userprofile/mine", Self::get_mine);
+        router.post("/gutp/v1/userprofile/update", Self::update);
+
+        Ok(())
+    }
+}
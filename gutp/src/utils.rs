@@ -1,5 +1,9 @@
-use crate::constants::PAGESIZE;
-use anyhow::{anyhow, Result};
+use crate::constants::{
+    APP_ID_KEY, APP_ID_MAX_LEN, APP_KEY, CALLER_ID_KEY, PAGESIZE, SUGGEST_LIMIT, SUGGEST_LIMIT_MAX,
+};
+use anyhow::{anyhow, bail, Result};
+use eightfish_sdk::Request;
+use spin_sdk::pg;
 use std::collections::HashMap;
 
 pub fn build_page_info(params: &HashMap<String, String>) -> Result<(u64, u64)> {
@@ -21,3 +25,160 @@ pub fn get_required_param(params: &HashMap<String, String>, param_name: &str) ->
         .ok_or(anyhow!(format!("{param_name} is required.")))?;
     Ok(value.to_string())
 }
+
+/// Get the authenticated user id of the request sender.
+pub fn get_caller_id(req: &Request) -> Result<String> {
+    let caller_id = req
+        .ext()
+        .get(CALLER_ID_KEY)
+        .ok_or(anyhow!("caller identity is required"))?;
+    Ok(caller_id.to_owned())
+}
+
+/// Get the app the request comes from, as validated by the global filter.
+/// Every read and write is scoped to it, so the app must be registered.
+pub fn get_app_id(req: &Request) -> Result<String> {
+    let app_id = req
+        .ext()
+        .get(APP_ID_KEY)
+        .ok_or(anyhow!("app context is required"))?;
+    if !req.ext().contains_key(APP_KEY) {
+        bail!("app {app_id} is not registered");
+    }
+    Ok(app_id.to_owned())
+}
+
+/// Work out the app context of a request, from the gateway filled ext value
+/// or the `app_id` param, and store it back to the ext. Both may be given,
+/// but then they must agree.
+pub fn resolve_app_id(req: &mut Request) -> Result<String> {
+    let params = req.parse_urlencoded()?;
+    let app_id = match (req.ext().get(APP_ID_KEY), params.get("app_id")) {
+        (Some(ext_app_id), Some(param_app_id)) if ext_app_id != param_app_id => {
+            bail!("app_id does not match the app of the request")
+        }
+        (Some(app_id), _) | (None, Some(app_id)) => app_id.to_owned(),
+        (None, None) => bail!("app context is required"),
+    };
+    if app_id.is_empty() || app_id.len() > APP_ID_MAX_LEN {
+        bail!("app_id must be 1 to {APP_ID_MAX_LEN} characters");
+    }
+    if !app_id
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    {
+        bail!("app_id may only contain letters, digits, '-', '_' and '.'");
+    }
+
+    req.ext_mut().insert(APP_ID_KEY.to_string(), app_id.clone());
+    Ok(app_id)
+}
+
+/// Reject the request if the client supplied a value for `param_name` which
+/// differs from the one derived on the server side.
+pub fn check_param_matches(
+    params: &HashMap<String, String>,
+    param_name: &str,
+    expected: &str,
+) -> Result<()> {
+    if let Some(value) = params.get(param_name) {
+        if value != expected {
+            bail!("{param_name} does not match the caller");
+        }
+    }
+    Ok(())
+}
+
+/// Run `f` inside a db transaction. Commit when it succeeds, roll back when
+/// any step fails, so a handler touching several rows or tables never leaves
+/// half-written data behind.
+pub fn with_transaction<T, F>(pg_conn: &pg::Connection, f: F) -> Result<T>
+where
+    F: FnOnce(&pg::Connection) -> Result<T>,
+{
+    _ = pg_conn.execute("BEGIN", &[])?;
+    match f(pg_conn) {
+        Ok(value) => {
+            _ = pg_conn.execute("COMMIT", &[])?;
+            Ok(value)
+        }
+        Err(err) => {
+            _ = pg_conn.execute("ROLLBACK", &[]);
+            Err(err)
+        }
+    }
+}
+
+/// Parse a comma separated list param. `None` if the param is absent.
+pub fn get_list_param(params: &HashMap<String, String>, param_name: &str) -> Option<Vec<String>> {
+    params.get(param_name).map(|value| {
+        let mut items: Vec<String> = vec![];
+        for item in value.split(',').map(str::trim) {
+            if !item.is_empty() && !items.iter().any(|i| i == item) {
+                items.push(item.to_string());
+            }
+        }
+        items
+    })
+}
+
+/// Derive ids for the extra records created by one request from its
+/// `random_str`. Deterministic, so every node produces the same ids.
+pub fn derive_id(base: &str, kind: &str, index: usize) -> String {
+    format!("{base}-{kind}{index}")
+}
+
+/// Build a list of sql placeholders like `$2, $3, $4`, starting from `start`.
+pub fn build_placeholders(start: usize, count: usize) -> String {
+    (start..start + count)
+        .map(|i| format!("${i}"))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Size of a suggestion list, from the optional `limit` param.
+pub fn build_suggest_limit(params: &HashMap<String, String>) -> Result<u64> {
+    let limit = params
+        .get("limit")
+        .unwrap_or(&SUGGEST_LIMIT.to_string())
+        .parse::<u64>()?;
+    Ok(limit.min(SUGGEST_LIMIT_MAX))
+}
+
+/// Turn user input into a LIKE prefix pattern, escaping the wildcards in it.
+pub fn build_like_prefix(prefix: &str) -> String {
+    let mut pattern = String::new();
+    for c in prefix.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push('%');
+    pattern
+}
+
+/// Get the authenticated user id of the request sender, if there is one.
+/// Reads may be anonymous.
+pub fn get_optional_caller_id(req: &Request) -> Option<String> {
+    req.ext().get(CALLER_ID_KEY).cloned()
+}
+
+/// Parse the optional `cursor` param of a cursor paged list, as the
+/// `(created_time, id)` of the last item the client already has.
+pub fn get_cursor_param(params: &HashMap<String, String>) -> Result<Option<(i64, String)>> {
+    match params.get("cursor") {
+        Some(cursor) => {
+            let (time, id) = cursor
+                .split_once(':')
+                .ok_or(anyhow!("cursor is malformed"))?;
+            Ok(Some((time.parse::<i64>()?, id.to_string())))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Build the cursor pointing after an item, see `get_cursor_param`.
+pub fn build_cursor(time: i64, id: &str) -> String {
+    format!("{time}:{id}")
+}
@@ -1,18 +1,75 @@
+-- an app connected to this instance, its id is the app context of every request
+CREATE TABLE gutpapp (
+    id TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    description TEXT NOT NULL,
+    owner_keys TEXT NOT NULL,                   -- comma separated caller ids allowed to manage the app
+    max_content_len BIGINT NOT NULL,            -- max bytes of post and comment content, 0 for no limit
+    allow_anonymous_read BOOLEAN NOT NULL,      -- if false, every request must come from a caller
+    features TEXT NOT NULL,                     -- comma separated enabled features, like reactions,bookmarks
+    created_time BIGINT NOT NULL
+);
+CREATE TABLE gutpapp_idhash (
+	id TEXT PRIMARY KEY,
+	hash TEXT NOT NULL
+);
+-- Apps are registered by the operators listed in OPERATOR_KEYS_ENV. Data written
+-- before the app registry has no gutpapp row, so requests to it are refused until
+-- the operator registers it, through the api or directly:
+--
+-- INSERT INTO gutpapp (id, name, description, owner_keys, max_content_len,
+--     allow_anonymous_read, features, created_time)
+-- VALUES ('<app id>', '<name>', '', '<owner caller id>', 0, true,
+--     'reactions,bookmarks,follows,messages,search', <unix time>);
+
+-- the managed categories of an app, subspaces and posts refer to them by name
+CREATE TABLE gutpcategory (
+    id TEXT PRIMARY KEY,
+    app_id TEXT NOT NULL,
+    name TEXT NOT NULL,
+    description TEXT NOT NULL,
+    weight SMALLINT NOT NULL,                   -- order in the category list, the heaviest first
+    created_time BIGINT NOT NULL
+);
+CREATE UNIQUE INDEX gutpcategory_app_name_idx ON gutpcategory (app_id, name);
+CREATE TABLE gutpcategory_idhash (
+	id TEXT PRIMARY KEY,
+	hash TEXT NOT NULL
+);
+
 CREATE TABLE gutpuser (
     id TEXT PRIMARY KEY,
     account TEXT NOT NULL,                      -- readable name, like michael
     oauth_source TEXT NOT NULL,                 -- from where, like github, google, facebook
     nickname TEXT NOT NULL,                      
     avatar TEXT NOT NULL,
-    role SMALLINT NOT NULL,                     -- role for simple permission system
+    public_key TEXT NOT NULL,                   -- base64 public key, group keys of private subspaces are wrapped to it
+    role SMALLINT NOT NULL,                     -- normal, or admin of the app as set by its owners
     status SMALLINT NOT NULL,                   -- current status of this account, like normal, frozen, deleted, ...
+    app_id TEXT NOT NULL,                       -- which app this account signed up in, users are not shared across apps
     created_time BIGINT NOT NULL
 );
+CREATE INDEX gutpuser_account_prefix_idx ON gutpuser (app_id, lower(account) text_pattern_ops);
+CREATE INDEX gutpuser_nickname_prefix_idx ON gutpuser (app_id, lower(nickname) text_pattern_ops);
 CREATE TABLE gutpuser_idhash (
 	id TEXT PRIMARY KEY,
 	hash TEXT NOT NULL
 );
 
+-- private profile of a user, like email, bio, links and settings, encrypted with
+-- the user's own key, only the user can read it
+CREATE TABLE gutpuserprofile (
+    id TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    blob TEXT NOT NULL,                         -- an encryption envelope
+    updated_time BIGINT NOT NULL
+);
+CREATE UNIQUE INDEX gutpuserprofile_user_idx ON gutpuserprofile (user_id);
+CREATE TABLE gutpuserprofile_idhash (
+	id TEXT PRIMARY KEY,
+	hash TEXT NOT NULL
+);
+
 CREATE TABLE gutpsubspace (
     id TEXT PRIMARY KEY,
     slug TEXT NOT NULL,                         -- slug readable name of this subspace within an url
@@ -20,6 +77,8 @@ CREATE TABLE gutpsubspace (
     description TEXT NOT NULL,
     banner TEXT NOT NULL,
     is_public BOOLEAN NOT NULL,                 -- is this subspace a public square, all data in this subspace is plaintext
+    key_version BIGINT NOT NULL,                -- current version of the group content key, 0 if it has none yet
+    rekey_required BOOLEAN NOT NULL,            -- a member left or was banned since the current key, it should be rotated
     status SMALLINT NOT NULL,                   -- normal, forzen, blacklist, deleted ...
     weight SMALLINT NOT NULL,                   -- weight of this subspace, used for ranking and recommendation
     owner_id TEXT NOT NULL,                     -- is this subspace a personal blog space, owner_id is the person's id
@@ -41,14 +100,24 @@ CREATE TABLE gutppost (
     subspace_id TEXT NOT NULL,                  -- belongs to which subspace
     parent_post_id TEXT NOT NULL,               -- used to construct a post tree
     ext_link TEXT NOT NULL,                      -- for link aggregator-like application
-    is_public BOOLEAN NOT NULL,                 -- if public, the content of this post will be stored in plaintext, otherwise encrypted
+    is_public BOOLEAN NOT NULL,                 -- if public, the content of this post will be stored in plaintext, otherwise title and content hold encryption envelopes
     status SMALLINT NOT NULL,
     weight SMALLINT NOT NULL,                   -- used for ranking and recommendation
+    pinned_time BIGINT NOT NULL,                -- when pinned to the top of its subspace, 0 if not pinned
     category TEXT NOT NULL,                     -- the same meaning with the one of subspace, used to retreive quickly 
     app_id TEXT NOT NULL,                       -- which app's post
     created_time BIGINT NOT NULL,
-    updated_time BIGINT NOT NULL                -- the last time timestamp of updating
+    updated_time BIGINT NOT NULL,               -- the last time timestamp of updating
+    -- not part of the model, kept up to date by the backend for the ranked feed:
+    -- weight of the post and of its subspace plus the scores of its reactions
+    score BIGINT NOT NULL DEFAULT 0,
+    -- score decayed by age, created_time is in milliseconds, so a post needs a 10
+    -- times higher score to rank as high as one created 12.5 hours later
+    hot_rank DOUBLE PRECISION GENERATED ALWAYS AS (SIGN(score) * LOG(GREATEST(ABS(score), 1)) + created_time / 45000000.0) STORED
 );
+CREATE INDEX gutppost_top_idx ON gutppost (app_id, score DESC, created_time DESC);
+CREATE INDEX gutppost_hot_idx ON gutppost (app_id, hot_rank DESC);
+CREATE INDEX gutppost_fts_idx ON gutppost USING GIN (to_tsvector('simple', title || ' ' || content));
 CREATE TABLE gutppost_idhash (
 	id TEXT PRIMARY KEY,
 	hash TEXT NOT NULL
@@ -61,11 +130,13 @@ CREATE TABLE gutpcomment (
     author_nickname TEXT NOT NULL,
     post_id TEXT NOT NULL,                      -- which post it belongs to
     parent_comment_id TEXT NOT NULL,            -- used to construct a comment tree
-    is_public BOOLEAN NOT NULL,
+    is_public BOOLEAN NOT NULL,                 -- if not public, content holds an encryption envelope
     status SMALLINT NOT NULL,
     weight INTEGER NOT NULL,
+    app_id TEXT NOT NULL,                       -- the same with the one of its post
     created_time BIGINT NOT NULL
 );
+CREATE INDEX gutpcomment_fts_idx ON gutpcomment USING GIN (to_tsvector('simple', content));
 CREATE TABLE gutpcomment_idhash (
 	id TEXT PRIMARY KEY,
 	hash TEXT NOT NULL
@@ -75,11 +146,17 @@ CREATE TABLE gutpcomment_idhash (
 CREATE TABLE gutptag (
     id TEXT PRIMARY KEY,
     caption TEXT NOT NULL,
+    caption_key TEXT NOT NULL,                  -- normalized caption (trimmed, whitespace collapsed, case folded)
     subspace_id TEXT NOT NULL,                  -- which subspace this tag belongs to
     is_public BOOLEAN NOT NULL,                 -- is this tag a public (plaintext) tag
     weight SMALLINT NOT NULL,
+    parent_tag_id TEXT NOT NULL,                -- parent tag in the tag hierarchy, empty for a top level tag
+    alias_of TEXT NOT NULL,                     -- if has value, this tag is a synonym of that canonical tag
+    post_count BIGINT NOT NULL,                 -- how many posts are linked to this tag, kept by posttag changes
+    app_id TEXT NOT NULL,                       -- the same with the one of its subspace
     created_time BIGINT NOT NULL
 );
+CREATE UNIQUE INDEX gutptag_subspace_caption_idx ON gutptag (subspace_id, caption_key text_pattern_ops);
 CREATE TABLE gutptag_idhash (
 	id TEXT PRIMARY KEY,
 	hash TEXT NOT NULL
@@ -92,6 +169,8 @@ CREATE TABLE gutpposttag (
     tag_id TEXT NOT NULL,
     created_time BIGINT NOT NULL
 );
+CREATE UNIQUE INDEX gutpposttag_post_tag_idx ON gutpposttag (post_id, tag_id);
+CREATE INDEX gutpposttag_tag_time_idx ON gutpposttag (tag_id, created_time);
 CREATE TABLE gutpposttag_idhash (
 	id TEXT PRIMARY KEY,
 	hash TEXT NOT NULL
@@ -101,7 +180,7 @@ CREATE TABLE gutpposttag_idhash (
 CREATE TABLE gutppostdiff (
     id TEXT PRIMARY KEY,
     post_id TEXT NOT NULL,                            -- which post's diff
-    diff TEXT NOT NULL,                               -- diff bewteen old version and new version
+    diff TEXT NOT NULL,                               -- diff bewteen old version and new version, an encryption envelope for non-public posts
     version_num INTEGER NOT NULL,                     -- will increase 1 by every modification
     created_time BIGINT NOT NULL
 );
@@ -118,6 +197,7 @@ CREATE TABLE gutpmoderator (
     is_subspace_moderator BOOLEAN NOT NULL,           -- is it a subspace-scoped moderator
     tag_id TEXT,                                      -- or it is a tag-scoped moderator, bound to the tag
     permission_level SMALLINT NOT NULL,               -- a simple mechanism for permission control
+    app_id TEXT NOT NULL,                             -- the same with the one of its subspace
     created_time BIGINT NOT NULL
 );
 CREATE TABLE gutpmoderator_idhash (
@@ -125,6 +205,188 @@ CREATE TABLE gutpmoderator_idhash (
 	hash TEXT NOT NULL
 );
 
+-- the reactions an app allows, like upvote, downvote, like
+CREATE TABLE gutpreactionkind (
+    id TEXT PRIMARY KEY,
+    app_id TEXT NOT NULL,
+    name TEXT NOT NULL,
+    score SMALLINT NOT NULL,                          -- how much one reaction of this kind adds to the ranking score
+    created_time BIGINT NOT NULL
+);
+CREATE UNIQUE INDEX gutpreactionkind_app_name_idx ON gutpreactionkind (app_id, name);
+CREATE TABLE gutpreactionkind_idhash (
+	id TEXT PRIMARY KEY,
+	hash TEXT NOT NULL
+);
+
+-- reactions of users on posts and comments
+CREATE TABLE gutpreaction (
+    id TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    target_model TEXT NOT NULL,                       -- gutppost or gutpcomment
+    target_id TEXT NOT NULL,
+    kind TEXT NOT NULL,                               -- name of the reaction kind
+    score SMALLINT NOT NULL,                          -- score of the kind when reacted
+    created_time BIGINT NOT NULL
+);
+CREATE UNIQUE INDEX gutpreaction_user_target_kind_idx ON gutpreaction (target_model, target_id, user_id, kind);
+CREATE TABLE gutpreaction_idhash (
+	id TEXT PRIMARY KEY,
+	hash TEXT NOT NULL
+);
+
+-- posts saved by users for later
+CREATE TABLE gutpbookmark (
+    id TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    post_id TEXT NOT NULL,
+    folder TEXT NOT NULL,                             -- user defined collection, empty for the default one
+    created_time BIGINT NOT NULL
+);
+CREATE UNIQUE INDEX gutpbookmark_user_post_idx ON gutpbookmark (user_id, post_id);
+CREATE TABLE gutpbookmark_idhash (
+	id TEXT PRIMARY KEY,
+	hash TEXT NOT NULL
+);
+
+-- users following other users or subspaces
+CREATE TABLE gutpfollow (
+    id TEXT PRIMARY KEY,
+    follower_id TEXT NOT NULL,
+    target_model TEXT NOT NULL,                       -- gutpuser or gutpsubspace
+    target_id TEXT NOT NULL,
+    created_time BIGINT NOT NULL
+);
+CREATE UNIQUE INDEX gutpfollow_follower_target_idx ON gutpfollow (follower_id, target_model, target_id);
+CREATE INDEX gutpfollow_target_idx ON gutpfollow (target_model, target_id);
+CREATE TABLE gutpfollow_idhash (
+	id TEXT PRIMARY KEY,
+	hash TEXT NOT NULL
+);
+
+-- membership of users in subspaces, the owner of a subspace is always a member of it
+CREATE TABLE gutpmember (
+    id TEXT PRIMARY KEY,
+    subspace_id TEXT NOT NULL,
+    user_id TEXT NOT NULL,
+    role SMALLINT NOT NULL,                           -- member, or admin who manages the members
+    status SMALLINT NOT NULL,                         -- pending approval, invited, active, banned
+    inviter_id TEXT NOT NULL,                         -- who invited or approved this member, empty if joined freely
+    created_time BIGINT NOT NULL,
+    updated_time BIGINT NOT NULL
+);
+CREATE UNIQUE INDEX gutpmember_subspace_user_idx ON gutpmember (subspace_id, user_id);
+CREATE INDEX gutpmember_user_idx ON gutpmember (user_id);
+CREATE TABLE gutpmember_idhash (
+	id TEXT PRIMARY KEY,
+	hash TEXT NOT NULL
+);
+
+-- group content key of a subspace, wrapped to the public key of each member
+CREATE TABLE gutpsubspacekey (
+    id TEXT PRIMARY KEY,
+    subspace_id TEXT NOT NULL,
+    user_id TEXT NOT NULL,                            -- the member it is wrapped for
+    key_version BIGINT NOT NULL,
+    wrapped_key TEXT NOT NULL,                        -- base64, only the member's private key can unwrap it
+    wrapper_id TEXT NOT NULL,                         -- the admin who wrapped it
+    created_time BIGINT NOT NULL
+);
+CREATE UNIQUE INDEX gutpsubspacekey_subspace_user_version_idx ON gutpsubspacekey (subspace_id, user_id, key_version);
+CREATE TABLE gutpsubspacekey_idhash (
+	id TEXT PRIMARY KEY,
+	hash TEXT NOT NULL
+);
+
+-- direct message channel between two users
+CREATE TABLE gutpconversation (
+    id TEXT PRIMARY KEY,
+    app_id TEXT NOT NULL,
+    user_a_id TEXT NOT NULL,                          -- the two users, user_a_id < user_b_id
+    user_b_id TEXT NOT NULL,
+    a_read_time BIGINT NOT NULL,                      -- messages up to this time are read by user a
+    b_read_time BIGINT NOT NULL,                      -- messages up to this time are read by user b
+    last_message_time BIGINT NOT NULL,
+    created_time BIGINT NOT NULL
+);
+CREATE UNIQUE INDEX gutpconversation_users_idx ON gutpconversation (app_id, user_a_id, user_b_id);
+CREATE INDEX gutpconversation_user_b_idx ON gutpconversation (app_id, user_b_id);
+CREATE TABLE gutpconversation_idhash (
+	id TEXT PRIMARY KEY,
+	hash TEXT NOT NULL
+);
+
+-- end-to-end encrypted direct message, only the two users can decrypt it
+CREATE TABLE gutpmessage (
+    id TEXT PRIMARY KEY,
+    conversation_id TEXT NOT NULL,
+    sender_id TEXT NOT NULL,
+    content TEXT NOT NULL,                            -- an encryption envelope
+    created_time BIGINT NOT NULL
+);
+CREATE INDEX gutpmessage_conversation_time_idx ON gutpmessage (conversation_id, created_time, id);
+CREATE TABLE gutpmessage_idhash (
+	id TEXT PRIMARY KEY,
+	hash TEXT NOT NULL
+);
+
+-- append-only trail of moderation actions
+CREATE TABLE gutpmodlog (
+    id TEXT PRIMARY KEY,
+    app_id TEXT NOT NULL,
+    actor_id TEXT NOT NULL,                           -- the user who did the action
+    subspace_id TEXT NOT NULL,                        -- in which subspace the target lives, empty for app wide actions
+    target_model TEXT NOT NULL,                       -- model name of the target, like gutppost
+    target_id TEXT NOT NULL,
+    action TEXT NOT NULL,                             -- like set_weight, pin, ban, set_status, delete
+    before_value TEXT NOT NULL,
+    after_value TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    created_time BIGINT NOT NULL
+);
+CREATE INDEX gutpmodlog_target_idx ON gutpmodlog (target_model, target_id);
+CREATE INDEX gutpmodlog_subspace_idx ON gutpmodlog (app_id, subspace_id, created_time);
+CREATE TABLE gutpmodlog_idhash (
+	id TEXT PRIMARY KEY,
+	hash TEXT NOT NULL
+);
+
+-- the reports on one target, aggregated into an item of the moderation queue
+CREATE TABLE gutpreportcase (
+    id TEXT PRIMARY KEY,
+    app_id TEXT NOT NULL,
+    subspace_id TEXT NOT NULL,                        -- whose moderators handle it, empty for the app admins
+    target_model TEXT NOT NULL,                       -- model name of the target, like gutppost
+    target_id TEXT NOT NULL,
+    report_count BIGINT NOT NULL,
+    status SMALLINT NOT NULL,                         -- open, dismissed or actioned
+    resolver_id TEXT NOT NULL,                        -- the moderator who resolved it
+    resolution TEXT NOT NULL,                         -- like dismiss, or the status set on the target
+    created_time BIGINT NOT NULL,
+    updated_time BIGINT NOT NULL                      -- when it was last reported or resolved
+);
+-- a target has at most one open case, new reports after it is resolved open another
+CREATE UNIQUE INDEX gutpreportcase_open_target_idx ON gutpreportcase (app_id, target_model, target_id) WHERE status = 0;
+CREATE INDEX gutpreportcase_queue_idx ON gutpreportcase (app_id, subspace_id, status);
+CREATE TABLE gutpreportcase_idhash (
+	id TEXT PRIMARY KEY,
+	hash TEXT NOT NULL
+);
+
+-- one user's report of a target, with the reason
+CREATE TABLE gutpreport (
+    id TEXT PRIMARY KEY,
+    case_id TEXT NOT NULL,
+    reporter_id TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    created_time BIGINT NOT NULL
+);
+CREATE UNIQUE INDEX gutpreport_case_reporter_idx ON gutpreport (case_id, reporter_id);
+CREATE TABLE gutpreport_idhash (
+	id TEXT PRIMARY KEY,
+	hash TEXT NOT NULL
+);
+
 -- an unified extension object for all levels
 CREATE TABLE gutpextobj (
     id TEXT PRIMARY KEY,